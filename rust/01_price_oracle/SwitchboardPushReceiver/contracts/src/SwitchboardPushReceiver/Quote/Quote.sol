@@ -0,0 +1,134 @@
+//SPDX-License-Identifier: UNLICENSED
+pragma solidity ^0.8.9;
+
+import {QuoteLib} from "./QuoteLib.sol";
+import {ErrorLib} from "../error/ErrorLib.sol";
+import {EventLib} from "../event/EventLib.sol";
+import {AdminLib} from "../admin/AdminLib.sol";
+
+import {ISwitchboard} from "@switchboard-xyz/evm.js/contracts/ISwitchboard.sol";
+
+// Get the Switchboard Library - this is the Core Mainnet Deployment, you can swap this for one of the networks below
+import {Switchboard} from "@switchboard-xyz/evm.js/contracts/core/Switchboard.sol";
+
+// On-demand quotes for any pair, served by the same function that pushes the feeds
+contract Quote {
+    // What the requester pays for the function run, see requestQuote
+    uint256 public constant EXPECTED_QUOTE_GAS_COST = 300_000;
+
+    // Call the switchboard function with the requested pair
+    // The function will call back into quoteCallback with the price
+    function requestQuote(
+        string memory pair, // e.g. "BTC/USD"
+        uint256 maxAge // oldest data accepted when the quote lands, in seconds
+    ) external payable returns (uint256 requestId) {
+        // make sure the value is correct - this will make it so the requesters are the ones
+        // paying for the function run
+        if (msg.value < EXPECTED_QUOTE_GAS_COST * tx.gasprice) {
+            revert ErrorLib.InvalidValue(msg.value);
+        }
+
+        // the function id is set on the first callback
+        address functionId = AdminLib.functionId();
+        if (functionId == address(0)) {
+            revert ErrorLib.FunctionUninitialized();
+        }
+
+        requestId = QuoteLib.nextRequestId();
+
+        // encode the request parameters
+        bytes memory encodedParams = abi.encode(
+            QuoteLib.QuoteParams({
+                requestId: requestId,
+                pair: pair,
+                maxAge: maxAge
+            })
+        );
+
+        // call out to the switchboard function, triggering an off-chain run
+        address callId = ISwitchboard(AdminLib.switchboard()).callFunction{
+            value: msg.value
+        }(functionId, encodedParams);
+
+        QuoteLib.request(callId, requestId, pair, maxAge);
+    }
+
+    // Switchboard Function will call this function with the quote for a request. Requests are
+    // looked up by the call they were made through, anyone can call the function directly with
+    // another request's id in the params, but only requestQuote's call answers the request.
+    function quoteCallback(
+        address callId,
+        int256 value, // the pair's price
+        uint256 confidence, // one standard deviation of the samples
+        uint8 decimals, // decimals of value and confidence
+        uint256 timestamp // data timestamp
+    ) external {
+        checkCallback();
+
+        // calls that weren't made through requestQuote have no request to fill
+        uint256 requestId = QuoteLib.requestIds(callId);
+        if (requestId == 0) {
+            emit EventLib.QuoteFailed(
+                0,
+                callId,
+                uint8(QuoteLib.FailureReason.InvalidParams)
+            );
+            return;
+        }
+
+        // quotes share a transaction with the feed updates, answering a request twice mustn't
+        // revert them
+        QuoteLib.Quote storage quote = QuoteLib.quotes(requestId);
+        if (quote.filled || quote.failure != QuoteLib.FailureReason.None) {
+            return;
+        }
+
+        // the requester only accepts data up to maxAge old
+        if (block.timestamp > timestamp + quote.maxAge) {
+            QuoteLib.fail(requestId, QuoteLib.FailureReason.Stale);
+            return;
+        }
+
+        QuoteLib.fill(requestId, value, confidence, decimals, timestamp);
+    }
+
+    // Failure callback - the function refused a request, e.g. because too few exchanges list
+    // the pair
+    function quoteFailureCallback(
+        address callId,
+        QuoteLib.FailureReason reason
+    ) external {
+        checkCallback();
+
+        // calls that weren't made through requestQuote have no request to fail
+        uint256 requestId = QuoteLib.requestIds(callId);
+        if (requestId == 0) {
+            emit EventLib.QuoteFailed(0, callId, uint8(reason));
+            return;
+        }
+
+        QuoteLib.Quote storage quote = QuoteLib.quotes(requestId);
+        if (quote.filled || quote.failure != QuoteLib.FailureReason.None) {
+            return;
+        }
+        QuoteLib.fail(requestId, reason);
+    }
+
+    function quotes(
+        uint256 requestId
+    ) external view returns (QuoteLib.Quote memory) {
+        return QuoteLib.quotes(requestId);
+    }
+
+    // Assert that the sender is switchboard & the correct function id is encoded
+    function checkCallback() internal {
+        address functionId = Switchboard.getEncodedFunctionId();
+        if (AdminLib.functionId() == address(0)) {
+            AdminLib.setFunctionId(functionId);
+        }
+
+        if (functionId != AdminLib.functionId()) {
+            revert ErrorLib.InvalidSender(AdminLib.functionId(), functionId);
+        }
+    }
+}
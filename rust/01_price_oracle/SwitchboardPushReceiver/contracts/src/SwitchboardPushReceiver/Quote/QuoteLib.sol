@@ -0,0 +1,118 @@
+//SPDX-License-Identifier: UNLICENSED
+pragma solidity ^0.8.9;
+
+import {EventLib} from "../event/EventLib.sol";
+
+library QuoteLib {
+    bytes32 constant DIAMOND_STORAGE_POSITION =
+        keccak256("switchboard.push.quote.v1.storage");
+
+    // Why the function refused a request, the codes match QuoteFailure in the function
+    enum FailureReason {
+        None,
+        InvalidParams,
+        UnknownPair,
+        Stale
+    }
+
+    // Switchboard Function Parameters
+    // This struct will be defined here, but also in the Switchboard Function itself
+    // We will abi.decode it off-chain to get the parameters
+    struct QuoteParams {
+        uint256 requestId;
+        string pair; // e.g. "BTC/USD"
+        uint256 maxAge; // oldest data accepted when the quote lands, in seconds
+    }
+
+    struct Quote {
+        address sender;
+        address callId;
+        string pair;
+        uint256 maxAge;
+        int256 value;
+        uint256 confidence; // one standard deviation of the samples, at the value's decimals
+        uint8 decimals;
+        uint256 timestamp; // data timestamp
+        bool filled;
+        FailureReason failure;
+    }
+
+    struct DiamondStorage {
+        uint256 nextRequestId;
+        // request id -> quote
+        mapping(uint256 => Quote) quotes;
+        // failure callbacks only know the call id, calls that failed to decode have no request id
+        mapping(address => uint256) requestIds;
+    }
+
+    function diamondStorage()
+        internal
+        pure
+        returns (DiamondStorage storage ds)
+    {
+        bytes32 position = DIAMOND_STORAGE_POSITION;
+        assembly {
+            ds.slot := position
+        }
+    }
+
+    // register a request made through callId
+    function request(
+        address callId,
+        uint256 requestId,
+        string memory pair,
+        uint256 maxAge
+    ) internal {
+        DiamondStorage storage ds = diamondStorage();
+        Quote storage quote = ds.quotes[requestId];
+        quote.sender = msg.sender;
+        quote.callId = callId;
+        quote.pair = pair;
+        quote.maxAge = maxAge;
+        ds.requestIds[callId] = requestId;
+        emit EventLib.QuoteRequested(requestId, msg.sender, callId, pair);
+    }
+
+    // request ids start at 1, 0 marks calls without a request
+    function nextRequestId() internal returns (uint256) {
+        DiamondStorage storage ds = diamondStorage();
+        ds.nextRequestId++;
+        return ds.nextRequestId;
+    }
+
+    function fill(
+        uint256 requestId,
+        int256 value,
+        uint256 confidence,
+        uint8 decimals,
+        uint256 timestamp
+    ) internal {
+        Quote storage quote = diamondStorage().quotes[requestId];
+        quote.value = value;
+        quote.confidence = confidence;
+        quote.decimals = decimals;
+        quote.timestamp = timestamp;
+        quote.filled = true;
+        emit EventLib.QuoteFilled(
+            requestId,
+            value,
+            confidence,
+            decimals,
+            timestamp
+        );
+    }
+
+    function fail(uint256 requestId, FailureReason reason) internal {
+        Quote storage quote = diamondStorage().quotes[requestId];
+        quote.failure = reason;
+        emit EventLib.QuoteFailed(requestId, quote.callId, uint8(reason));
+    }
+
+    function quotes(uint256 requestId) internal view returns (Quote storage) {
+        return diamondStorage().quotes[requestId];
+    }
+
+    function requestIds(address callId) internal view returns (uint256) {
+        return diamondStorage().requestIds[callId];
+    }
+}
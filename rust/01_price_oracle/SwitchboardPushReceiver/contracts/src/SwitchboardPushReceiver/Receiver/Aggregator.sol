@@ -59,8 +59,13 @@ contract Aggregator is AggregatorV3Interface {
         description = _description;
     }
 
-    function decimals() external pure override returns (uint8) {
-        return 18;
+    function decimals() external view override returns (uint8) {
+        uint8 _decimals = switchboardPricesContract
+            .feeds(feedName)
+            .decimals;
+
+        // feeds registered before decimals were configurable are reported with 18 decimals
+        return _decimals == 0 ? 18 : _decimals;
     }
 
     function version() external pure override returns (uint256) {
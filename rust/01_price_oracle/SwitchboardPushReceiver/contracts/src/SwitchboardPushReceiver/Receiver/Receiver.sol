@@ -63,6 +63,55 @@ contract Receiver {
         ReceiverLib.failureCallback(_feedNames);
     }
 
+    // Failure callback with reasons - marking latest result as failed for a feed the function
+    // refused to update, e.g. because the venues didn't confirm an implausible jump
+    function failureReasonCallback(
+        bytes32[] memory _feedNames, // feed names
+        ReceiverLib.FailureReason[] memory _reasons // why each feed wasn't updated
+    ) external {
+        address functionId = Switchboard.getEncodedFunctionId();
+        if (AdminLib.functionId() == address(0)) {
+            AdminLib.setFunctionId(functionId);
+        }
+
+        // Assert that the sender is switchboard & the correct function id is encoded
+        if (functionId != AdminLib.functionId()) {
+            revert ErrorLib.InvalidSender(AdminLib.functionId(), functionId);
+        }
+
+        // make sure the input lengths are correct
+        if (_feedNames.length != _reasons.length) {
+            revert ErrorLib.IncorrectInputLength();
+        }
+
+        // Update each feed internally
+        ReceiverLib.failureReasonCallback(_feedNames, _reasons);
+    }
+
+    // Decimals callback - setting the fixed point decimals the function reports a feed with
+    function decimalsCallback(
+        bytes32[] memory _feedNames, // feed names
+        uint8[] memory _decimals // decimals of the feed values
+    ) external {
+        address functionId = Switchboard.getEncodedFunctionId();
+        if (AdminLib.functionId() == address(0)) {
+            AdminLib.setFunctionId(functionId);
+        }
+
+        // Assert that the sender is switchboard & the correct function id is encoded
+        if (functionId != AdminLib.functionId()) {
+            revert ErrorLib.InvalidSender(AdminLib.functionId(), functionId);
+        }
+
+        // make sure the input lengths are correct
+        if (_feedNames.length != _decimals.length) {
+            revert ErrorLib.IncorrectInputLength();
+        }
+
+        // Update each feed internally
+        ReceiverLib.decimalsCallback(_feedNames, _decimals);
+    }
+
     // Deploy a Classic Push Model Adapter
     function deployFeedAdapter(
         address feedId,
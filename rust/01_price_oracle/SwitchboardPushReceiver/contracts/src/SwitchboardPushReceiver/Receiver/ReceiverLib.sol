@@ -8,6 +8,14 @@ library ReceiverLib {
     bytes32 constant DIAMOND_STORAGE_POSITION =
         keccak256("switchboard.push.receiver.v1.storage");
 
+    // Why the function refused to update a feed, reported by FeedFailed. None is reserved for
+    // feeds that were marked failed without a reason.
+    enum FailureReason {
+        None,
+        PriceJump,
+        ReferenceDivergence
+    }
+
     struct Result {
         int256 value;
         uint256 startedAt;
@@ -21,6 +29,7 @@ library ReceiverLib {
         Result latestResult; // used by default for getLatestResult
         bool historyEnabled; // by default off so we don't store all feed histories for all 500+ feeds forever
         bool latestResultFailed;
+        uint8 decimals; // 0 for feeds registered before decimals were configurable (read as 18)
     }
 
     struct DiamondStorage {
@@ -54,6 +63,35 @@ library ReceiverLib {
         }
     }
 
+    function failureReasonCallback(
+        bytes32[] memory _feedNames,
+        FailureReason[] memory _reasons
+    ) internal {
+        DiamondStorage storage ds = diamondStorage();
+        for (uint256 i = 0; i < _feedNames.length; i++) {
+            ds.feeds[_feedNames[i]].latestResultFailed = true;
+            emit EventLib.FeedFailed(_feedNames[i], uint8(_reasons[i]));
+        }
+    }
+
+    function decimalsCallback(
+        bytes32[] memory _feedNames,
+        uint8[] memory _decimals
+    ) internal {
+        DiamondStorage storage ds = diamondStorage();
+        for (uint256 i = 0; i < _feedNames.length; i++) {
+            Feed storage feed = ds.feeds[_feedNames[i]];
+
+            // skip feeds that haven't been registered by a value callback yet
+            if (feed.feedId == address(0)) {
+                continue;
+            }
+
+            feed.decimals = _decimals[i];
+            emit EventLib.NewDecimals(_feedNames[i], _decimals[i]);
+        }
+    }
+
     // Switchboard Function will call this function with the feed ids and values
     function callback(
         bytes32[] memory _feedNames, // the function
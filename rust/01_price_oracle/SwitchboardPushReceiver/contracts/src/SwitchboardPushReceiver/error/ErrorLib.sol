@@ -10,4 +10,6 @@ library ErrorLib {
     error IncorrectInputLength();
     error RoundEmpty(bytes32 feedName, uint80 roundId);
     error FeedUninitialized(address feedId);
+    error InvalidValue(uint256 value);
+    error FunctionUninitialized();
 }
@@ -13,10 +13,30 @@ library EventLib {
         address indexed adapter,
         address indexed sender
     );
+    event NewDecimals(bytes32 indexed feedId, uint8 decimals);
+    event FeedFailed(bytes32 indexed feedId, uint8 reason);
     event ReadEvent(
         address indexed feedId,
         address indexed sender,
         int256 value,
         uint256 timestamp
     );
+    event QuoteRequested(
+        uint256 indexed requestId,
+        address indexed sender,
+        address callId,
+        string pair
+    );
+    event QuoteFilled(
+        uint256 indexed requestId,
+        int256 value,
+        uint256 confidence,
+        uint8 decimals,
+        uint256 timestamp
+    );
+    event QuoteFailed(
+        uint256 indexed requestId,
+        address callId,
+        uint8 reason
+    );
 }
@@ -0,0 +1,13 @@
+//SPDX-License-Identifier: UNLICENSED
+pragma solidity ^0.8.9;
+
+// Just enough of an ERC20 for the function to read a pool's token decimals
+contract MockToken {
+    string public symbol;
+    uint8 public decimals;
+
+    constructor(string memory _symbol, uint8 _decimals) {
+        symbol = _symbol;
+        decimals = _decimals;
+    }
+}
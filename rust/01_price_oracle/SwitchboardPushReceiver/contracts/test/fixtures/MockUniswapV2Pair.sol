@@ -0,0 +1,48 @@
+//SPDX-License-Identifier: UNLICENSED
+pragma solidity ^0.8.9;
+
+// A Uniswap V2 pair whose reserves are set directly, for testing the function's DEX sources on a
+// local dev chain. Its cumulative prices accrue between changes like a real pair's do.
+contract MockUniswapV2Pair {
+    address public token0;
+    address public token1;
+
+    uint112 private reserve0;
+    uint112 private reserve1;
+    uint32 private blockTimestampLast;
+
+    uint256 public price0CumulativeLast;
+    uint256 public price1CumulativeLast;
+
+    constructor(address _token0, address _token1) {
+        token0 = _token0;
+        token1 = _token1;
+    }
+
+    function setReserves(uint112 _reserve0, uint112 _reserve1) external {
+        // accrue the old price for the time it held, overflow is desired like in UniswapV2Pair
+        unchecked {
+            uint32 timestamp = uint32(block.timestamp);
+            uint32 elapsed = timestamp - blockTimestampLast;
+            if (elapsed > 0 && reserve0 != 0 && reserve1 != 0) {
+                price0CumulativeLast +=
+                    ((uint256(reserve1) << 112) / reserve0) *
+                    elapsed;
+                price1CumulativeLast +=
+                    ((uint256(reserve0) << 112) / reserve1) *
+                    elapsed;
+            }
+        }
+        reserve0 = _reserve0;
+        reserve1 = _reserve1;
+        blockTimestampLast = uint32(block.timestamp);
+    }
+
+    function getReserves()
+        external
+        view
+        returns (uint112, uint112, uint32)
+    {
+        return (reserve0, reserve1, blockTimestampLast);
+    }
+}
@@ -0,0 +1,54 @@
+//SPDX-License-Identifier: UNLICENSED
+pragma solidity ^0.8.9;
+
+// A Uniswap V3 pool whose spot price, in-range liquidity and TWAP tick are set directly, for
+// testing the function's DEX sources on a local dev chain. `observe` reports a tick that has
+// been constant at `twapTick` forever.
+contract MockUniswapV3Pool {
+    address public token0;
+    address public token1;
+
+    uint128 public liquidity;
+    uint160 private sqrtPriceX96;
+    int24 private tick;
+    int24 public twapTick;
+
+    constructor(address _token0, address _token1) {
+        token0 = _token0;
+        token1 = _token1;
+    }
+
+    function setState(
+        uint160 _sqrtPriceX96,
+        int24 _tick,
+        int24 _twapTick,
+        uint128 _liquidity
+    ) external {
+        sqrtPriceX96 = _sqrtPriceX96;
+        tick = _tick;
+        twapTick = _twapTick;
+        liquidity = _liquidity;
+    }
+
+    function slot0()
+        external
+        view
+        returns (uint160, int24, uint16, uint16, uint16, uint8, bool)
+    {
+        return (sqrtPriceX96, tick, 0, 1, 1, 0, true);
+    }
+
+    function observe(
+        uint32[] calldata secondsAgos
+    ) external view returns (int56[] memory, uint160[] memory) {
+        int56[] memory tickCumulatives = new int56[](secondsAgos.length);
+        uint160[] memory secondsPerLiquidityCumulativeX128s = new uint160[](
+            secondsAgos.length
+        );
+        for (uint256 i = 0; i < secondsAgos.length; i++) {
+            uint256 time = block.timestamp - secondsAgos[i];
+            tickCumulatives[i] = int56(twapTick) * int56(uint56(time));
+        }
+        return (tickCumulatives, secondsPerLiquidityCumulativeX128s);
+    }
+}
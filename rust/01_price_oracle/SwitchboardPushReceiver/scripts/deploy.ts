@@ -64,6 +64,11 @@ export async function deployDiamond() {
     ["OwnershipFacet", defaultCutAction],
     ["Admin", defaultCutAction],
     ["Receiver", defaultCutAction],
+    // diamonds deployed before quotes existed need the facet added once with ADD_QUOTE_FACET=1
+    [
+      "Quote",
+      process.env.ADD_QUOTE_FACET ? FacetCutAction.Add : defaultCutAction,
+    ],
   ];
   const cut = [];
   for (const [facetName, modifyMode] of FacetNames) {
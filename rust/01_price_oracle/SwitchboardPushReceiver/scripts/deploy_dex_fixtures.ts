@@ -0,0 +1,63 @@
+import * as fs from "fs";
+import { ethers, network } from "hardhat";
+
+// The fixtures live with the contract tests in contracts/test/fixtures, outside hardhat's
+// sources, so they're deployed from the artifacts `forge build` writes
+async function fixture(name: string) {
+  const [deployer] = await ethers.getSigners();
+  const artifact = JSON.parse(
+    fs.readFileSync(`forge-artifacts/${name}.sol/${name}.json`, "utf8")
+  );
+  return new ethers.ContractFactory(
+    artifact.abi,
+    artifact.bytecode.object,
+    deployer
+  );
+}
+
+// Deploys a WETH/USDC Uniswap V2 pair and V3 pool, both pricing WETH at 2000 USDC, for testing
+// the function's DEX sources against a local dev chain
+async function main() {
+  const MockToken = await fixture("MockToken");
+  const weth = await MockToken.deploy("WETH", 18);
+  await weth.deployed();
+  const usdc = await MockToken.deploy("USDC", 6);
+  await usdc.deployed();
+
+  // 1000 WETH against 2M USDC
+  const V2Pair = await fixture("MockUniswapV2Pair");
+  const v2 = await V2Pair.deploy(weth.address, usdc.address);
+  await v2.deployed();
+  await (
+    await v2.setReserves(
+      ethers.utils.parseUnits("1000", 18),
+      ethers.utils.parseUnits("2000000", 6)
+    )
+  ).wait();
+
+  // tick -200311 is 2000 USDC per WETH, the in-range liquidity amounts to about 2M USDC
+  const V3Pool = await fixture("MockUniswapV3Pool");
+  const v3 = await V3Pool.deploy(weth.address, usdc.address);
+  await v3.deployed();
+  await (
+    await v3.setState(
+      "3543191142285914000000000",
+      -200311,
+      -200311,
+      "44721359549995790"
+    )
+  ).wait();
+
+  // the function reads V2 pairs as they were twap_seconds ago, give them that much history
+  await network.provider.send("hardhat_mine", ["0x100", "0x3c"]);
+
+  console.log(`export DEX_V2_FIXTURE=${v2.address}`);
+  console.log(`export DEX_V3_FIXTURE=${v3.address}`);
+}
+
+main()
+  .then(() => process.exit(0))
+  .catch((error) => {
+    console.error(error);
+    process.exit(1);
+  });
@@ -1,8 +1,8 @@
 fn main() {
-    // Read the environment variable
-    let value =
-        std::env::var("SWITCHBOARD_PUSH_ADDRESS").expect("SWITCHBOARD_PUSH_ADDRESS must be set");
-
-    // Pass it to the Rust compiler
-    println!("cargo:rustc-env=SWITCHBOARD_PUSH_ADDRESS={}", value);
+    // The receiver address is resolved at runtime from SWITCHBOARD_PUSH_ADDRESS.
+    // If it's also set at build time, pass it through so it gets pinned as the default.
+    println!("cargo:rerun-if-env-changed=SWITCHBOARD_PUSH_ADDRESS");
+    if let Ok(value) = std::env::var("SWITCHBOARD_PUSH_ADDRESS") {
+        println!("cargo:rustc-env=SWITCHBOARD_PUSH_ADDRESS={}", value);
+    }
 }
@@ -0,0 +1,246 @@
+use rust_decimal::prelude::*;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+// A pair needs more samples than this to be aggregated
+pub const MIN_SAMPLES: usize = 2;
+
+// A venue agrees with an aggregate when its sample is this close to the median, in bps of it
+pub const AGREEMENT_BPS: i64 = 100;
+
+// One pair's samples reduced to a single value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aggregate {
+    pub median: Decimal,
+    // standard deviation of the samples the median was taken from
+    pub std_dev: Decimal,
+    // how many samples made it into the median
+    pub samples: usize,
+    // how many of the median's samples agree with it, within AGREEMENT_BPS. Callers pass one
+    // sample per venue, so it's how many venues confirm the value.
+    pub venues: usize,
+}
+
+// A sample tagged with the venue it came from, e.g. "kraken"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VenueSample {
+    pub venue: String,
+    pub price: Decimal,
+    pub weight: Decimal,
+}
+
+// Keep one sample per venue, so a venue that lists a pair under several symbols only counts
+// once: its heaviest sample, and of equally heavy ones the middle one by price
+pub fn one_per_venue(samples: Vec<VenueSample>) -> Vec<VenueSample> {
+    let mut by_venue = BTreeMap::<String, Vec<VenueSample>>::new();
+    for sample in samples {
+        by_venue
+            .entry(sample.venue.clone())
+            .or_default()
+            .push(sample);
+    }
+    by_venue
+        .into_values()
+        .map(|mut samples| {
+            let heaviest = samples.iter().map(|x| x.weight).max().unwrap();
+            samples.retain(|x| x.weight == heaviest);
+            samples.sort_by_key(|x| x.price);
+            samples.swap_remove((samples.len() - 1) / 2)
+        })
+        .collect()
+}
+
+fn distinct(venues: &[String]) -> Vec<&String> {
+    let mut distinct: Vec<&String> = venues.iter().collect();
+    distinct.sort();
+    distinct.dedup();
+    distinct
+}
+
+// How many different venues are in `venues`
+pub fn distinct_venues(venues: &[String]) -> usize {
+    distinct(venues).len()
+}
+
+// Whether enough distinct venues, and every required one, list a pair
+pub fn has_quorum(venues: &[String], min_venues: usize, required_venues: &[String]) -> bool {
+    let distinct = distinct(venues);
+    distinct.len() >= min_venues && required_venues.iter().all(|x| distinct.contains(&x))
+}
+
+// The price at which half the weight is above and half below, the midpoint where the weight
+// splits exactly between two prices. With equal weights this is the plain median.
+fn median_of(samples: &[(Decimal, Decimal)]) -> Decimal {
+    let half = samples.iter().map(|x| x.1).sum::<Decimal>() / Decimal::from(2);
+    let mut cumulative = Decimal::ZERO;
+    for (i, (price, weight)) in samples.iter().enumerate() {
+        cumulative += *weight;
+        if cumulative == half && i + 1 < samples.len() {
+            return (*price + samples[i + 1].0) / Decimal::from(2);
+        }
+        if cumulative >= half {
+            return *price;
+        }
+    }
+    samples[samples.len() - 1].0
+}
+
+fn std_dev_of(samples: &[(Decimal, Decimal)]) -> Decimal {
+    // get the weighted mean
+    let total: Decimal = samples.iter().map(|x| x.1).sum();
+    let mean = samples.iter().map(|x| x.0 * x.1).sum::<Decimal>() / total;
+
+    // get variance
+    let squared_deviations: Decimal = samples
+        .iter()
+        .map(|&(price, weight)| (price - mean).powi(2) * weight)
+        .sum();
+    let variance = squared_deviations / total;
+
+    // get standard deviation
+    variance.sqrt().unwrap()
+}
+
+// Take the median of the prices, drop the ones that aren't within 1 std dev of it and take the
+// median again. None if there are too few prices to aggregate.
+pub fn aggregate_prices(prices: Vec<Decimal>) -> Option<Aggregate> {
+    aggregate_weighted(prices.into_iter().map(|x| (x, Decimal::ONE)).collect())
+}
+
+// Like `aggregate_prices`, but each (price, weight) sample counts for its weight, e.g. DEX pools
+// by their liquidity. Samples without weight are dropped.
+pub fn aggregate_weighted(mut samples: Vec<(Decimal, Decimal)>) -> Option<Aggregate> {
+    samples.retain(|x| x.1 > Decimal::ZERO);
+    if samples.len() <= MIN_SAMPLES {
+        return None;
+    }
+    samples.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    // get the median price
+    let median = median_of(&samples);
+    let std_dev = std_dev_of(&samples);
+
+    // filter out prices that are not within 1 std dev of the mean
+    let filtered: Vec<(Decimal, Decimal)> = if samples.len() > 3 {
+        let lower_bound = median - std_dev;
+        let upper_bound = median + std_dev;
+        samples
+            .iter()
+            .filter(|x| x.0 > lower_bound && x.0 < upper_bound)
+            .copied()
+            .collect()
+    } else {
+        samples.clone()
+    };
+    // when every sample agrees the bounds are empty, keep them all
+    let samples = if filtered.is_empty() {
+        samples
+    } else {
+        filtered
+    };
+
+    // recalculate median
+    let median = median_of(&samples);
+    let tolerance = median.abs() * Decimal::new(AGREEMENT_BPS, 4);
+    let venues = samples
+        .iter()
+        .filter(|x| (x.0 - median).abs() <= tolerance)
+        .count();
+    Some(Aggregate {
+        median,
+        std_dev: std_dev_of(&samples),
+        samples: samples.len(),
+        venues,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prices(values: &[i64]) -> Vec<Decimal> {
+        values.iter().map(|x| Decimal::from(*x)).collect()
+    }
+
+    #[test]
+    fn needs_more_than_two_samples() {
+        assert_eq!(aggregate_prices(prices(&[100, 101])), None);
+        assert_eq!(
+            aggregate_prices(prices(&[101, 100, 102])).map(|x| x.median),
+            Some(Decimal::from(101))
+        );
+    }
+
+    #[test]
+    fn drops_outliers_before_taking_the_median() {
+        let aggregate = aggregate_prices(prices(&[100, 101, 99, 100, 500])).unwrap();
+        assert_eq!(aggregate.median, Decimal::from(100));
+        assert_eq!(aggregate.samples, 4);
+        // the outlier's venue doesn't confirm the median
+        assert_eq!(aggregate.venues, 4);
+    }
+
+    #[test]
+    fn keeps_identical_samples() {
+        let aggregate = aggregate_prices(prices(&[100, 100, 100, 100])).unwrap();
+        assert_eq!(aggregate.median, Decimal::from(100));
+        assert_eq!(aggregate.std_dev, Decimal::ZERO);
+        assert_eq!(aggregate.samples, 4);
+    }
+
+    #[test]
+    fn weighs_samples_by_their_weight() {
+        let weighted = |values: &[(i64, i64)]| {
+            values
+                .iter()
+                .map(|(price, weight)| (Decimal::from(*price), Decimal::from(*weight)))
+                .collect::<Vec<_>>()
+        };
+        // a deep pool outweighs two thin ones
+        let aggregate = aggregate_weighted(weighted(&[(100, 1), (101, 1), (102, 5)])).unwrap();
+        assert_eq!(aggregate.median, Decimal::from(102));
+        // equal weights split between the middle two
+        let aggregate = aggregate_weighted(weighted(&[(100, 2), (101, 2), (103, 2), (104, 2)]));
+        assert_eq!(aggregate.map(|x| x.median), Some(Decimal::from(102)));
+        // samples without weight don't count towards the minimum
+        assert_eq!(
+            aggregate_weighted(weighted(&[(100, 1), (101, 1), (102, 0)])),
+            None
+        );
+    }
+
+    #[test]
+    fn counts_each_venue_once() {
+        let sample = |venue: &str, price: i64, weight: i64| VenueSample {
+            venue: venue.to_string(),
+            price: Decimal::from(price),
+            weight: Decimal::from(weight),
+        };
+        let samples = one_per_venue(vec![
+            // kraken lists the pair under three symbols
+            sample("kraken", 100, 1),
+            sample("kraken", 300, 1),
+            sample("kraken", 101, 1),
+            sample("binance", 102, 1),
+            // the deeper of two pools on the same DEX
+            sample("uniswap", 90, 1),
+            sample("uniswap", 99, 2),
+        ]);
+        assert_eq!(
+            samples,
+            vec![
+                sample("binance", 102, 1),
+                sample("kraken", 101, 1),
+                sample("uniswap", 99, 2)
+            ]
+        );
+
+        let venues: Vec<String> = ["kraken", "kraken", "binance"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        assert!(!has_quorum(&venues, 3, &[]));
+        assert!(has_quorum(&venues, 2, &["kraken".to_string()]));
+        assert!(!has_quorum(&venues, 2, &["coinbase".to_string()]));
+    }
+}
@@ -27,6 +27,7 @@ impl Into<NormalizedTicker> for BitfinexPair {
         let book = self;
         let mut res = NormalizedTicker::default();
         res.price = book.last_price;
+        res.volume = Some(book.volume * book.last_price);
         res
     }
 }
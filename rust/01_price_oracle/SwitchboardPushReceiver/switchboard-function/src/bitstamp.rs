@@ -29,6 +29,7 @@ impl Into<NormalizedTicker> for BitstampTicker {
         let book = self;
         let mut res = NormalizedTicker::default();
         res.price = book.last;
+        res.volume = Some(book.volume * book.last);
         res
     }
 }
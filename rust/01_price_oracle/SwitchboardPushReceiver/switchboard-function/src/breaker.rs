@@ -0,0 +1,140 @@
+use crate::*;
+
+// How far a value moved from the on-chain one, as a fraction of it. Signed feeds like funding
+// rates cross zero, so theirs is the absolute move, already a fraction of notional. None if either
+// can't be converted or a price's on-chain value is zero.
+pub fn jump_of(key: &[u8; 32], current: FixedPoint, value: FixedPoint) -> Option<Decimal> {
+    if DerivativesConfig::is_funding_feed(&feed_name_to_string(key)) {
+        return absolute_move(current, value).ok();
+    }
+    let current = current.to_decimal().ok()?;
+    let value = value.to_decimal().ok()?;
+    if current.is_zero() {
+        return None;
+    }
+    Some(((value - current) / current).abs())
+}
+
+// Hold back values that jumped further from the on-chain value than their feed's
+// `max_jump_bps`, unless at least `jump_quorum` venues agree on the new value. `venues` counts
+// the distinct venues each value was taken from, before outliers were filtered, whatever kind of
+// feed it is. Feeds without a count can't be confirmed. Returns the refused feeds, which are
+// removed from `feed_updates`.
+pub fn check_jumps(
+    feed_map: &HashMap<[u8; 32], FixedPoint>,
+    feed_updates: &mut HashMap<[u8; 32], FixedPoint>,
+    venues: &HashMap<[u8; 32], usize>,
+    config: &OracleConfig,
+) -> Vec<([u8; 32], FeedFailure)> {
+    let mut refused = vec![];
+    for (key, value) in feed_updates.iter() {
+        let name = feed_name_to_string(key);
+        let feed = config.feed(&name);
+        let max_jump = match feed.max_jump_bps {
+            Some(bps) => Decimal::new(bps as i64, 4),
+            None => continue,
+        };
+        let jump = match feed_map.get(key).and_then(|x| jump_of(key, *x, *value)) {
+            Some(jump) if jump > max_jump => jump,
+            _ => continue,
+        };
+        let confirmations = venues.get(key).copied().unwrap_or(0);
+        let percent = (jump * Decimal::ONE_HUNDRED).round_dp(2);
+        if confirmations >= feed.jump_quorum {
+            println!(
+                "{} -> jumped {}%, confirmed by {} venues",
+                name, percent, confirmations
+            );
+            continue;
+        }
+        println!(
+            "{} -> refused, jumped {}% but only {} of {} venues confirm it",
+            name, percent, confirmations, feed.jump_quorum
+        );
+        refused.push((*key, FeedFailure::PriceJump));
+    }
+    refused.sort_by_key(|x| x.0);
+    for (key, _) in &refused {
+        feed_updates.remove(key);
+    }
+    refused
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::I256;
+
+    #[test]
+    fn refuses_unconfirmed_jumps() {
+        let price = |x: i64| FixedPoint::new(I256::from(x), 0);
+        let mut config = OracleConfig::default();
+        config.default.max_jump_bps = Some(2000);
+        let feed_map = HashMap::from([
+            (feed_name_bytes("BTC/USD"), price(30_000)),
+            (feed_name_bytes("ETH/USD"), price(2_000)),
+            (feed_name_bytes("SOL/USD"), price(20)),
+        ]);
+        let mut feed_updates = HashMap::from([
+            // 10% move, under the limit
+            (feed_name_bytes("BTC/USD"), price(27_000)),
+            // 90% crash with only 3 venues behind it
+            (feed_name_bytes("ETH/USD"), price(200)),
+            // doubled, and every venue agrees
+            (feed_name_bytes("SOL/USD"), price(40)),
+            (feed_name_bytes("NEW/USD"), price(1)),
+        ]);
+        let venues = HashMap::from([
+            (feed_name_bytes("ETH/USD"), 3),
+            (feed_name_bytes("SOL/USD"), 6),
+        ]);
+
+        let refused = check_jumps(&feed_map, &mut feed_updates, &venues, &config);
+        assert_eq!(
+            refused,
+            vec![(feed_name_bytes("ETH/USD"), FeedFailure::PriceJump)]
+        );
+        assert_eq!(feed_updates.len(), 3);
+        assert!(!feed_updates.contains_key(&feed_name_bytes("ETH/USD")));
+    }
+
+    #[test]
+    fn only_counts_venues_that_agree_with_the_jump() {
+        let mut config = OracleConfig::default();
+        config.default.max_jump_bps = Some(2000);
+        let key = feed_name_bytes("ETH/USD");
+        let feed_map = HashMap::from([(key, FixedPoint::new(I256::from(100), 0))]);
+        // three venues print a 90% crash, two still quote the old price
+        let prices = [10, 10, 10, 100, 100].map(Decimal::from).to_vec();
+        let aggregate = aggregate_prices(prices).unwrap();
+        assert_eq!(aggregate.median, Decimal::from(10));
+        let mut feed_updates = HashMap::from([(key, FixedPoint::new(I256::from(10), 0))]);
+        let venues = HashMap::from([(key, aggregate.venues)]);
+
+        let refused = check_jumps(&feed_map, &mut feed_updates, &venues, &config);
+        assert_eq!(refused, vec![(key, FeedFailure::PriceJump)]);
+    }
+
+    #[test]
+    fn measures_funding_jumps_against_notional() {
+        let rate = |x: i64| FixedPoint::new(I256::from(x), 4);
+        let mut config = OracleConfig::default();
+        config.default.max_jump_bps = Some(5);
+        let feed_map = HashMap::from([
+            (feed_name_bytes("BTC/USDT:FUND8h"), rate(1)),
+            (feed_name_bytes("ETH/USDT:FUND8h"), rate(1)),
+        ]);
+        let mut feed_updates = HashMap::from([
+            // flipped sign, but only moved 2 bps of notional
+            (feed_name_bytes("BTC/USDT:FUND8h"), rate(-1)),
+            // moved 10 bps of notional
+            (feed_name_bytes("ETH/USDT:FUND8h"), rate(-9)),
+        ]);
+
+        let refused = check_jumps(&feed_map, &mut feed_updates, &HashMap::new(), &config);
+        assert_eq!(
+            refused,
+            vec![(feed_name_bytes("ETH/USDT:FUND8h"), FeedFailure::PriceJump)]
+        );
+    }
+}
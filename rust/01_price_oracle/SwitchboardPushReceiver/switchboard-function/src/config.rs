@@ -0,0 +1,147 @@
+use crate::*;
+
+use serde::Deserialize;
+
+// Per-feed configuration. Fields left out of a feed's entry take the built-in defaults.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct FeedConfig {
+    // fixed point decimals the feed is published with
+    pub decimals: u8,
+    // seconds the on-chain value may go without an update before it's sent whatever the gas price
+    pub heartbeat: u64,
+    // a move of at least this many basis points is sent whatever the gas price
+    pub urgent_deviation_bps: u32,
+    // a jump from the on-chain value beyond this many basis points needs `jump_quorum` venues
+    // to confirm it, None turns the circuit breaker off
+    pub max_jump_bps: Option<u32>,
+    pub jump_quorum: usize,
+    // distinct venues that must list the pair for it to be published
+    pub min_venues: usize,
+    // venues the pair can't be published without, e.g. ["coinbase"]
+    pub required_venues: Vec<String>,
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        FeedConfig {
+            decimals: DEFAULT_DECIMALS,
+            heartbeat: 3600,
+            urgent_deviation_bps: 100,
+            max_jump_bps: None,
+            jump_quorum: 5,
+            min_venues: MIN_SAMPLES + 1,
+            required_venues: vec![],
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct OracleConfig {
+    // applied to every feed without an entry in `feeds`
+    pub default: FeedConfig,
+    // keyed by feed name, e.g. "BTC/USD"
+    pub feeds: HashMap<String, FeedConfig>,
+    // above this gas price only urgent updates are sent, see src/gas.rs
+    pub max_gas_price_gwei: Option<u64>,
+    // pairs published as TWAP feeds, see src/twap.rs
+    pub twap: TwapConfig,
+    // pairs published as volatility and volume feeds, see src/risk.rs
+    pub risk: RiskConfig,
+    // perpetuals published as mark, index and funding feeds, see src/derivatives.rs
+    pub derivatives: DerivativesConfig,
+    // on-chain pools read as extra samples, see src/dex.rs
+    pub dex: DexConfig,
+    // third-party AggregatorV3 feeds values are checked against, see src/reference.rs
+    pub reference: ReferenceConfig,
+    // venues dropped when their prices are off across pairs, see src/health.rs
+    pub health: HealthConfig,
+}
+
+impl OracleConfig {
+    // The config is compiled into the binary so it's covered by the enclave measurement
+    pub fn load() -> Self {
+        let config: OracleConfig = serde_json::from_str(include_str!("config.json")).unwrap();
+        for feed in std::iter::once(&config.default).chain(config.feeds.values()) {
+            if feed.decimals > MAX_DECIMALS {
+                panic!("{}", ConversionError::UnsupportedDecimals(feed.decimals));
+            }
+            // a median needs more than MIN_SAMPLES venues whatever the config says
+            if feed.min_venues <= MIN_SAMPLES {
+                panic!("min_venues must be greater than {}", MIN_SAMPLES);
+            }
+        }
+        // feed names are stored as bytes32
+        // -- candle and derivatives feeds are read from a fixed set of venues
+        let mut names = vec![];
+        let mut venue_limits = vec![];
+        for window in &config.twap.windows {
+            let window = TwapWindow::parse(window).unwrap();
+            let twaps = config.twap.pairs.iter().map(|x| window.feed_name(x));
+            venue_limits.extend(twaps.clone().map(|x| (x, CANDLE_VENUES.len())));
+            names.extend(twaps);
+        }
+        for window in &config.risk.volatility_windows {
+            let window = TwapWindow::parse(window).unwrap();
+            let volatilities = config
+                .risk
+                .pairs
+                .iter()
+                .map(|x| RiskConfig::volatility_feed_name(x, &window));
+            venue_limits.extend(volatilities.clone().map(|x| (x, CANDLE_VENUES.len())));
+            names.extend(volatilities);
+        }
+        names.extend(
+            config
+                .risk
+                .pairs
+                .iter()
+                .map(|x| RiskConfig::volume_feed_name(x)),
+        );
+        for pair in &config.derivatives.pairs {
+            let derivatives = DerivativesConfig::feed_names(pair);
+            venue_limits.extend(
+                derivatives
+                    .iter()
+                    .map(|x| (x.clone(), config.derivatives.venues.len())),
+            );
+            names.extend(derivatives);
+        }
+        for name in names {
+            if name.len() > 32 {
+                panic!("feed name {:?} is longer than 32 bytes", name);
+            }
+        }
+        // a feed with fewer venues than its quorum would never be published, and the circuit
+        // breaker would refuse its every jump
+        for (name, venues) in venue_limits {
+            let feed = config.feed(&name);
+            if feed.min_venues > venues {
+                panic!(
+                    "{} has a min_venues of {} but only {} venues",
+                    name, feed.min_venues, venues
+                );
+            }
+            if feed.max_jump_bps.is_some() && feed.jump_quorum > venues {
+                panic!(
+                    "{} has a jump_quorum of {} but only {} venues",
+                    name, feed.jump_quorum, venues
+                );
+            }
+        }
+        if !config.dex.pools.is_empty() && config.dex.twap_seconds == 0 {
+            panic!("dex.twap_seconds must be greater than 0");
+        }
+        for decimals in [config.risk.volatility_decimals, config.risk.volume_decimals] {
+            if decimals > MAX_DECIMALS {
+                panic!("{}", ConversionError::UnsupportedDecimals(decimals));
+            }
+        }
+        config
+    }
+
+    pub fn feed(&self, name: &str) -> &FeedConfig {
+        self.feeds.get(name).unwrap_or(&self.default)
+    }
+}
@@ -0,0 +1,361 @@
+use crate::*;
+
+use ethers::{
+    prelude::abigen,
+    providers::Middleware,
+    types::{Address, Bytes, U256},
+};
+
+// Rough execution gas of each part of a run, MAX_UPDATES and MAX_REGISTRATIONS were sized to fit
+// the gas limit with these. The overhead covers the Switchboard contract's verification of the
+// emitted transaction.
+pub const UPDATE_GAS: u64 = 50_000;
+pub const REGISTRATION_GAS: u64 = 250_000;
+pub const DECIMALS_GAS: u64 = 10_000;
+pub const FAILURE_GAS: u64 = 10_000;
+pub const EMIT_OVERHEAD_GAS: u64 = 150_000;
+
+// Calldata each part of a run adds. A callback's selector, heads and array lengths come to about
+// 164 bytes, the overhead covers the signed transaction the Switchboard contract verifies.
+pub const UPDATE_BYTES: u64 = 64;
+pub const DECIMALS_BYTES: u64 = 64;
+pub const FAILURE_BYTES: u64 = 32;
+pub const CALLBACK_BYTES: u64 = 164;
+pub const EMIT_OVERHEAD_BYTES: u64 = 500;
+
+// OP-stack chains price L1 data through the GasPriceOracle predeploy
+abigen!(
+    OpGasPriceOracle,
+    r#"[
+        function getL1Fee(bytes) external view returns (uint256)
+    ]"#,
+);
+
+// Arbitrum prices it through the NodeInterface, which only exists for eth_call
+abigen!(
+    ArbNodeInterface,
+    r#"[
+        function gasEstimateL1Component(address,bool,bytes) external payable returns (uint64,uint256,uint256)
+    ]"#,
+);
+
+const OP_GAS_PRICE_ORACLE: &str = "0x420000000000000000000000000000000000000F";
+const ARB_NODE_INTERFACE: &str = "0x00000000000000000000000000000000000000C8";
+
+#[derive(Debug)]
+pub enum CostError {
+    Provider(String),
+    UnknownChainKind(String),
+}
+
+impl fmt::Display for CostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CostError::Provider(err) => write!(f, "failed to price the run: {}", err),
+            CostError::UnknownChainKind(value) => write!(
+                f,
+                "unknown CHAIN_KIND {:?}, expected l1, arbitrum or op-stack",
+                value
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CostError {}
+
+// How a chain charges for the calldata a transaction posts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainKind {
+    // calldata is paid for in execution gas
+    L1,
+    // the L1 data fee is charged as extra L2 gas, so it counts against the gas limit
+    Arbitrum,
+    // the L1 data fee is charged on top of the execution gas
+    OpStack,
+}
+
+impl ChainKind {
+    pub fn from_chain_id(chain_id: u64) -> Self {
+        match chain_id {
+            42161 | 42170 | 421613 | 421614 => ChainKind::Arbitrum,
+            10 | 420 | 11155420 | 8453 | 84531 | 84532 | 7777777 | 34443 => ChainKind::OpStack,
+            _ => ChainKind::L1,
+        }
+    }
+
+    // CHAIN_KIND overrides the chain id lookup, e.g. for rollups that aren't in the list
+    pub fn resolve(chain_id: u64) -> Result<Self, CostError> {
+        match std::env::var("CHAIN_KIND") {
+            Ok(value) if !value.trim().is_empty() => match value.trim().to_lowercase().as_str() {
+                "l1" => Ok(ChainKind::L1),
+                "arbitrum" => Ok(ChainKind::Arbitrum),
+                "op-stack" | "optimism" => Ok(ChainKind::OpStack),
+                _ => Err(CostError::UnknownChainKind(value)),
+            },
+            _ => Ok(Self::from_chain_id(chain_id)),
+        }
+    }
+}
+
+// The execution gas and calldata of a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunSize {
+    pub gas: u64,
+    pub bytes: u64,
+}
+
+impl RunSize {
+    pub fn of(selection: &FeedSelection) -> Self {
+        let update_gas = match selection.registering_feeds {
+            true => REGISTRATION_GAS,
+            false => UPDATE_GAS,
+        };
+        let updates = selection.updates.len() as u64;
+        let decimals = selection.decimals_changes.len() as u64;
+        let (missing, refused) = match selection.registering_feeds {
+            true => (0, 0),
+            false => (
+                selection.missing_feeds.len() as u64,
+                selection.failures.len() as u64,
+            ),
+        };
+        let failures = missing + refused;
+        let callbacks = 1 + (decimals > 0) as u64 + (missing > 0) as u64 + (refused > 0) as u64;
+        RunSize {
+            gas: EMIT_OVERHEAD_GAS
+                + updates * update_gas
+                + decimals * DECIMALS_GAS
+                + failures * FAILURE_GAS,
+            bytes: EMIT_OVERHEAD_BYTES
+                + callbacks * CALLBACK_BYTES
+                + updates * UPDATE_BYTES
+                + decimals * DECIMALS_BYTES
+                + failures * FAILURE_BYTES,
+        }
+    }
+}
+
+impl RunSize {
+    // What one update adds to a run: its entry in the callback, and a decimals entry if the feed's
+    // decimals change with it
+    pub fn of_update(selection: &FeedSelection, key: &[u8; 32]) -> Self {
+        let update_gas = match selection.registering_feeds {
+            true => REGISTRATION_GAS,
+            false => UPDATE_GAS,
+        };
+        let decimals = selection.decimals_changes.iter().any(|x| x.0 == *key) as u64;
+        RunSize {
+            gas: update_gas + decimals * DECIMALS_GAS,
+            bytes: UPDATE_BYTES + decimals * DECIMALS_BYTES,
+        }
+    }
+}
+
+// Prices a run on this chain: execution gas at the current gas price, plus the L1 data fee of its
+// calldata on rollups
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CostModel {
+    pub kind: ChainKind,
+    // wei per unit of execution gas
+    pub gas_price: U256,
+    // wei per byte of calldata posted to L1, zero on L1s
+    pub l1_fee_per_byte: U256,
+}
+
+impl CostModel {
+    // Read the gas price and, on rollups, the L1 data fee of `calldata` sent to `to`. The fee is
+    // spread evenly over the calldata's bytes, so runs of any size can be priced from one read.
+    pub async fn read<M: Middleware + 'static>(
+        client: Arc<M>,
+        to: Address,
+        calldata: Bytes,
+    ) -> Result<Self, CostError> {
+        let chain_id = client
+            .get_chainid()
+            .await
+            .map_err(|e| CostError::Provider(e.to_string()))?;
+        let kind = ChainKind::resolve(chain_id.low_u64())?;
+        let gas_price = read_gas_price(client.as_ref())
+            .await
+            .map_err(|e| CostError::Provider(e.to_string()))?
+            .total();
+        let bytes = U256::from(calldata.len().max(1));
+        let l1_fee = match kind {
+            ChainKind::L1 => U256::zero(),
+            ChainKind::OpStack => {
+                let oracle =
+                    OpGasPriceOracle::new(OP_GAS_PRICE_ORACLE.parse::<Address>().unwrap(), client);
+                oracle
+                    .get_l1_fee(calldata)
+                    .call()
+                    .await
+                    .map_err(|e| CostError::Provider(e.to_string()))?
+            }
+            ChainKind::Arbitrum => {
+                let node =
+                    ArbNodeInterface::new(ARB_NODE_INTERFACE.parse::<Address>().unwrap(), client);
+                let (l1_gas, base_fee, _) = node
+                    .gas_estimate_l1_component(to, false, calldata)
+                    .call()
+                    .await
+                    .map_err(|e| CostError::Provider(e.to_string()))?;
+                U256::from(l1_gas) * base_fee
+            }
+        };
+        Ok(CostModel {
+            kind,
+            gas_price,
+            l1_fee_per_byte: l1_fee / bytes,
+        })
+    }
+
+    // Prices execution gas alone, for runs that couldn't read the chain's prices
+    pub fn execution_only() -> Self {
+        CostModel {
+            kind: ChainKind::L1,
+            gas_price: U256::zero(),
+            l1_fee_per_byte: U256::zero(),
+        }
+    }
+
+    pub fn l1_fee(&self, bytes: u64) -> U256 {
+        self.l1_fee_per_byte * U256::from(bytes)
+    }
+
+    // What a run of this size costs in wei
+    pub fn cost(&self, size: RunSize) -> U256 {
+        self.gas_price * U256::from(size.gas) + self.l1_fee(size.bytes)
+    }
+
+    // The run's cost per unit of execution gas, comparable to a gas price ceiling
+    pub fn effective_gas_price(&self, size: RunSize) -> U256 {
+        self.cost(size) / U256::from(size.gas.max(1))
+    }
+
+    // L2 gas the L1 data fee is charged as, only Arbitrum counts it against the gas limit
+    pub fn l1_gas(&self, bytes: u64) -> u64 {
+        if self.kind != ChainKind::Arbitrum || self.gas_price.is_zero() {
+            return 0;
+        }
+        let fee = self.l1_fee(bytes);
+        ((fee + self.gas_price - 1) / self.gas_price).low_u64()
+    }
+
+    // How many updates fit in the gas limit, alongside the run's other callbacks
+    pub fn max_updates(&self, gas_limit: u64, selection: &FeedSelection) -> usize {
+        let without_updates = FeedSelection {
+            updates: vec![],
+            ..selection.clone()
+        };
+        let fixed = RunSize::of(&without_updates);
+        let (update_gas, cap) = match selection.registering_feeds {
+            true => (REGISTRATION_GAS, MAX_REGISTRATIONS),
+            false => (UPDATE_GAS, MAX_UPDATES),
+        };
+        let available = gas_limit.saturating_sub(fixed.gas + self.l1_gas(fixed.bytes));
+        let per_update = update_gas + self.l1_gas(UPDATE_BYTES);
+        ((available / per_update) as usize).min(cap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::I256;
+
+    const GWEI: u64 = 1_000_000_000;
+
+    fn selection(updates: usize) -> FeedSelection {
+        FeedSelection {
+            updates: (0..updates)
+                .map(|i| {
+                    (
+                        [i as u8; 32],
+                        FixedPoint::new(I256::from(1), 18),
+                        UpdateReason::NewFeed,
+                    )
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn model(kind: ChainKind, l1_fee_per_byte: u64) -> CostModel {
+        CostModel {
+            kind,
+            gas_price: U256::from(GWEI / 10),
+            l1_fee_per_byte: U256::from(l1_fee_per_byte),
+        }
+    }
+
+    #[test]
+    fn maps_chain_ids_to_their_kind() {
+        assert_eq!(ChainKind::from_chain_id(42161), ChainKind::Arbitrum);
+        assert_eq!(ChainKind::from_chain_id(8453), ChainKind::OpStack);
+        assert_eq!(ChainKind::from_chain_id(1), ChainKind::L1);
+        // core dao testnet, the default network
+        assert_eq!(ChainKind::from_chain_id(1115), ChainKind::L1);
+    }
+
+    #[test]
+    fn adds_the_l1_data_fee_to_the_cost() {
+        let size = RunSize::of(&selection(10));
+        assert_eq!(size.gas, EMIT_OVERHEAD_GAS + 10 * UPDATE_GAS);
+        assert_eq!(
+            size.bytes,
+            EMIT_OVERHEAD_BYTES + CALLBACK_BYTES + 10 * UPDATE_BYTES
+        );
+
+        let l1 = model(ChainKind::L1, 0);
+        assert_eq!(l1.effective_gas_price(size), l1.gas_price);
+        let op = model(ChainKind::OpStack, 2 * GWEI);
+        assert_eq!(
+            op.cost(size),
+            op.gas_price * U256::from(size.gas) + U256::from(2 * GWEI) * U256::from(size.bytes)
+        );
+        assert!(op.effective_gas_price(size) > op.gas_price);
+    }
+
+    #[test]
+    fn prices_an_update_with_its_decimals_change() {
+        let mut selection = selection(2);
+        selection.decimals_changes = vec![([1u8; 32], 8)];
+        assert_eq!(
+            RunSize::of_update(&selection, &[0u8; 32]),
+            RunSize {
+                gas: UPDATE_GAS,
+                bytes: UPDATE_BYTES
+            }
+        );
+        assert_eq!(
+            RunSize::of_update(&selection, &[1u8; 32]),
+            RunSize {
+                gas: UPDATE_GAS + DECIMALS_GAS,
+                bytes: UPDATE_BYTES + DECIMALS_BYTES
+            }
+        );
+
+        // on rollups the L1 data fee makes each update pricier than its execution
+        let op = model(ChainKind::OpStack, 2 * GWEI);
+        let update = RunSize::of_update(&selection, &[0u8; 32]);
+        assert!(op.effective_gas_price(update) > op.gas_price);
+    }
+
+    #[test]
+    fn fits_fewer_updates_when_l1_data_counts_against_the_gas_limit() {
+        let selection = selection(MAX_UPDATES);
+        let gas_limit = 2_000_000;
+        let op = model(ChainKind::OpStack, 2 * GWEI);
+        assert_eq!(
+            op.max_updates(gas_limit, &selection),
+            ((gas_limit - EMIT_OVERHEAD_GAS) / UPDATE_GAS) as usize
+        );
+
+        // 64 bytes at 20 gas per byte adds 1280 gas to every update
+        let arbitrum = model(ChainKind::Arbitrum, 2 * GWEI);
+        assert_eq!(arbitrum.l1_gas(UPDATE_BYTES), 1_280);
+        assert!(
+            arbitrum.max_updates(gas_limit, &selection) < op.max_updates(gas_limit, &selection)
+        );
+    }
+}
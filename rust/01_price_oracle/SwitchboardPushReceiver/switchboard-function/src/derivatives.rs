@@ -0,0 +1,512 @@
+use crate::*;
+
+use futures::future::join_all;
+use serde::Deserialize;
+
+// Funding rates are published per 8 hours, whatever interval a venue charges them at
+pub const FUNDING_PERIOD: u64 = 8 * 3600;
+
+// Venues disagree on funding more than on prices, so there's no outlier filter and fewer venues
+// are enough for the median
+const MIN_FUNDING_SAMPLES: usize = 2;
+
+// A venue's funding rate agrees with the median within this much of notional, 1 bp
+const FUNDING_AGREEMENT: Decimal = Decimal::from_parts(1, 0, 0, false, 4);
+
+#[derive(Debug)]
+pub enum DerivativesError {
+    Request(String),
+    Decode(String),
+}
+
+impl fmt::Display for DerivativesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DerivativesError::Request(err) => write!(f, "failed to fetch derivatives: {}", err),
+            DerivativesError::Decode(err) => write!(f, "failed to decode derivatives: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DerivativesError {}
+
+// Perpetuals published as mark price, index price and funding rate feeds
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct DerivativesConfig {
+    pub pairs: Vec<String>,
+    // venues read, all of them unless configured. Binance's futures API refuses US IPs, unlike
+    // the binance.us spot API, so functions hosted in the US leave it out.
+    pub venues: Vec<DerivativesVenue>,
+}
+
+impl Default for DerivativesConfig {
+    fn default() -> Self {
+        DerivativesConfig {
+            pairs: vec![],
+            venues: DERIVATIVES_VENUES.to_vec(),
+        }
+    }
+}
+
+impl DerivativesConfig {
+    // e.g. "BTC/USDT:MARK", "BTC/USDT:INDEX" and "BTC/USDT:FUND8h"
+    pub fn feed_names(pair: &str) -> [String; 3] {
+        [
+            format!("{}:MARK", pair),
+            format!("{}:INDEX", pair),
+            format!("{}:FUND{}h", pair, FUNDING_PERIOD / 3600),
+        ]
+    }
+
+    // Funding rates are signed, they cross zero whenever the side paying funding flips
+    pub fn is_funding_feed(name: &str) -> bool {
+        name.ends_with(&format!(":FUND{}h", FUNDING_PERIOD / 3600))
+    }
+}
+
+// One venue's view of a perpetual
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DerivativesSample {
+    pub mark: Option<Decimal>,
+    pub index: Option<Decimal>,
+    // relative to the price, per FUNDING_PERIOD
+    pub funding_rate: Option<Decimal>,
+}
+
+// Scale a rate charged every `interval` seconds to FUNDING_PERIOD
+fn per_funding_period(rate: Decimal, interval: u64) -> Decimal {
+    rate * Decimal::from(FUNDING_PERIOD) / Decimal::from(interval.max(1))
+}
+
+// The venues with public derivatives endpoints
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DerivativesVenue {
+    Okx,
+    Binance,
+    Bitfinex,
+    KrakenFutures,
+    KucoinFutures,
+}
+
+pub const DERIVATIVES_VENUES: [DerivativesVenue; 5] = [
+    DerivativesVenue::Okx,
+    DerivativesVenue::Binance,
+    DerivativesVenue::Bitfinex,
+    DerivativesVenue::KrakenFutures,
+    DerivativesVenue::KucoinFutures,
+];
+
+impl DerivativesVenue {
+    // The venue's name, as in `venues` and `required_venues`
+    pub fn name(&self) -> String {
+        match self {
+            DerivativesVenue::Okx => "okx",
+            DerivativesVenue::Binance => "binance",
+            DerivativesVenue::Bitfinex => "bitfinex",
+            DerivativesVenue::KrakenFutures => "kraken_futures",
+            DerivativesVenue::KucoinFutures => "kucoin_futures",
+        }
+        .to_string()
+    }
+
+    // The endpoints the pair's perpetual is read from, parse gets their bodies in the same order
+    // https://www.okx.com/docs-v5/en/#public-data-rest-api-get-mark-price
+    // https://binance-docs.github.io/apidocs/futures/en/#mark-price
+    // https://docs.bitfinex.com/reference/rest-public-derivatives-status
+    // https://docs.futures.kraken.com/#http-api-trading-v3-api-market-data-get-tickers
+    // https://www.kucoin.com/docs/rest/futures-trading/market-data/get-current-mark-price
+    fn urls(&self, pair: &Pair) -> Vec<String> {
+        // kraken and kucoin still list bitcoin as XBT
+        let xbt = match pair.base.as_str() {
+            "BTC" => "XBT",
+            base => base,
+        };
+        match self {
+            DerivativesVenue::Okx => vec![
+                format!(
+                    "https://www.okx.com/api/v5/public/mark-price?instType=SWAP&instId={}-{}-SWAP",
+                    pair.base, pair.quote
+                ),
+                format!(
+                    "https://www.okx.com/api/v5/market/index-tickers?instId={}-{}",
+                    pair.base, pair.quote
+                ),
+                format!(
+                    "https://www.okx.com/api/v5/public/funding-rate?instId={}-{}-SWAP",
+                    pair.base, pair.quote
+                ),
+            ],
+            DerivativesVenue::Binance => vec![format!(
+                "https://fapi.binance.com/fapi/v1/premiumIndex?symbol={}{}",
+                pair.base, pair.quote
+            )],
+            DerivativesVenue::Bitfinex => {
+                // bitfinex perpetuals settle in USDt, which it calls UST
+                let quote = match pair.quote.as_str() {
+                    "USDT" => "UST",
+                    quote => quote,
+                };
+                vec![format!(
+                    "https://api-pub.bitfinex.com/v2/status/deriv?keys=t{}F0:{}F0",
+                    pair.base, quote
+                )]
+            }
+            DerivativesVenue::KrakenFutures => {
+                vec!["https://futures.kraken.com/derivatives/api/v3/tickers".to_string()]
+            }
+            DerivativesVenue::KucoinFutures => vec![
+                format!(
+                    "https://api-futures.kucoin.com/api/v1/mark-price/{}{}M/current",
+                    xbt, pair.quote
+                ),
+                format!(
+                    "https://api-futures.kucoin.com/api/v1/funding-rate/{}{}M/current",
+                    xbt, pair.quote
+                ),
+            ],
+        }
+    }
+
+    fn parse(&self, pair: &Pair, bodies: &[Value]) -> Result<DerivativesSample, DerivativesError> {
+        let unexpected =
+            |body: &Value| DerivativesError::Decode(format!("unexpected response {}", body));
+        let field = |body: &Value, pointer: &str| {
+            body.pointer(pointer)
+                .and_then(decimal_of)
+                .ok_or(unexpected(body))
+        };
+        match self {
+            DerivativesVenue::Okx => {
+                let interval = field(&bodies[2], "/data/0/nextFundingTime")?
+                    - field(&bodies[2], "/data/0/fundingTime")?;
+                Ok(DerivativesSample {
+                    mark: Some(field(&bodies[0], "/data/0/markPx")?),
+                    index: Some(field(&bodies[1], "/data/0/idxPx")?),
+                    funding_rate: Some(per_funding_period(
+                        field(&bodies[2], "/data/0/fundingRate")?,
+                        (interval / Decimal::from(1000))
+                            .to_u64()
+                            .unwrap_or(FUNDING_PERIOD),
+                    )),
+                })
+            }
+            // binance funds every 8 hours
+            DerivativesVenue::Binance => Ok(DerivativesSample {
+                mark: Some(field(&bodies[0], "/markPrice")?),
+                index: Some(field(&bodies[0], "/indexPrice")?),
+                funding_rate: Some(field(&bodies[0], "/lastFundingRate")?),
+            }),
+            // [KEY, MTS, _, DERIV_PRICE, SPOT_PRICE, _, INSURANCE_FUND_BALANCE, _,
+            //  NEXT_FUNDING_EVT_MTS, NEXT_FUNDING_ACCRUED, NEXT_FUNDING_STEP, _, CURRENT_FUNDING,
+            //  _, _, MARK_PRICE, ...], funded every 8 hours
+            DerivativesVenue::Bitfinex => Ok(DerivativesSample {
+                mark: Some(field(&bodies[0], "/0/15")?),
+                index: Some(field(&bodies[0], "/0/4")?),
+                funding_rate: Some(field(&bodies[0], "/0/12")?),
+            }),
+            // kraken's funding rate is absolute and charged hourly. Its perpetuals are all quoted in
+            // USD, they stand in for the USDT pair like bitfinex's UST ones.
+            DerivativesVenue::KrakenFutures => {
+                let base = match pair.base.as_str() {
+                    "BTC" => "XBT",
+                    base => base,
+                };
+                let quote = match pair.quote.as_str() {
+                    "USDT" => "USD",
+                    quote => quote,
+                };
+                let symbol = format!("PF_{}{}", base, quote);
+                let ticker = bodies[0]
+                    .get("tickers")
+                    .and_then(|x| x.as_array())
+                    .and_then(|x| {
+                        x.iter()
+                            .find(|t| t.get("symbol").and_then(|s| s.as_str()) == Some(&symbol))
+                    })
+                    .ok_or(DerivativesError::Decode(format!(
+                        "no ticker for {}",
+                        symbol
+                    )))?;
+                let index = field(ticker, "/indexPrice")?;
+                if index.is_zero() {
+                    return Err(unexpected(ticker));
+                }
+                Ok(DerivativesSample {
+                    mark: Some(field(ticker, "/markPrice")?),
+                    index: Some(index),
+                    funding_rate: Some(per_funding_period(
+                        field(ticker, "/fundingRate")? / index,
+                        3600,
+                    )),
+                })
+            }
+            DerivativesVenue::KucoinFutures => {
+                let granularity = field(&bodies[1], "/data/granularity")? / Decimal::from(1000);
+                Ok(DerivativesSample {
+                    mark: Some(field(&bodies[0], "/data/value")?),
+                    index: Some(field(&bodies[0], "/data/indexPrice")?),
+                    funding_rate: Some(per_funding_period(
+                        field(&bodies[1], "/data/value")?,
+                        granularity.to_u64().unwrap_or(FUNDING_PERIOD),
+                    )),
+                })
+            }
+        }
+    }
+}
+
+async fn fetch_derivatives(
+    client: &reqwest::Client,
+    venue: DerivativesVenue,
+    pair: &Pair,
+) -> Result<DerivativesSample, DerivativesError> {
+    let mut bodies = vec![];
+    for url in venue.urls(pair) {
+        let body: Value = client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| DerivativesError::Request(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| DerivativesError::Decode(e.to_string()))?;
+        bodies.push(body);
+    }
+    venue.parse(pair, &bodies)
+}
+
+// A pair's perpetual across venues. Mark and index prices are aggregated like spot, funding rates
+// are the plain median.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DerivativesAggregate {
+    pub mark: Option<Decimal>,
+    pub index: Option<Decimal>,
+    pub funding_rate: Option<Decimal>,
+    // how many venues agree with the mark, index and funding rate, in that order
+    pub venues: [usize; 3],
+}
+
+pub fn aggregate_derivatives(samples: &[DerivativesSample]) -> DerivativesAggregate {
+    let mark = aggregate_prices(samples.iter().filter_map(|x| x.mark).collect());
+    let index = aggregate_prices(samples.iter().filter_map(|x| x.index).collect());
+    let mut funding: Vec<Decimal> = samples.iter().filter_map(|x| x.funding_rate).collect();
+    funding.sort();
+    let funding_rate = match funding.len() {
+        n if n < MIN_FUNDING_SAMPLES => None,
+        n if n % 2 == 0 => Some((funding[n / 2 - 1] + funding[n / 2]) / Decimal::from(2)),
+        n => Some(funding[n / 2]),
+    };
+    let funding_venues = funding_rate.map_or(0, |median| {
+        funding
+            .iter()
+            .filter(|x| (**x - median).abs() <= FUNDING_AGREEMENT)
+            .count()
+    });
+    DerivativesAggregate {
+        mark: mark.as_ref().map(|x| x.median),
+        index: index.as_ref().map(|x| x.median),
+        funding_rate,
+        venues: [
+            mark.map_or(0, |x| x.venues),
+            index.map_or(0, |x| x.venues),
+            funding_venues,
+        ],
+    }
+}
+
+// Read each configured perpetual from every venue and aggregate it into its three feeds, along
+// with how many venues each feed was taken from
+pub async fn get_derivatives_data(
+    config: &OracleConfig,
+) -> (HashMap<[u8; 32], FixedPoint>, HashMap<[u8; 32], usize>) {
+    let client = reqwest::Client::new();
+    let mut feed_map = HashMap::<[u8; 32], FixedPoint>::new();
+    let mut feed_venues = HashMap::<[u8; 32], usize>::new();
+    for name in &config.derivatives.pairs {
+        let pair = Pair::from_string(name.clone());
+        let venues = &config.derivatives.venues;
+        let samples = join_all(
+            venues
+                .iter()
+                .map(|venue| fetch_derivatives(&client, *venue, &pair)),
+        )
+        .await;
+        let samples: Vec<(String, DerivativesSample)> = venues
+            .iter()
+            .zip(samples)
+            .filter_map(|(venue, sample)| match sample {
+                Ok(sample) => Some((venue.name(), sample)),
+                Err(e) => {
+                    println!("{} {:?} -> {}", name, venue, e);
+                    None
+                }
+            })
+            .collect();
+
+        // the venues each of the three feeds is listed by, for the quorum
+        let listing = |value: fn(&DerivativesSample) -> Option<Decimal>| -> Vec<String> {
+            samples
+                .iter()
+                .filter(|x| value(&x.1).is_some())
+                .map(|x| x.0.clone())
+                .collect()
+        };
+        let listings = [
+            listing(|x| x.mark),
+            listing(|x| x.index),
+            listing(|x| x.funding_rate),
+        ];
+
+        let samples: Vec<DerivativesSample> = samples.into_iter().map(|x| x.1).collect();
+        let aggregate = aggregate_derivatives(&samples);
+        let values = [aggregate.mark, aggregate.index, aggregate.funding_rate];
+        let counts = aggregate.venues;
+        let feeds = DerivativesConfig::feed_names(name);
+        for (((feed_name, value), count), listing) in
+            feeds.iter().zip(values).zip(counts).zip(listings)
+        {
+            let feed = config.feed(feed_name);
+            if !has_quorum(&listing, feed.min_venues, &feed.required_venues) {
+                println!("{} -> skipped, too few venues", feed_name);
+                continue;
+            }
+            let value = match value {
+                Some(value) => value,
+                None => {
+                    println!("{} -> skipped, not enough venues", feed_name);
+                    continue;
+                }
+            };
+            match FixedPoint::from_decimal(value, config.feed(feed_name).decimals) {
+                Ok(value) => {
+                    println!("{} -> {}", feed_name, value.value);
+                    feed_map.insert(feed_name_bytes(feed_name), value);
+                    feed_venues.insert(feed_name_bytes(feed_name), count);
+                }
+                Err(e) => println!("{} -> skipped, {}", feed_name, e),
+            }
+        }
+    }
+    (feed_map, feed_venues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(body: &str) -> Value {
+        serde_json::from_str(body).unwrap()
+    }
+
+    fn sample(mark: i64, funding_rate: Option<&str>) -> DerivativesSample {
+        DerivativesSample {
+            mark: Some(Decimal::from(mark)),
+            index: Some(Decimal::from(mark)),
+            funding_rate: funding_rate.map(|x| Decimal::from_str(x).unwrap()),
+        }
+    }
+
+    #[test]
+    fn normalizes_each_venues_perpetual() {
+        let pair = Pair::from("BTC/USDT");
+        let expected = |funding_rate: &str| DerivativesSample {
+            mark: Some(Decimal::from(100)),
+            index: Some(Decimal::from(99)),
+            funding_rate: Some(Decimal::from_str(funding_rate).unwrap()),
+        };
+
+        let okx = [
+            json(r#"{"code":"0","data":[{"instId":"BTC-USDT-SWAP","markPx":"100"}]}"#),
+            json(r#"{"code":"0","data":[{"instId":"BTC-USDT","idxPx":"99"}]}"#),
+            // funded every 4 hours
+            json(
+                r#"{"code":"0","data":[{"fundingRate":"0.0001","fundingTime":"14400000","nextFundingTime":"28800000"}]}"#,
+            ),
+        ];
+        assert_eq!(
+            DerivativesVenue::Okx.parse(&pair, &okx).unwrap(),
+            expected("0.0002")
+        );
+
+        let binance = [json(
+            r#"{"markPrice":"100","indexPrice":"99","lastFundingRate":"0.0001"}"#,
+        )];
+        assert_eq!(
+            DerivativesVenue::Binance.parse(&pair, &binance).unwrap(),
+            expected("0.0001")
+        );
+
+        let bitfinex = [json(
+            r#"[["tBTCF0:USTF0",1,null,100.5,99,null,1,null,1,0,0,null,0.0001,null,null,100,null,null,5]]"#,
+        )];
+        assert_eq!(
+            DerivativesVenue::Bitfinex.parse(&pair, &bitfinex).unwrap(),
+            expected("0.0001")
+        );
+
+        // 0.00099 USD an hour on a 99 USD index is 0.00001 an hour
+        let kraken = [json(
+            r#"{"tickers":[{"symbol":"PF_XBTUSD","markPrice":100,"indexPrice":99,"fundingRate":0.00099}]}"#,
+        )];
+        assert_eq!(
+            DerivativesVenue::KrakenFutures
+                .parse(&pair, &kraken)
+                .unwrap(),
+            expected("0.00008")
+        );
+
+        let kucoin = [
+            json(r#"{"code":"200000","data":{"value":100,"indexPrice":99}}"#),
+            json(r#"{"code":"200000","data":{"value":0.0001,"granularity":28800000}}"#),
+        ];
+        assert_eq!(
+            DerivativesVenue::KucoinFutures
+                .parse(&pair, &kucoin)
+                .unwrap(),
+            expected("0.0001")
+        );
+    }
+
+    #[test]
+    fn reads_the_configured_venues() {
+        let config: DerivativesConfig =
+            serde_json::from_str(r#"{"pairs":["BTC/USDT"],"venues":["okx","kraken_futures"]}"#)
+                .unwrap();
+        assert_eq!(
+            config.venues,
+            vec![DerivativesVenue::Okx, DerivativesVenue::KrakenFutures]
+        );
+        let config: DerivativesConfig = serde_json::from_str(r#"{"pairs":[]}"#).unwrap();
+        assert_eq!(config.venues, DERIVATIVES_VENUES.to_vec());
+        assert!(DerivativesConfig::is_funding_feed("BTC/USDT:FUND8h"));
+        assert!(!DerivativesConfig::is_funding_feed("BTC/USDT:MARK"));
+    }
+
+    #[test]
+    fn aggregates_funding_with_fewer_venues_than_prices() {
+        let aggregate =
+            aggregate_derivatives(&[sample(100, Some("0.0001")), sample(101, Some("-0.0003"))]);
+        // two venues aren't enough for a price
+        assert_eq!(aggregate.mark, None);
+        assert_eq!(
+            aggregate.funding_rate,
+            Some(Decimal::from_str("-0.0001").unwrap())
+        );
+
+        let aggregate = aggregate_derivatives(&[
+            sample(100, Some("0.0001")),
+            sample(101, None),
+            sample(102, Some("0.0002")),
+            sample(103, Some("0.0009")),
+        ]);
+        assert_eq!(aggregate.mark, Some(Decimal::from_str("101.5").unwrap()));
+        assert_eq!(
+            aggregate.funding_rate,
+            Some(Decimal::from_str("0.0002").unwrap())
+        );
+        // the venue charging 0.09% doesn't confirm the median
+        assert_eq!(aggregate.venues[2], 2);
+    }
+}
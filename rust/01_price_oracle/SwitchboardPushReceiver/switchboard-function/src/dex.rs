@@ -0,0 +1,702 @@
+use crate::*;
+
+use ethers::{
+    contract::ContractError,
+    prelude::abigen,
+    providers::Middleware,
+    types::{Address, BlockNumber, I256, U256, U512, U64},
+};
+use futures::future::join_all;
+use serde::Deserialize;
+
+// Only the calls we make, any Uniswap V2 fork exposes them
+abigen!(
+    UniswapV2Pair,
+    r#"[
+        function token0() external view returns (address)
+        function token1() external view returns (address)
+        function getReserves() external view returns (uint112,uint112,uint32)
+        function price0CumulativeLast() external view returns (uint256)
+    ]"#,
+);
+
+// and any Uniswap V3 fork these
+abigen!(
+    UniswapV3Pool,
+    r#"[
+        function token0() external view returns (address)
+        function token1() external view returns (address)
+        function liquidity() external view returns (uint128)
+        function slot0() external view returns (uint160,int24,uint16,uint16,uint16,uint8,bool)
+        function observe(uint32[]) external view returns (int56[],uint160[])
+    ]"#,
+);
+
+// Bounds of TickMath.getSqrtRatioAtTick
+pub const MIN_TICK: i32 = -887272;
+pub const MAX_TICK: i32 = 887272;
+
+// Decimals prices are computed to before they're converted to a decimal
+const PRICE_DECIMALS: u8 = 28;
+
+// Blocks the chain's block time is averaged over, to find the block a V2 TWAP starts at
+const BLOCK_TIME_SAMPLE: u64 = 100;
+
+abigen!(
+    Erc20Decimals,
+    r#"[
+        function decimals() external view returns (uint8)
+    ]"#,
+);
+
+#[derive(Debug)]
+pub enum DexError {
+    Provider(String),
+    // a reserve is zero, there's no price
+    EmptyPool,
+    // the price or a reserve doesn't fit in a decimal
+    OutOfRange,
+    // less liquidity than `min_liquidity`, in the quote currency
+    ThinPool(Decimal),
+    // the pool has no price history over `twap_seconds`
+    ShortHistory,
+    // the spot price is further from the TWAP than `max_spot_deviation_bps` allows
+    SpotDeviation { spot: Decimal, twap: Decimal },
+}
+
+impl fmt::Display for DexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DexError::Provider(err) => write!(f, "failed to read the pool: {}", err),
+            DexError::EmptyPool => write!(f, "the pool is empty"),
+            DexError::OutOfRange => write!(f, "the pool's price is out of range for a decimal"),
+            DexError::ThinPool(liquidity) => {
+                write!(f, "only {} of liquidity in the pool", liquidity)
+            }
+            DexError::ShortHistory => write!(f, "the pool has no history over the TWAP window"),
+            DexError::SpotDeviation { spot, twap } => write!(
+                f,
+                "spot price {} is too far from the TWAP {}, the pool may be manipulated",
+                spot, twap
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DexError {}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolKind {
+    // priced from the TWAP of `price0CumulativeLast`
+    UniswapV2,
+    // priced from the TWAP tick of `observe`
+    UniswapV3,
+}
+
+// Which of the pool's tokens is the pair's base
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BaseToken {
+    #[default]
+    Token0,
+    Token1,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct PoolConfig {
+    // the pair its samples count towards, e.g. a WETH/USDC pool as "ETH/USD"
+    pub pair: String,
+    pub kind: PoolKind,
+    pub address: Address,
+    #[serde(default)]
+    pub base: BaseToken,
+    // the venue the pool counts as towards a pair's quorum, e.g. "uniswap", the pool's own
+    // address if left out. Pools of the same DEX should share one, they're arbitraged together.
+    pub venue: Option<String>,
+}
+
+// Pools on the chain the function pushes to, read as extra samples next to the exchanges
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct DexConfig {
+    pub pools: Vec<PoolConfig>,
+    // seconds prices are averaged over. V3 pools need enough observations to cover it, V2 pools
+    // are read at a block this long ago, so the provider has to serve state that old.
+    pub twap_seconds: u32,
+    // liquidity, in the quote currency, at which a pool weighs as much as an exchange
+    pub full_weight_liquidity: u64,
+    // pools with less liquidity than this are skipped
+    pub min_liquidity: u64,
+    // pools whose spot price is further than this from their TWAP are skipped
+    pub max_spot_deviation_bps: u32,
+}
+
+impl Default for DexConfig {
+    fn default() -> Self {
+        DexConfig {
+            pools: vec![],
+            twap_seconds: 1800,
+            full_weight_liquidity: 10_000_000,
+            min_liquidity: 100_000,
+            max_spot_deviation_bps: 200,
+        }
+    }
+}
+
+// One pool's price, a sample weighted by the pool's liquidity
+#[derive(Debug, Clone, PartialEq)]
+pub struct DexQuote {
+    pub pair: Pair,
+    pub venue: String,
+    pub price: Decimal,
+    // twice the quote reserve, in the quote currency
+    pub liquidity: Decimal,
+    // between 0 and 1, an exchange's sample weighs 1
+    pub weight: Decimal,
+}
+
+// A pool's price and reserves in whole tokens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolState {
+    // token0 in token1
+    pub price: Decimal,
+    pub reserve0: Decimal,
+    pub reserve1: Decimal,
+}
+
+impl PoolState {
+    // Orient the price to the pair and weigh it by the pool's liquidity
+    pub fn quote(&self, pool: &PoolConfig, config: &DexConfig) -> Result<DexQuote, DexError> {
+        let (price, quote_reserve) = match pool.base {
+            BaseToken::Token0 => (self.price, self.reserve1),
+            BaseToken::Token1 => (
+                Decimal::ONE
+                    .checked_div(self.price)
+                    .ok_or(DexError::EmptyPool)?,
+                self.reserve0,
+            ),
+        };
+        let liquidity = quote_reserve * Decimal::from(2);
+        if liquidity < Decimal::from(config.min_liquidity) {
+            return Err(DexError::ThinPool(liquidity));
+        }
+        let weight =
+            (liquidity / Decimal::from(config.full_weight_liquidity.max(1))).min(Decimal::ONE);
+        Ok(DexQuote {
+            pair: Pair::from_string(pool.pair.clone()),
+            venue: pool.venue.clone().unwrap_or(format!("{:?}", pool.address)),
+            price,
+            liquidity,
+            weight,
+        })
+    }
+}
+
+// A raw token amount in whole tokens
+fn to_amount(raw: U256, decimals: u8) -> Result<Decimal, DexError> {
+    if raw.bit(255) {
+        return Err(DexError::OutOfRange);
+    }
+    FixedPoint::new(I256::from_raw(raw), decimals)
+        .to_decimal()
+        .map_err(|_| DexError::OutOfRange)
+}
+
+// A V2 pool's spot price is the ratio of its reserves
+pub fn v2_state(
+    reserve0: u128,
+    reserve1: u128,
+    decimals0: u8,
+    decimals1: u8,
+) -> Result<PoolState, DexError> {
+    let reserve0 = to_amount(U256::from(reserve0), decimals0)?;
+    let reserve1 = to_amount(U256::from(reserve1), decimals1)?;
+    if reserve0.is_zero() || reserve1.is_zero() {
+        return Err(DexError::EmptyPool);
+    }
+    Ok(PoolState {
+        price: reserve1.checked_div(reserve0).ok_or(DexError::OutOfRange)?,
+        reserve0,
+        reserve1,
+    })
+}
+
+// token0 in token1 from a raw price, raw token1 per raw token0 with `fraction_bits` fractional
+// bits, like a V3 sqrtPriceX96 squared or a V2 UQ112x112 average
+pub fn fixed_point_price(
+    raw: U512,
+    fraction_bits: usize,
+    decimals0: u8,
+    decimals1: u8,
+) -> Result<Decimal, DexError> {
+    if decimals0.max(decimals1) > MAX_DECIMALS {
+        return Err(DexError::OutOfRange);
+    }
+    // scale up before dividing so small prices keep their digits
+    let numerator = raw
+        .checked_mul(U512::exp10((decimals0 + PRICE_DECIMALS) as usize))
+        .ok_or(DexError::OutOfRange)?;
+    let denominator = (U512::one() << fraction_bits) * U512::exp10(decimals1 as usize);
+    let price = U256::try_from(numerator / denominator).map_err(|_| DexError::OutOfRange)?;
+    match to_amount(price, PRICE_DECIMALS)? {
+        price if price.is_zero() => Err(DexError::OutOfRange),
+        price => Ok(price),
+    }
+}
+
+// The cumulative price of token0 in token1 at `timestamp`, UQ112x112 seconds, the way Uniswap's
+// UniswapV2OracleLibrary counts the time since the reserves last changed at their price.
+// Cumulatives wrap around, only differences between them mean anything.
+pub fn v2_cumulative(
+    price0_cumulative_last: U256,
+    reserve0: u128,
+    reserve1: u128,
+    reserves_at: u32,
+    timestamp: u32,
+) -> Result<U256, DexError> {
+    if reserve0 == 0 || reserve1 == 0 {
+        return Err(DexError::EmptyPool);
+    }
+    let elapsed = U256::from(timestamp.wrapping_sub(reserves_at));
+    let price = (U256::from(reserve1) << 112) / U256::from(reserve0);
+    Ok(price0_cumulative_last.overflowing_add(price.overflowing_mul(elapsed).0).0)
+}
+
+// token0 in token1, averaged between two V2 cumulatives `seconds` apart
+pub fn v2_twap(
+    start: U256,
+    end: U256,
+    seconds: u64,
+    decimals0: u8,
+    decimals1: u8,
+) -> Result<Decimal, DexError> {
+    if seconds == 0 {
+        return Err(DexError::ShortHistory);
+    }
+    let average = end.overflowing_sub(start).0 / U256::from(seconds);
+    fixed_point_price(U512::from(average), 112, decimals0, decimals1)
+}
+
+// The average tick between two `observe` cumulatives, rounded down like Uniswap's OracleLibrary
+pub fn mean_tick(cumulatives: &[i64], seconds: u32) -> Result<i32, DexError> {
+    let (start, end) = match cumulatives {
+        [start, end, ..] => (*start, *end),
+        _ => return Err(DexError::ShortHistory),
+    };
+    let delta = end.checked_sub(start).ok_or(DexError::OutOfRange)?;
+    let seconds = seconds.max(1) as i64;
+    let mut tick = delta / seconds;
+    if delta < 0 && delta % seconds != 0 {
+        tick -= 1;
+    }
+    match i32::try_from(tick) {
+        Ok(tick) if (MIN_TICK..=MAX_TICK).contains(&tick) => Ok(tick),
+        _ => Err(DexError::OutOfRange),
+    }
+}
+
+// sqrt(1.0001^tick) as a Q64.96, exactly as Uniswap's TickMath.getSqrtRatioAtTick computes it
+pub fn sqrt_ratio_at_tick(tick: i32) -> Result<U256, DexError> {
+    if !(MIN_TICK..=MAX_TICK).contains(&tick) {
+        return Err(DexError::OutOfRange);
+    }
+    // 2^128 / sqrt(1.0001)^bit, for each bit of the tick
+    const FACTORS: [(u32, &str); 19] = [
+        (0x2, "fff97272373d413259a46990580e213a"),
+        (0x4, "fff2e50f5f656932ef12357cf3c7fdcc"),
+        (0x8, "ffe5caca7e10e4e61c3624eaa0941cd0"),
+        (0x10, "ffcb9843d60f6159c9db58835c926644"),
+        (0x20, "ff973b41fa98c081472e6896dfb254c0"),
+        (0x40, "ff2ea16466c96a3843ec78b326b52861"),
+        (0x80, "fe5dee046a99a2a811c461f1969c3053"),
+        (0x100, "fcbe86c7900a88aedcffc83b479aa3a4"),
+        (0x200, "f987a7253ac413176f2b074cf7815e54"),
+        (0x400, "f3392b0822b70005940c7a398e4b70f3"),
+        (0x800, "e7159475a2c29b7443b29c7fa6e889d9"),
+        (0x1000, "d097f3bdfd2022b8845ad8f792aa5825"),
+        (0x2000, "a9f746462d870fdf8a65dc1f90e061e5"),
+        (0x4000, "70d869a156d2a1b890bb3df62baf32f7"),
+        (0x8000, "31be135f97d08fd981231505542fcfa6"),
+        (0x10000, "9aa508b5b7a84e1c677de54f3e99bc9"),
+        (0x20000, "5d6af8dedb81196699c329225ee604"),
+        (0x40000, "2216e584f5fa1ea926041bedfe98"),
+        (0x80000, "48a170391f7dc42444e8fa2"),
+    ];
+    let factor = |hex: &str| U256::from_str_radix(hex, 16).unwrap();
+    let abs_tick = tick.unsigned_abs();
+    let mut ratio = match abs_tick & 0x1 {
+        0 => U256::one() << 128,
+        _ => factor("fffcb933bd6fad37aa2d162d1a594001"),
+    };
+    for (bit, hex) in FACTORS {
+        if abs_tick & bit != 0 {
+            ratio = (ratio * factor(hex)) >> 128;
+        }
+    }
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+    // round up to a Q64.96
+    let rounding = match (ratio % (U256::one() << 32)).is_zero() {
+        true => U256::zero(),
+        false => U256::one(),
+    };
+    Ok((ratio >> 32) + rounding)
+}
+
+// token0 in token1 at a tick, the way Uniswap's OracleLibrary quotes at a tick
+pub fn tick_price(tick: i32, decimals0: u8, decimals1: u8) -> Result<Decimal, DexError> {
+    let sqrt_ratio = sqrt_ratio_at_tick(tick)?;
+    fixed_point_price(sqrt_ratio.full_mul(sqrt_ratio), 192, decimals0, decimals1)
+}
+
+// How far a spot price is from the TWAP, in bps of the TWAP
+pub fn spot_deviation_bps(spot: Decimal, twap: Decimal) -> Result<Decimal, DexError> {
+    let ratio = spot.checked_div(twap).ok_or(DexError::OutOfRange)?;
+    Ok((ratio - Decimal::ONE).abs() * Decimal::from(10_000))
+}
+
+// Skip a pool whose spot price was pushed away from its TWAP, a single block's price is cheap to
+// move. V3 ticks are steps of 1.0001 rather than bps, so both ticks are priced before comparing.
+fn check_spot(spot: Decimal, twap: Decimal, config: &DexConfig) -> Result<(), DexError> {
+    match spot_deviation_bps(spot, twap)? > Decimal::from(config.max_spot_deviation_bps) {
+        true => Err(DexError::SpotDeviation { spot, twap }),
+        false => Ok(()),
+    }
+}
+
+// The reserves the in-range liquidity amounts to at the current price, in raw token amounts:
+// `L / sqrtP` of token0 and `L * sqrtP` of token1
+pub fn virtual_reserves(liquidity: u128, sqrt_price_x96: U256) -> Result<(U256, U256), DexError> {
+    if sqrt_price_x96.is_zero() {
+        return Err(DexError::EmptyPool);
+    }
+    let q96 = U512::one() << 96;
+    let reserve0 = (U256::from(liquidity).full_mul(U256::one() << 96)) / U512::from(sqrt_price_x96);
+    let reserve1 = U256::from(liquidity).full_mul(sqrt_price_x96) / q96;
+    Ok((
+        U256::try_from(reserve0).map_err(|_| DexError::OutOfRange)?,
+        U256::try_from(reserve1).map_err(|_| DexError::OutOfRange)?,
+    ))
+}
+
+async fn token_decimals<M: Middleware + 'static>(
+    client: Arc<M>,
+    token: Address,
+) -> Result<u8, DexError> {
+    Erc20Decimals::new(token, client)
+        .decimals()
+        .call()
+        .await
+        .map_err(|e| DexError::Provider(e.to_string()))
+}
+
+// The latest block's number and timestamp, and the block about `seconds` before it going by the
+// chain's recent block time
+async fn twap_blocks<M: Middleware + 'static>(
+    client: &M,
+    seconds: u32,
+) -> Result<((U64, u64), (U64, u64)), DexError> {
+    let block = |number: BlockNumber| async move {
+        match client.get_block(number).await {
+            Ok(Some(block)) => Ok((block.number.unwrap_or_default(), block.timestamp.low_u64())),
+            Ok(None) => Err(DexError::Provider(format!("no block {:?}", number))),
+            Err(e) => Err(DexError::Provider(e.to_string())),
+        }
+    };
+    let latest = block(BlockNumber::Latest).await?;
+    let sample = block(latest.0.saturating_sub(BLOCK_TIME_SAMPLE.into()).into()).await?;
+    let blocks = (latest.0 - sample.0).as_u64();
+    let block_time = match blocks {
+        0 => return Err(DexError::ShortHistory),
+        blocks => ((latest.1 - sample.1) / blocks).max(1),
+    };
+    let behind = (seconds as u64).div_ceil(block_time);
+    let start = block(latest.0.saturating_sub(behind.into()).into()).await?;
+    Ok((latest, start))
+}
+
+async fn read_v2<M: Middleware + 'static>(
+    client: Arc<M>,
+    address: Address,
+    config: &DexConfig,
+) -> Result<PoolState, DexError> {
+    let pool = UniswapV2Pair::new(address, client.clone());
+    let (latest, start) = twap_blocks(client.as_ref(), config.twap_seconds).await?;
+    let provider = |e: ContractError<M>| DexError::Provider(e.to_string());
+    let token0 = pool.token_0().call().await.map_err(provider)?;
+    let token1 = pool.token_1().call().await.map_err(provider)?;
+    let at_latest = (pool.get_reserves(), pool.price_0_cumulative_last());
+    let reserves = at_latest.0.block(latest.0).call().await.map_err(provider)?;
+    let cumulative = at_latest.1.block(latest.0).call().await.map_err(provider)?;
+    let at_start = (pool.get_reserves(), pool.price_0_cumulative_last());
+    let start_reserves = at_start.0.block(start.0).call().await.map_err(provider)?;
+    let start_cumulative = at_start.1.block(start.0).call().await.map_err(provider)?;
+    let decimals0 = token_decimals(client.clone(), token0).await?;
+    let decimals1 = token_decimals(client, token1).await?;
+
+    // price from the TWAP, a single block's spot price is cheap to move
+    let (reserve0, reserve1, reserves_at) = reserves;
+    let end = v2_cumulative(cumulative, reserve0, reserve1, reserves_at, latest.1 as u32)?;
+    let (start_reserve0, start_reserve1, start_reserves_at) = start_reserves;
+    let start_cumulative = v2_cumulative(
+        start_cumulative,
+        start_reserve0,
+        start_reserve1,
+        start_reserves_at,
+        start.1 as u32,
+    )?;
+    let twap = v2_twap(
+        start_cumulative,
+        end,
+        latest.1.saturating_sub(start.1),
+        decimals0,
+        decimals1,
+    )?;
+    let spot = v2_state(reserve0, reserve1, decimals0, decimals1)?;
+    check_spot(spot.price, twap, config)?;
+    Ok(PoolState {
+        price: twap,
+        ..spot
+    })
+}
+
+async fn read_v3<M: Middleware + 'static>(
+    client: Arc<M>,
+    address: Address,
+    config: &DexConfig,
+) -> Result<PoolState, DexError> {
+    let pool = UniswapV3Pool::new(address, client.clone());
+    let token0 = pool.token_0().call().await;
+    let token1 = pool.token_1().call().await;
+    let liquidity = pool.liquidity().call().await;
+    let slot0 = pool.slot_0().call().await;
+    let observations = pool.observe(vec![config.twap_seconds, 0]).call().await;
+    let (token0, token1, liquidity, slot0, (cumulatives, _)) =
+        match (token0, token1, liquidity, slot0, observations) {
+            (Ok(a), Ok(b), Ok(c), Ok(d), Ok(e)) => (a, b, c, d, e),
+            (Err(e), ..)
+            | (_, Err(e), ..)
+            | (_, _, Err(e), ..)
+            | (.., Err(e), _)
+            | (.., Err(e)) => return Err(DexError::Provider(e.to_string())),
+        };
+    let (sqrt_price_x96, spot_tick) = (slot0.0, slot0.1);
+
+    // price from the TWAP, a single block's spot price is cheap to move
+    let twap_tick = mean_tick(&cumulatives, config.twap_seconds)?;
+    let decimals0 = token_decimals(client.clone(), token0).await?;
+    let decimals1 = token_decimals(client, token1).await?;
+    let twap = tick_price(twap_tick, decimals0, decimals1)?;
+    check_spot(tick_price(spot_tick, decimals0, decimals1)?, twap, config)?;
+    let (reserve0, reserve1) = virtual_reserves(liquidity, sqrt_price_x96)?;
+    Ok(PoolState {
+        price: twap,
+        reserve0: to_amount(reserve0, decimals0)?,
+        reserve1: to_amount(reserve1, decimals1)?,
+    })
+}
+
+pub async fn read_pool<M: Middleware + 'static>(
+    client: Arc<M>,
+    pool: &PoolConfig,
+    config: &DexConfig,
+) -> Result<DexQuote, DexError> {
+    let state = match pool.kind {
+        PoolKind::UniswapV2 => read_v2(client, pool.address, config).await?,
+        PoolKind::UniswapV3 => read_v3(client, pool.address, config).await?,
+    };
+    state.quote(pool, config)
+}
+
+// Read every configured pool through the function's provider, pools that can't be read or are
+// too thin are left out
+pub async fn read_dex_quotes<M: Middleware + 'static>(
+    client: Arc<M>,
+    config: &DexConfig,
+) -> Vec<DexQuote> {
+    let quotes = join_all(
+        config
+            .pools
+            .iter()
+            .map(|pool| read_pool(client.clone(), pool, config)),
+    )
+    .await;
+    let mut result = vec![];
+    for (pool, quote) in config.pools.iter().zip(quotes) {
+        match quote {
+            Ok(quote) => {
+                println!(
+                    "{} {:?} -> {} (weight {})",
+                    pool.pair, pool.address, quote.price, quote.weight
+                );
+                result.push(quote);
+            }
+            Err(e) => println!("{} {:?} -> skipped, {}", pool.pair, pool.address, e),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{Http, Provider};
+
+    fn pool(base: BaseToken) -> PoolConfig {
+        PoolConfig {
+            pair: "ETH/USD".to_string(),
+            kind: PoolKind::UniswapV2,
+            address: Address::zero(),
+            base,
+            venue: None,
+        }
+    }
+
+    #[test]
+    fn prices_v2_pools_from_their_reserves() {
+        let config = DexConfig::default();
+        // 1000 WETH (18 decimals) against 2M USDC (6 decimals)
+        let state = v2_state(1_000 * 10u128.pow(18), 2_000_000 * 10u128.pow(6), 18, 6).unwrap();
+        assert_eq!(state.price, Decimal::from(2000));
+
+        let quote = state.quote(&pool(BaseToken::Token0), &config).unwrap();
+        assert_eq!(quote.liquidity, Decimal::from(4_000_000));
+        assert_eq!(quote.weight, Decimal::new(4, 1));
+
+        // the same pool with USDC as the base, its liquidity is counted in WETH
+        assert!(matches!(
+            state.quote(&pool(BaseToken::Token1), &config),
+            Err(DexError::ThinPool(_))
+        ));
+        let config = DexConfig {
+            min_liquidity: 0,
+            full_weight_liquidity: 1_000,
+            ..config
+        };
+        let quote = state.quote(&pool(BaseToken::Token1), &config).unwrap();
+        assert_eq!(quote.price, Decimal::new(5, 4));
+        assert_eq!(quote.liquidity, Decimal::from(2_000));
+        assert_eq!(quote.weight, Decimal::ONE);
+
+        assert!(matches!(v2_state(0, 1, 18, 6), Err(DexError::EmptyPool)));
+    }
+
+    #[test]
+    fn prices_v3_pools_from_the_twap_tick() {
+        // rounds towards negative infinity like the OracleLibrary
+        assert_eq!(mean_tick(&[0, 1800 * 10 + 5], 1800).unwrap(), 10);
+        assert_eq!(mean_tick(&[0, -(1800 * 10 + 5)], 1800).unwrap(), -11);
+        // a pool that returns too few cumulatives, or a tick past the bounds
+        assert!(matches!(mean_tick(&[0], 1800), Err(DexError::ShortHistory)));
+        assert!(matches!(
+            mean_tick(&[0, (MAX_TICK as i64 + 1) * 1800], 1800),
+            Err(DexError::OutOfRange)
+        ));
+
+        // TickMath's own test values
+        assert_eq!(sqrt_ratio_at_tick(0).unwrap(), U256::one() << 96);
+        assert_eq!(
+            sqrt_ratio_at_tick(MIN_TICK).unwrap(),
+            U256::from(4295128739u64)
+        );
+        assert_eq!(
+            sqrt_ratio_at_tick(MAX_TICK).unwrap(),
+            U256::from_dec_str("1461446703485210103287273052203988822378723970342").unwrap()
+        );
+        assert_eq!(
+            sqrt_ratio_at_tick(1).unwrap(),
+            U256::from_dec_str("79232123823359799118286999568").unwrap()
+        );
+        assert!(sqrt_ratio_at_tick(MAX_TICK + 1).is_err());
+
+        // tick -200311 is about 2000 USDC per WETH
+        let price = tick_price(-200_311, 18, 6).unwrap();
+        assert_eq!(price.round_dp(4), Decimal::from_str("2000.0403").unwrap());
+        // and about 0.0005 WETH per USDC the other way round
+        let price = tick_price(200_311, 6, 18).unwrap();
+        assert_eq!(
+            price.round_dp(10),
+            Decimal::from_str("0.0004999899").unwrap()
+        );
+
+        // at a sqrt price of 1, both virtual reserves equal the liquidity
+        let (reserve0, reserve1) = virtual_reserves(1_000_000, U256::one() << 96).unwrap();
+        assert_eq!(reserve0, U256::from(1_000_000));
+        assert_eq!(reserve1, U256::from(1_000_000));
+        // at a price of 4 there's half as much token0 and twice as much token1
+        let (reserve0, reserve1) = virtual_reserves(1_000_000, U256::from(2) << 96).unwrap();
+        assert_eq!(reserve0, U256::from(500_000));
+        assert_eq!(reserve1, U256::from(2_000_000));
+    }
+
+    #[test]
+    fn prices_v2_pools_from_their_cumulatives() {
+        // 1000 WETH against 2M USDC since the reserves last changed, 600 seconds before
+        let (reserve0, reserve1) = (1_000 * 10u128.pow(18), 2_000_000 * 10u128.pow(6));
+        let start = v2_cumulative(U256::MAX, reserve0, reserve1, 100, 700).unwrap();
+        let end = v2_cumulative(U256::MAX, reserve0, reserve1, 100, 2_500).unwrap();
+        // cumulatives wrap around
+        assert!(end < U256::MAX);
+        let twap = v2_twap(start, end, 1_800, 18, 6).unwrap();
+        assert_eq!(twap.round_dp(8), Decimal::from(2000));
+        assert!(matches!(
+            v2_twap(start, end, 0, 18, 6),
+            Err(DexError::ShortHistory)
+        ));
+        assert!(matches!(
+            v2_cumulative(U256::zero(), 0, reserve1, 100, 700),
+            Err(DexError::EmptyPool)
+        ));
+    }
+
+    #[test]
+    fn skips_pools_whose_spot_left_the_twap() {
+        let config = DexConfig::default();
+        let twap = Decimal::from(2000);
+        assert!(check_spot(Decimal::from(2039), twap, &config).is_ok());
+        assert!(matches!(
+            check_spot(Decimal::from(2041), twap, &config),
+            Err(DexError::SpotDeviation { .. })
+        ));
+
+        // 200 ticks are a 2.02% move, past 200 bps
+        let spot = tick_price(-200_311 + 200, 18, 6).unwrap();
+        let twap = tick_price(-200_311, 18, 6).unwrap();
+        assert_eq!(
+            spot_deviation_bps(spot, twap).unwrap().round(),
+            Decimal::from(202)
+        );
+        assert!(check_spot(spot, twap, &config).is_err());
+    }
+
+    // Reads the pools `scripts/deploy_dex_fixtures.ts` deploys on a local dev chain, see the README
+    #[tokio::test]
+    #[ignore]
+    async fn reads_pools_on_a_dev_chain() {
+        let rpc_url = std::env::var("RPC_URL").unwrap_or("http://127.0.0.1:8545".to_string());
+        let client = Arc::new(Provider::<Http>::try_from(rpc_url).unwrap());
+        let config = DexConfig {
+            pools: vec![
+                PoolConfig {
+                    kind: PoolKind::UniswapV2,
+                    address: std::env::var("DEX_V2_FIXTURE").unwrap().parse().unwrap(),
+                    ..pool(BaseToken::Token0)
+                },
+                PoolConfig {
+                    kind: PoolKind::UniswapV3,
+                    address: std::env::var("DEX_V3_FIXTURE").unwrap().parse().unwrap(),
+                    ..pool(BaseToken::Token0)
+                },
+            ],
+            ..Default::default()
+        };
+        let quotes = read_dex_quotes(client, &config).await;
+        assert_eq!(quotes.len(), 2);
+        for quote in quotes {
+            assert!((quote.price.to_f64().unwrap() - 2000.0).abs() < 1.0);
+            assert!(quote.weight > Decimal::ZERO && quote.weight <= Decimal::ONE);
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use ethers::types::I256;
+use rust_decimal::prelude::*;
+use rust_decimal::Decimal;
+use std::fmt;
+
+// Decimals every feed was published with before they became configurable
+pub const DEFAULT_DECIMALS: u8 = 18;
+
+// 10^76 is the largest power of ten an I256 can hold
+pub const MAX_DECIMALS: u8 = 76;
+
+// The largest scale a Decimal can hold
+const MAX_DECIMAL_SCALE: u32 = 28;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConversionError {
+    UnsupportedDecimals(u8),
+    // the value doesn't fit in an I256 at the requested decimals
+    Overflow(Decimal, u8),
+    // a non-zero value rounds to zero at the requested decimals
+    Underflow(Decimal, u8),
+    // the integer part of the I256 doesn't fit in a Decimal
+    OutOfRange(I256, u8),
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::UnsupportedDecimals(decimals) => write!(
+                f,
+                "{} decimals is unsupported (max {})",
+                decimals, MAX_DECIMALS
+            ),
+            ConversionError::Overflow(value, decimals) => {
+                write!(f, "{} overflows an int256 at {} decimals", value, decimals)
+            }
+            ConversionError::Underflow(value, decimals) => {
+                write!(f, "{} rounds to zero at {} decimals", value, decimals)
+            }
+            ConversionError::OutOfRange(value, decimals) => write!(
+                f,
+                "{} at {} decimals is out of range for a decimal",
+                value, decimals
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+// A fixed point value as it's stored on-chain: `value / 10^decimals`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedPoint {
+    pub value: I256,
+    pub decimals: u8,
+}
+
+impl FixedPoint {
+    pub fn new(value: I256, decimals: u8) -> Self {
+        FixedPoint { value, decimals }
+    }
+
+    // Round `value` to `decimals` places (half away from zero) and scale it up to an I256
+    pub fn from_decimal(value: Decimal, decimals: u8) -> Result<Self, ConversionError> {
+        if decimals > MAX_DECIMALS {
+            return Err(ConversionError::UnsupportedDecimals(decimals));
+        }
+        let rounded = value.round_dp_with_strategy(
+            (decimals as u32).min(MAX_DECIMAL_SCALE),
+            RoundingStrategy::MidpointAwayFromZero,
+        );
+        if rounded.is_zero() && !value.is_zero() {
+            return Err(ConversionError::Underflow(value, decimals));
+        }
+
+        // after rounding the scale is at most `decimals`, so this only ever scales up
+        let shift = decimals as u32 - rounded.scale();
+        I256::from(rounded.mantissa())
+            .checked_mul(I256::exp10(shift as usize))
+            .map(|value| FixedPoint { value, decimals })
+            .ok_or(ConversionError::Overflow(value, decimals))
+    }
+
+    // Convert back to a Decimal. Digits beyond Decimal's 96 bit mantissa are rounded away, but
+    // the integer part is never truncated - that's an error instead.
+    pub fn to_decimal(&self) -> Result<Decimal, ConversionError> {
+        if self.decimals > MAX_DECIMALS {
+            return Err(ConversionError::UnsupportedDecimals(self.decimals));
+        }
+        // find the fewest trailing digits we need to drop for the value to fit, then drop them
+        // with a single rounding step so we never double round
+        let decimals = self.decimals as u32;
+        let mut drop = decimals.saturating_sub(MAX_DECIMAL_SCALE);
+        loop {
+            let mantissa = round_div(self.value, I256::exp10(drop as usize));
+            if let Ok(m) = i128::try_from(mantissa) {
+                if let Ok(d) = Decimal::try_from_i128_with_scale(m, decimals - drop) {
+                    return Ok(d);
+                }
+            }
+            if drop == decimals {
+                return Err(ConversionError::OutOfRange(self.value, self.decimals));
+            }
+            drop += 1;
+        }
+    }
+}
+
+// Integer division rounding half away from zero
+fn round_div(value: I256, divisor: I256) -> I256 {
+    let (quotient, remainder) = (value / divisor, value % divisor);
+    if remainder.unsigned_abs() * 2 >= divisor.unsigned_abs() {
+        quotient + I256::from(value.signum().as_i32())
+    } else {
+        quotient
+    }
+}
+
+impl fmt::Display for FixedPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({} decimals)", self.value, self.decimals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_common_prices() {
+        for (price, decimals) in [("26123.45", 18), ("0.00000812", 18), ("1.0001", 8)] {
+            let price = Decimal::from_str(price).unwrap();
+            let fixed = FixedPoint::from_decimal(price, decimals).unwrap();
+            assert_eq!(fixed.to_decimal().unwrap(), price);
+        }
+    }
+
+    #[test]
+    fn large_values_keep_full_precision_at_18_decimals() {
+        // 1e12 at 18 decimals needs a 1e30 mantissa, more than a Decimal can rescale to
+        let price = Decimal::from_str("1000000000000.5").unwrap();
+        let fixed = FixedPoint::from_decimal(price, 18).unwrap();
+        assert_eq!(
+            fixed.value,
+            I256::from_dec_str("1000000000000500000000000000000").unwrap()
+        );
+        assert_eq!(fixed.to_decimal().unwrap(), price);
+    }
+
+    #[test]
+    fn rounds_to_configured_decimals() {
+        let price = Decimal::from_str("1.23456789").unwrap();
+        let fixed = FixedPoint::from_decimal(price, 4).unwrap();
+        assert_eq!(fixed.value, I256::from(12346));
+
+        let price = Decimal::from_str("-1.23455").unwrap();
+        let fixed = FixedPoint::from_decimal(price, 4).unwrap();
+        assert_eq!(fixed.value, I256::from(-12346));
+    }
+
+    #[test]
+    fn rejects_unrepresentable_values() {
+        let tiny = Decimal::from_str("0.00000001").unwrap();
+        assert_eq!(
+            FixedPoint::from_decimal(tiny, 6),
+            Err(ConversionError::Underflow(tiny, 6))
+        );
+        assert_eq!(
+            FixedPoint::from_decimal(Decimal::ONE, MAX_DECIMALS + 1),
+            Err(ConversionError::UnsupportedDecimals(MAX_DECIMALS + 1))
+        );
+        let huge = FixedPoint::new(I256::MAX, 0);
+        assert!(matches!(
+            huge.to_decimal(),
+            Err(ConversionError::OutOfRange(_, 0))
+        ));
+        assert_eq!(
+            FixedPoint::from_decimal(Decimal::MAX, MAX_DECIMALS),
+            Err(ConversionError::Overflow(Decimal::MAX, MAX_DECIMALS))
+        );
+    }
+
+    #[test]
+    fn rounds_away_digits_beyond_decimal_precision() {
+        // 36 decimals is more than a Decimal can hold, so the tail gets rounded away
+        let fixed = FixedPoint::new(
+            I256::from_dec_str("1234567890123456789012345678901234567").unwrap(),
+            36,
+        );
+        assert_eq!(
+            fixed.to_decimal().unwrap(),
+            Decimal::from_str("1.2345678901234567890123456789").unwrap()
+        );
+    }
+}
@@ -0,0 +1,425 @@
+use crate::*;
+
+use ethers::{
+    providers::Middleware,
+    types::{BlockNumber, U256},
+};
+use rust_decimal::prelude::ToPrimitive;
+
+const GWEI: u64 = 1_000_000_000;
+
+#[derive(Debug)]
+pub enum GasError {
+    Provider(String),
+}
+
+impl fmt::Display for GasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GasError::Provider(err) => write!(f, "failed to read the gas price: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for GasError {}
+
+// What a transaction would pay per unit of gas right now
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasPrice {
+    // None on chains without EIP-1559
+    pub base_fee: Option<U256>,
+    // the tip on EIP-1559 chains, the whole gas price elsewhere
+    pub priority_fee: U256,
+}
+
+impl GasPrice {
+    pub fn total(&self) -> U256 {
+        self.base_fee.unwrap_or_default() + self.priority_fee
+    }
+}
+
+impl fmt::Display for GasPrice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.base_fee {
+            Some(base_fee) => write!(
+                f,
+                "{} wei (base fee {}, priority fee {})",
+                self.total(),
+                base_fee,
+                self.priority_fee
+            ),
+            None => write!(f, "{} wei", self.total()),
+        }
+    }
+}
+
+// Read the latest block's base fee and the suggested priority fee, or the legacy gas price on
+// chains whose blocks have no base fee
+pub async fn read_gas_price<M: Middleware>(client: &M) -> Result<GasPrice, GasError> {
+    let block = client
+        .get_block(BlockNumber::Latest)
+        .await
+        .map_err(|e| GasError::Provider(e.to_string()))?;
+    if let Some(base_fee) = block.and_then(|x| x.base_fee_per_gas) {
+        let priority_fee: U256 = client
+            .provider()
+            .request("eth_maxPriorityFeePerGas", ())
+            .await
+            .map_err(|e| GasError::Provider(e.to_string()))?;
+        return Ok(GasPrice {
+            base_fee: Some(base_fee),
+            priority_fee,
+        });
+    }
+    let gas_price = client
+        .get_gas_price()
+        .await
+        .map_err(|e| GasError::Provider(e.to_string()))?;
+    Ok(GasPrice {
+        base_fee: None,
+        priority_fee: gas_price,
+    })
+}
+
+// The gas price above which only urgent updates are sent. MAX_GAS_PRICE_GWEI overrides the
+// configured ceiling, None means there's no ceiling.
+pub fn gas_price_ceiling(config: &OracleConfig) -> Option<U256> {
+    let gwei = match std::env::var("MAX_GAS_PRICE_GWEI") {
+        Ok(value) if !value.trim().is_empty() => value.trim().parse::<u64>().ok(),
+        _ => config.max_gas_price_gwei,
+    }?;
+    Some(U256::from(gwei) * U256::from(GWEI))
+}
+
+// Why an update is sent even though the network is congested
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Urgency {
+    // the on-chain value is older than the feed's heartbeat
+    Heartbeat,
+    // the value moved by at least the feed's urgent deviation
+    Deviation,
+    // the on-chain value is in the wrong scale or can't be read
+    Broken,
+}
+
+// Whether an update can't wait for gas to get cheaper. New feeds can, registering them is the
+// most expensive thing a run does.
+pub fn urgency(
+    name: &str,
+    reason: &UpdateReason,
+    updated_at: Option<u64>,
+    config: &OracleConfig,
+    now: u64,
+) -> Option<Urgency> {
+    let feed = config.feed(name);
+    if let Some(updated_at) = updated_at {
+        if now.saturating_sub(updated_at) >= feed.heartbeat {
+            return Some(Urgency::Heartbeat);
+        }
+    }
+    match reason {
+        UpdateReason::NewFeed => None,
+        UpdateReason::DecimalsChanged(_) | UpdateReason::Uncomparable(_) => Some(Urgency::Broken),
+        UpdateReason::Deviation(ratio) => {
+            let urgent = Decimal::new(feed.urgent_deviation_bps as i64, 4);
+            match deviation_of(*ratio) >= urgent {
+                true => Some(Urgency::Deviation),
+                false => None,
+            }
+        }
+    }
+}
+
+// An update's worth next to what it adds to the run's cost
+struct Merit {
+    urgent: bool,
+    // how close the move is to the feed's urgent deviation, in bps of it. Urgent updates and new
+    // feeds are worth all of it.
+    value_bps: u64,
+    // the update's cost per unit of its execution gas, L1 data included
+    price: U256,
+}
+
+fn merit(
+    selection: &FeedSelection,
+    update: &([u8; 32], FixedPoint, UpdateReason),
+    updated_at: &HashMap<[u8; 32], u64>,
+    config: &OracleConfig,
+    cost: Option<&CostModel>,
+    now: u64,
+) -> Merit {
+    let (key, _, reason) = update;
+    let name = feed_name_to_string(key);
+    let urgent = urgency(&name, reason, updated_at.get(key).copied(), config, now).is_some();
+    let value = match reason {
+        // urgent moves are at or past the urgent deviation, so it isn't zero here
+        UpdateReason::Deviation(ratio) if !urgent => {
+            let urgent_deviation = Decimal::new(config.feed(&name).urgent_deviation_bps as i64, 4);
+            (deviation_of(*ratio) / urgent_deviation).min(Decimal::ONE)
+        }
+        _ => Decimal::ONE,
+    };
+    let price = match cost {
+        Some(cost) => cost.effective_gas_price(RunSize::of_update(selection, key)),
+        None => U256::one(),
+    };
+    Merit {
+        urgent,
+        value_bps: (value * Decimal::from(10_000)).to_u64().unwrap_or(0),
+        price,
+    }
+}
+
+// Rank the updates so capping the selection only cuts the least worthwhile: the ones that can't
+// wait first, then the rest by their value per unit of marginal cost, L1 data included. Updates
+// worth the same keep their order.
+pub fn rank_updates(
+    selection: &mut FeedSelection,
+    updated_at: &HashMap<[u8; 32], u64>,
+    config: &OracleConfig,
+    cost: Option<&CostModel>,
+    now: u64,
+) {
+    let mut ranked: Vec<_> = std::mem::take(&mut selection.updates)
+        .into_iter()
+        .map(|x| (merit(selection, &x, updated_at, config, cost, now), x))
+        .collect();
+    ranked.sort_by(|(a, _), (b, _)| {
+        b.urgent.cmp(&a.urgent).then_with(|| {
+            let a_per_cost = U256::from(a.value_bps).saturating_mul(b.price);
+            let b_per_cost = U256::from(b.value_bps).saturating_mul(a.price);
+            b_per_cost.cmp(&a_per_cost)
+        })
+    });
+    selection.updates = ranked.into_iter().map(|(_, x)| x).collect();
+}
+
+// Once the run's gas price, L1 data included, is past the ceiling, drop the updates that aren't
+// worth their cost, returning their names. Under the ceiling everything is sent. Past it urgent
+// updates are always sent, and any other update is sent while its marginal cost per unit of gas is
+// under the ceiling scaled by its value, so small moves and new feeds wait for cheaper gas. Feeds
+// that get no samples are still marked failed, a stale value is worse than the gas.
+pub fn trim_updates(
+    selection: &mut FeedSelection,
+    updated_at: &HashMap<[u8; 32], u64>,
+    config: &OracleConfig,
+    cost: &CostModel,
+    ceiling: U256,
+    now: u64,
+) -> Vec<[u8; 32]> {
+    let mut deferred = vec![];
+    if cost.effective_gas_price(RunSize::of(selection)) <= ceiling {
+        return deferred;
+    }
+    let updates = std::mem::take(&mut selection.updates);
+    for update in updates {
+        let merit = merit(selection, &update, updated_at, config, Some(cost), now);
+        let allowed = ceiling * U256::from(merit.value_bps) / U256::from(10_000);
+        match merit.urgent || merit.price <= allowed {
+            true => selection.updates.push(update),
+            false => deferred.push(update.0),
+        }
+    }
+    selection
+        .decimals_changes
+        .retain(|(key, _)| !deferred.contains(key));
+    deferred
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::I256;
+
+    fn update(name: &str, reason: UpdateReason) -> ([u8; 32], FixedPoint, UpdateReason) {
+        (
+            feed_name_bytes(name),
+            FixedPoint::new(I256::from(1), 18),
+            reason,
+        )
+    }
+
+    fn op_stack(gas_price: u64, l1_fee_per_byte: u64) -> CostModel {
+        CostModel {
+            kind: ChainKind::OpStack,
+            gas_price: U256::from(gas_price),
+            l1_fee_per_byte: U256::from(l1_fee_per_byte),
+        }
+    }
+
+    fn names(selection: &FeedSelection) -> Vec<[u8; 32]> {
+        selection.updates.iter().map(|x| x.0).collect()
+    }
+
+    fn mixed_selection() -> FeedSelection {
+        FeedSelection {
+            updates: vec![
+                // 0.1% move, a tenth of the default urgent deviation
+                update("BTC/USD", UpdateReason::Deviation(Decimal::new(999, 3))),
+                // 0.6% move
+                update("AVAX/USD", UpdateReason::Deviation(Decimal::new(994, 3))),
+                // 5% move
+                update("ETH/USD", UpdateReason::Deviation(Decimal::new(95, 2))),
+                update("NEW/USD", UpdateReason::NewFeed),
+            ],
+            decimals_changes: vec![(feed_name_bytes("NEW/USD"), 8)],
+            missing_feeds: vec![feed_name_bytes("DOGE/USD")],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn trims_updates_only_past_the_ceiling() {
+        let config = OracleConfig::default();
+        let now = 10_000;
+        let ceiling = U256::from(10 * GWEI);
+
+        // under the ceiling every update goes out, however small
+        let mut selection = mixed_selection();
+        let cost = op_stack(5 * GWEI, 0);
+        let deferred = trim_updates(
+            &mut selection,
+            &HashMap::new(),
+            &config,
+            &cost,
+            ceiling,
+            now,
+        );
+        assert!(deferred.is_empty());
+        assert_eq!(selection.updates.len(), 4);
+        assert_eq!(selection.decimals_changes.len(), 1);
+
+        // the same gas price with an L1 data fee past the ceiling leaves only the urgent move
+        let mut selection = mixed_selection();
+        let cost = op_stack(5 * GWEI, 5_500 * GWEI);
+        let deferred = trim_updates(
+            &mut selection,
+            &HashMap::new(),
+            &config,
+            &cost,
+            ceiling,
+            now,
+        );
+        assert_eq!(
+            deferred,
+            vec![
+                feed_name_bytes("BTC/USD"),
+                feed_name_bytes("AVAX/USD"),
+                feed_name_bytes("NEW/USD")
+            ]
+        );
+        assert_eq!(names(&selection), vec![feed_name_bytes("ETH/USD")]);
+        assert!(selection.decimals_changes.is_empty());
+        assert_eq!(selection.missing_feeds, vec![feed_name_bytes("DOGE/USD")]);
+    }
+
+    #[test]
+    fn sends_stale_feeds_whatever_the_cost() {
+        let config = OracleConfig::default();
+        let now = 10_000;
+        let mut selection = FeedSelection {
+            updates: vec![
+                update("BTC/USD", UpdateReason::Deviation(Decimal::new(999, 3))),
+                // small move, but the on-chain value is past its heartbeat
+                update("SOL/USD", UpdateReason::Deviation(Decimal::new(999, 3))),
+            ],
+            ..Default::default()
+        };
+        let updated_at = HashMap::from([
+            (feed_name_bytes("BTC/USD"), now - 60),
+            (feed_name_bytes("SOL/USD"), now - config.default.heartbeat),
+        ]);
+        let cost = op_stack(100 * GWEI, 0);
+        let deferred = trim_updates(
+            &mut selection,
+            &updated_at,
+            &config,
+            &cost,
+            U256::from(GWEI),
+            now,
+        );
+        assert_eq!(deferred, vec![feed_name_bytes("BTC/USD")]);
+        assert_eq!(names(&selection), vec![feed_name_bytes("SOL/USD")]);
+    }
+
+    #[test]
+    fn ranks_updates_by_value_per_cost() {
+        let config = OracleConfig::default();
+        let now = 10_000;
+        let mut selection = mixed_selection();
+        // the same move with a decimals change costs more to send
+        selection.updates.insert(
+            0,
+            update("DOT/USD", UpdateReason::Deviation(Decimal::new(994, 3))),
+        );
+        selection
+            .decimals_changes
+            .push((feed_name_bytes("DOT/USD"), 18));
+        let cost = op_stack(5 * GWEI, 500 * GWEI);
+
+        rank_updates(&mut selection, &HashMap::new(), &config, Some(&cost), now);
+        assert_eq!(
+            names(&selection),
+            vec![
+                feed_name_bytes("ETH/USD"),
+                feed_name_bytes("NEW/USD"),
+                feed_name_bytes("AVAX/USD"),
+                feed_name_bytes("DOT/USD"),
+                feed_name_bytes("BTC/USD")
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_urgent_updates_when_capping() {
+        let config = OracleConfig::default();
+        let now = 10_000;
+        let mut updates: Vec<_> = (0..MAX_UPDATES)
+            .map(|i| {
+                update(
+                    &format!("FEED{}/USD", i),
+                    UpdateReason::Deviation(Decimal::new(999, 3)),
+                )
+            })
+            .collect();
+        // the only urgent update comes last, past the cap
+        updates.push(update(
+            "ETH/USD",
+            UpdateReason::Deviation(Decimal::new(95, 2)),
+        ));
+        let mut selection = FeedSelection {
+            updates,
+            ..Default::default()
+        };
+
+        // without the chain's prices, updates worth the same keep their order
+        rank_updates(&mut selection, &HashMap::new(), &config, None, now);
+        assert_eq!(selection.updates[0].0, feed_name_bytes("ETH/USD"));
+        assert_eq!(selection.updates[1].0, feed_name_bytes("FEED0/USD"));
+        selection.truncate(selection.limit());
+        let cost = op_stack(100 * GWEI, 0);
+        trim_updates(
+            &mut selection,
+            &HashMap::new(),
+            &config,
+            &cost,
+            U256::from(GWEI),
+            now,
+        );
+        assert_eq!(names(&selection), vec![feed_name_bytes("ETH/USD")]);
+    }
+
+    #[test]
+    fn sums_the_base_and_priority_fee() {
+        let price = GasPrice {
+            base_fee: Some(U256::from(30 * GWEI)),
+            priority_fee: U256::from(2 * GWEI),
+        };
+        assert_eq!(price.total(), U256::from(32 * GWEI));
+        let legacy = GasPrice {
+            base_fee: None,
+            priority_fee: U256::from(5 * GWEI),
+        };
+        assert_eq!(legacy.total(), U256::from(5 * GWEI));
+    }
+}
@@ -26,6 +26,7 @@ impl Into<NormalizedTicker> for GateIoPair {
         let book = self;
         let mut res = NormalizedTicker::default();
         res.price = book.last;
+        res.volume = Some(book.quote_volume);
         res
     }
 }
@@ -0,0 +1,136 @@
+use crate::*;
+
+use serde::Deserialize;
+
+// Venues that serve a broken snapshot, zero prices or a stale cache, skew every pair they list.
+// Before aggregating, each venue's prices are compared with a preliminary consensus of every
+// pair it shares with other venues, and venues that are off across the board are dropped.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HealthConfig {
+    // venues whose median error against the consensus is above this are excluded
+    pub max_venue_error_bps: u32,
+    // venues sharing fewer pairs than this with the consensus aren't judged
+    pub min_shared_pairs: usize,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            max_venue_error_bps: 500,
+            min_shared_pairs: 3,
+        }
+    }
+}
+
+// A venue dropped from this run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VenueExclusion {
+    pub venue: String,
+    // median relative error of its prices against the consensus, 0.01 is 1%
+    pub error: Decimal,
+    // pairs it shares with the consensus
+    pub pairs: usize,
+}
+
+impl fmt::Display for VenueExclusion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "venue {} excluded, its prices are a median {}% off the consensus across {} pairs",
+            self.venue,
+            (self.error * Decimal::ONE_HUNDRED).round_dp(2),
+            self.pairs
+        )
+    }
+}
+
+fn median_of(values: &mut [Decimal]) -> Decimal {
+    values.sort();
+    let mid = values.len() / 2;
+    match values.len() % 2 {
+        0 => (values[mid - 1] + values[mid]) / Decimal::from(2),
+        _ => values[mid],
+    }
+}
+
+// The venues whose prices are off the consensus across the pairs they share. Each pair's
+// consensus is the median of one price per venue, and pairs listed by too few venues for a
+// median don't count.
+pub fn unhealthy_venues(
+    samples: &HashMap<Pair, Vec<VenueSample>>,
+    config: &HealthConfig,
+) -> Vec<VenueExclusion> {
+    let mut errors = HashMap::<String, Vec<Decimal>>::new();
+    for pair_samples in samples.values() {
+        let venues = one_per_venue(pair_samples.clone());
+        if venues.len() <= MIN_SAMPLES {
+            continue;
+        }
+        let mut prices: Vec<Decimal> = venues.iter().map(|x| x.price).collect();
+        let consensus = median_of(&mut prices);
+        if consensus <= Decimal::ZERO {
+            continue;
+        }
+        for sample in venues {
+            let error = ((sample.price - consensus) / consensus).abs();
+            errors.entry(sample.venue).or_default().push(error);
+        }
+    }
+
+    let max_error = Decimal::new(config.max_venue_error_bps as i64, 4);
+    let mut exclusions: Vec<VenueExclusion> = errors
+        .into_iter()
+        .filter(|(_, errors)| errors.len() >= config.min_shared_pairs)
+        .map(|(venue, mut errors)| VenueExclusion {
+            venue,
+            pairs: errors.len(),
+            error: median_of(&mut errors),
+        })
+        .filter(|x| x.error > max_error)
+        .collect();
+    exclusions.sort_by(|a, b| a.venue.cmp(&b.venue));
+    exclusions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excludes_venues_that_are_off_across_pairs() {
+        let sample = |venue: &str, price: i64| VenueSample {
+            venue: venue.to_string(),
+            price: Decimal::from(price),
+            weight: Decimal::ONE,
+        };
+        let mut samples = HashMap::new();
+        for (pair, price) in [("BTC/USD", 30_000), ("ETH/USD", 2_000), ("SOL/USD", 20)] {
+            samples.insert(
+                Pair::from_string(pair.to_string()),
+                vec![
+                    sample("binance", price),
+                    sample("kraken", price + price / 1000),
+                    sample("okx", price - price / 1000),
+                    // serves zeros for every pair
+                    sample("broken", 0),
+                ],
+            );
+        }
+        // only lists one pair, and it's way off there
+        samples
+            .get_mut(&Pair::from_string("SOL/USD".to_string()))
+            .unwrap()
+            .push(sample("tiny", 40));
+
+        let exclusions = unhealthy_venues(&samples, &HealthConfig::default());
+        assert_eq!(
+            exclusions,
+            vec![VenueExclusion {
+                venue: "broken".to_string(),
+                error: Decimal::ONE,
+                pairs: 3,
+            }]
+        );
+    }
+}
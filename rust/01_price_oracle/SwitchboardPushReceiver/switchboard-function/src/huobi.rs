@@ -41,6 +41,8 @@ impl Into<NormalizedTicker> for HuobiTicker {
         let avg_price =
             (self.bid * self.bidSize + self.ask * ask_size_clone) / (self.bidSize + ask_size_clone);
         res.price = Decimal::from_f64(avg_price).unwrap();
+        // huobi's amount is in the base currency, vol in the quote currency
+        res.volume = Decimal::from_f64(self.vol);
         res
     }
 }
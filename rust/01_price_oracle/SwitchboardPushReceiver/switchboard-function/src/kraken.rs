@@ -72,6 +72,8 @@ impl Into<NormalizedTicker> for KrakenTickerInfo {
         let book = self;
         let mut res = NormalizedTicker::default();
         res.price = book.vwap[0];
+        // index 1 is the last 24 hours, index 0 only today
+        res.volume = Some(book.volume[1] * book.vwap[1]);
         res
     }
 }
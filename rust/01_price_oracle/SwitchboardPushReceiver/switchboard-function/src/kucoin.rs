@@ -37,6 +37,7 @@ impl Into<NormalizedTicker> for KucoinTicker {
             res.price = Decimal::from_str(&avg.to_string()).unwrap_or(book.last);
         }
         res.price = book.last;
+        res.volume = Some(book.volValue);
         res
     }
 }
@@ -22,26 +22,60 @@ pub mod poloniex;
 pub use poloniex::*;
 pub mod pair;
 pub use pair::*;
+pub mod target;
+pub use target::*;
+pub mod fixed;
+pub use fixed::*;
+pub mod config;
+pub use config::*;
+pub mod dry_run;
+pub use dry_run::*;
+pub mod runner;
+pub use runner::*;
+pub mod selection;
+pub use selection::*;
+pub mod aggregate;
+pub use aggregate::*;
+pub mod quote;
+pub use quote::*;
+pub mod gas;
+pub use gas::*;
+pub mod cost;
+pub use cost::*;
+pub mod twap;
+pub use twap::*;
+pub mod risk;
+pub use risk::*;
+pub mod derivatives;
+pub use derivatives::*;
+pub mod dex;
+pub use dex::*;
+pub mod reference;
+pub use reference::*;
+pub mod breaker;
+pub use breaker::*;
+pub mod health;
+pub use health::*;
 
 use rust_decimal::prelude::*;
 use rust_decimal::Decimal;
 use serde::Deserialize;
 use std::collections::HashMap;
 use switchboard_common;
-use switchboard_evm::sdk::EVMFunctionRunner;
 pub use switchboard_utils::reqwest;
 
 use ethers::{
-    prelude::{abigen, SignerMiddleware},
+    contract::EthCall,
+    prelude::SignerMiddleware,
     providers::{Http, Provider},
-    types::I256,
+    types::{I256, U256},
 };
 use rand;
-use rand::seq::SliceRandom;
 use rand::SeedableRng;
 use serde_json::Value;
 use std::hash::Hasher;
 
+use std::fmt;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
@@ -49,6 +83,8 @@ use std::time::{Duration, SystemTime};
 #[derive(Deserialize, Default, Clone, Debug)]
 pub struct NormalizedTicker {
     pub price: Decimal,
+    // traded in the last 24h, in the quote currency. None where the venue doesn't report it.
+    pub volume: Option<Decimal>,
 }
 #[allow(non_snake_case)]
 #[derive(Deserialize, Default, Clone, Debug)]
@@ -77,6 +113,36 @@ enum Sample {
     Okex(OkexTicker),
     Poloniex(PoloniexTicker),
     CoinbaseSpot(Decimal),
+    // read on-chain, weighted by the pool's liquidity
+    Dex(DexQuote),
+}
+
+impl Sample {
+    // how much the sample counts for in the median, an exchange's ticker counts for 1
+    fn weight(&self) -> Decimal {
+        match self {
+            Sample::Dex(quote) => quote.weight,
+            _ => Decimal::ONE,
+        }
+    }
+
+    // the venue the sample came from, each venue counts once towards a pair's quorum
+    fn venue(&self) -> String {
+        match self {
+            Sample::Binance(_) => "binance".to_string(),
+            Sample::Bitfinex(_) => "bitfinex".to_string(),
+            Sample::Bitstamp(_) => "bitstamp".to_string(),
+            Sample::Bittrex(_) => "bittrex".to_string(),
+            Sample::GateIo(_) => "gateio".to_string(),
+            Sample::Huobi(_) => "huobi".to_string(),
+            Sample::Kraken(_) => "kraken".to_string(),
+            Sample::Kucoin(_) => "kucoin".to_string(),
+            Sample::Okex(_) => "okx".to_string(),
+            Sample::Poloniex(_) => "poloniex".to_string(),
+            Sample::CoinbaseSpot(_) => "coinbase".to_string(),
+            Sample::Dex(quote) => quote.venue.clone(),
+        }
+    }
 }
 
 impl Into<NormalizedTicker> for Sample {
@@ -98,22 +164,38 @@ impl Into<NormalizedTicker> for Sample {
                 res.price = t;
                 res
             }
+            Sample::Dex(t) => NormalizedTicker {
+                price: t.price,
+                volume: None,
+            },
         }
     }
 }
 
-#[tokio::main(worker_threads = 12)]
-async fn main() {
-    // define the abi for the callback
-    // -- here it's just a function named "callback", expecting the feed names, values, and timestamps
-    // -- we also include a view function for getting all feeds
-    // running `npx hardhat typechain` will create artifacts for the contract
-    // this in particular is found at
-    // SwitchboardPushReceiver/artifacts/contracts/src/SwitchboardPushReceiver/Receiver/Receiver.sol/Receiver.json
+// define the abi for the callback
+// -- here it's just a function named "callback", expecting the feed names, values, and timestamps
+// -- we also include a view function for getting all feeds
+// running `npx hardhat typechain` will create artifacts for the contract
+// this in particular is found at
+// SwitchboardPushReceiver/artifacts/contracts/src/SwitchboardPushReceiver/Receiver/Receiver.sol/Receiver.json
+// -- the bindings get their own module, the abi's `Result` struct would shadow std's otherwise
+mod receiver_abi {
+    use ethers::prelude::abigen;
     abigen!(Receiver, "./src/abi/Receiver.json",);
+}
+use receiver_abi::{
+    CallbackCall, DecimalsCallbackCall, FailureCallbackCall, FailureReasonCallbackCall,
+    GetAllFeedsCall, Receiver,
+};
 
+#[tokio::main(worker_threads = 12)]
+async fn main() {
     // Generates a new enclave wallet, pulls in relevant environment variables
-    let function_runner = EVMFunctionRunner::new().unwrap();
+    // -- dry runs don't need the enclave, see src/dry_run.rs
+    let function_runner = Runner::new().unwrap();
+
+    // per-feed settings (decimals, ...) bundled with the function
+    let config = OracleConfig::load();
 
     // set the gas limit and expiration date
     // -- this is the maximum amount of gas that can be used for the transaction (and it's a lot)
@@ -125,16 +207,82 @@ async fn main() {
         + 64;
 
     // setup the provider + signer
-    let provider = Provider::<Http>::try_from("https://rpc.test.btcs.network").unwrap();
+    // -- RPC_URL overrides the default network, e.g. to point the function at a local dev chain
+    let rpc_url = std::env::var("RPC_URL").unwrap_or("https://rpc.test.btcs.network".to_string());
+    let provider = Provider::<Http>::try_from(rpc_url).unwrap();
     let client = Arc::new(
-        SignerMiddleware::new_with_provider_chain(provider, function_runner.enclave_wallet.clone())
+        SignerMiddleware::new_with_provider_chain(provider, function_runner.enclave_wallet())
             .await
             .unwrap(),
     );
 
-    let contract_address = env!("SWITCHBOARD_PUSH_ADDRESS")
-        .parse::<ethers::types::Address>()
-        .unwrap();
+    // resolve the receiver at runtime so one enclave build can serve any deployment
+    // -- a SWITCHBOARD_PUSH_ADDRESS set at build time is only used as a pinned default
+    let contract_address = resolve_receiver_address(
+        "SWITCHBOARD_PUSH_ADDRESS",
+        option_env!("SWITCHBOARD_PUSH_ADDRESS"),
+    )
+    .unwrap();
+
+    // make sure the receiver is deployed and exposes the calls we're about to make
+    verify_receiver(
+        client.clone(),
+        contract_address,
+        &[
+            CallbackCall::selector(),
+            FailureCallbackCall::selector(),
+            DecimalsCallbackCall::selector(),
+            GetAllFeedsCall::selector(),
+        ],
+    )
+    .await
+    .unwrap();
+
+    // price the configured DEX pools on this chain, they're samples next to the exchanges'
+    let dex_quotes = read_dex_quotes(client.clone(), &config.dex).await;
+
+    // fetch every venue once, quotes and feeds are priced from the same samples
+    // -- a venue serving a broken snapshot is dropped from every pair, volume included
+    let mut samples = fetch_samples(&dex_quotes).await;
+    exclude_unhealthy_venues(&mut samples, &config.health);
+
+    // serve pending quote requests in the same run as the feeds, with a bounded share of the gas
+    // -- receivers without the Quote facet leave the requests pending
+    let quoter = Quoter::new(contract_address, client.clone());
+    let quote_requests = FunctionRunner::params::<QuoteParams>(&function_runner);
+    let mut quote_plan = QuotePlan::default();
+    if !quote_requests.is_empty() {
+        match verify_receiver(
+            client.clone(),
+            contract_address,
+            &[
+                QuoteCallbackCall::selector(),
+                QuoteFailureCallbackCall::selector(),
+            ],
+        )
+        .await
+        {
+            Ok(()) => {
+                // aggregate just the requested pairs
+                let timestamp = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or(Duration::ZERO)
+                    .as_secs();
+                let aggregates = get_quote_data(&requested_pairs(&quote_requests), &samples);
+                quote_plan = plan_quotes(
+                    quote_requests,
+                    &aggregates,
+                    &config,
+                    timestamp,
+                    quote_gas_budget(gas_limit),
+                );
+                for (call_id, reason, detail) in &quote_plan.failed {
+                    println!("quote call {:?} refused ({}): {}", call_id, reason, detail);
+                }
+            }
+            Err(e) => println!("{}, leaving quote requests pending", e),
+        }
+    }
 
     let receiver_contract = Receiver::new(contract_address, client);
 
@@ -145,100 +293,286 @@ async fn main() {
     let feeds = all_feeds.unwrap_or(Vec::new());
 
     // take feed.feed_name and map it to feed.latest_result
-    let mut feed_map = HashMap::<[u8; 32], I256>::new();
+    let mut feed_map = HashMap::<[u8; 32], FixedPoint>::new();
+    let mut updated_at = HashMap::<[u8; 32], u64>::new();
     for feed in feeds {
-        feed_map.insert(feed.feed_name, feed.latest_result.value);
+        // feeds registered before decimals were configurable report 0 decimals
+        let decimals = if feed.decimals == 0 {
+            DEFAULT_DECIMALS
+        } else {
+            feed.decimals
+        };
+        feed_map.insert(
+            feed.feed_name,
+            FixedPoint::new(feed.latest_result.value, decimals),
+        );
+        updated_at.insert(feed.feed_name, feed.latest_result.updated_at.low_u64());
     }
 
     // get fresh feed data
-    let mut feed_updates = get_feed_data().await;
-
-    // check if we're still registering feeds (significantly more expensive in gas cost)
-    // -- if so, only use the first 20 elements of the feed_updates
-    // allow up to 1 registration alongside updates so we don't block updates for an entire run if a feed is added
-    let registering_feeds: bool = feed_map.len() < feed_updates.len() - 1;
-
-    // get list of feed names that weren't received in get_feed_data
-    let mut missing_feeds = Vec::<[u8; 32]>::new();
-    for key in feed_map.keys() {
-        if !feed_updates.contains_key(key) {
-            missing_feeds.push(*key);
+    // -- each source also counts the venues behind every value, for the circuit breaker
+    let (mut feed_updates, mut venues) = get_feed_data(&config, samples);
+
+    // TWAP feeds are published next to spot, under their own names
+    let (twaps, twap_venues) = get_twap_data(&config).await;
+    feed_updates.extend(twaps);
+    venues.extend(twap_venues);
+
+    // so are the volatility feeds, the volume feeds come with spot
+    let (volatilities, volatility_venues) = get_volatility_data(&config).await;
+    feed_updates.extend(volatilities);
+    venues.extend(volatility_venues);
+
+    // and the perpetuals' mark, index and funding feeds
+    let (derivatives, derivatives_venues) = get_derivatives_data(&config).await;
+    feed_updates.extend(derivatives);
+    venues.extend(derivatives_venues);
+
+    // don't push implausible jumps unless enough venues confirm them
+    let mut refusals = check_jumps(&feed_map, &mut feed_updates, &venues, &config);
+
+    // hold the values up against the configured third-party feeds before picking what to send
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    let references = read_references(receiver_contract.client(), &config.reference, now).await;
+    for check in check_references(&mut feed_updates, &references, &config.reference) {
+        println!("reference check {}", check);
+        if check.refused {
+            refusals.push((check.feed, FeedFailure::ReferenceDivergence));
         }
     }
 
-    // delete all entries with a diff less than 0.1
-    for (key, value) in feed_updates.clone() {
-        if feed_map.contains_key(&key) {
-            let diff = get_percentage_diff(*feed_map.get(&key).unwrap(), value);
-            // %0.01 diff should triger an update
-            if registering_feeds || diff < Decimal::from_str("0.1").unwrap() {
-                feed_updates.remove(&key);
-            }
+    // setup feeds for shuffling
+    // -- dry runs outside the enclave seed from the OS instead
+    let mut rng = match is_dry_run() {
+        true => rand::rngs::StdRng::from_entropy(),
+        false => {
+            let mut randomness = [0; 32];
+            switchboard_common::Gramine::read_rand(&mut randomness).unwrap();
+            rand::rngs::StdRng::from_seed(randomness)
         }
+    };
 
-    }
+    // pick the feeds to send this run
+    let mut selection = select_feed_updates(&feed_map, &feed_updates, &mut rng);
 
-    // get a vec of feed names and values remaining
-    let mut feed_names = Vec::<[u8; 32]>::new();
-    let mut feed_values = Vec::<I256>::new();
+    // price the run, on rollups the L1 data fee of the callbacks often outweighs their execution
+    // -- the fee is read per byte, so the whole selection prices any cut of it
+    let calldata: Vec<u8> =
+        feed_update_calls(&receiver_contract, &selection, expiration_time_seconds)
+            .iter()
+            .filter_map(|x| x.calldata())
+            .flat_map(|x| x.to_vec())
+            .collect();
+    let cost = CostModel::read(
+        receiver_contract.client(),
+        receiver_contract.address(),
+        calldata.into(),
+    )
+    .await;
+
+    // urgent updates go first and the rest by what they're worth against what they cost, so the
+    // caps below only cut the updates least worth sending this run
+    rank_updates(
+        &mut selection,
+        &updated_at,
+        &config,
+        cost.as_ref().ok(),
+        now,
+    );
+    selection.truncate(selection.limit());
+    selection.fail(&refusals);
 
-    // setup feeds for shuffling
-    let mut randomness = [0; 32];
-    switchboard_common::Gramine::read_rand(&mut randomness).unwrap();
-    let mut rng = rand::rngs::StdRng::from_seed(randomness);
-    let mut feed_updates: Vec<([u8; 32], I256)> = feed_updates.into_iter().collect();
-
-    // only shuffle feeds if we're at the stage where we're submitting results
-    if !registering_feeds {
-        feed_updates.shuffle(&mut rng);
+    // receivers deployed before failure reasons existed can still mark the feeds failed
+    if !selection.failures.is_empty() {
+        if let Err(e) = verify_receiver(
+            receiver_contract.client(),
+            contract_address,
+            &[FailureReasonCallbackCall::selector()],
+        )
+        .await
+        {
+            println!("{}, marking refused feeds failed without a reason", e);
+            let failed = selection.failures.drain(..).map(|x| x.0);
+            selection.missing_feeds.extend(failed);
+            selection.missing_feeds.sort();
+        }
     }
 
-
-    for (key, value) in feed_updates {
-        // only use the first 30 elements of the feed_updates
-        // -- this is to prevent the transaction from going over the gas limit
-        if feed_names.len() >= 20 && registering_feeds {
-            break;
-        }
-        if feed_names.len() >= 100 && !registering_feeds {
-            break;
+    // only send as many updates as fit in the gas the quotes left, L1 data included where it's
+    // charged as gas
+    // -- without the chain's prices, execution gas alone bounds the run
+    let quote_size = quote_plan.size();
+    let max_updates = match &cost {
+        Ok(cost) => cost.max_updates(
+            gas_limit.saturating_sub(quote_size.gas + cost.l1_gas(quote_size.bytes)),
+            &selection,
+        ),
+        Err(_) => CostModel::execution_only().max_updates(gas_limit.saturating_sub(quote_size.gas), &selection),
+    };
+    selection.truncate(max_updates);
+
+    // under a gas price ceiling, only send the updates worth what they add to the run's cost
+    if let Some(ceiling) = gas_price_ceiling(&config) {
+        match &cost {
+            Ok(cost) => {
+                let gas_price = cost.effective_gas_price(RunSize::of(&selection));
+                let deferred = trim_updates(&mut selection, &updated_at, &config, cost, ceiling, now);
+                // -- under the ceiling nothing is deferred and the run goes out as is
+                if !deferred.is_empty() {
+                    println!(
+                        "gas price {} wei is above the ceiling of {} wei, deferring {} update(s) not worth their cost",
+                        gas_price,
+                        ceiling,
+                        deferred.len()
+                    );
+                    if selection.updates.is_empty()
+                        && selection.missing_feeds.is_empty()
+                        && selection.failures.is_empty()
+                        && quote_plan.resolved_call_ids().is_empty()
+                    {
+                        println!("nothing worth sending, skipping this run");
+                        return;
+                    }
+                }
+            }
+            // better to pay for a run than to stop updating
+            Err(e) => println!("{}, sending every update", e),
         }
-        feed_names.push(key);
-        feed_values.push(value);
     }
 
+    // a dry run's runner prints the calls instead of emitting them
+    if is_dry_run() {
+        print_selection(&feed_map, &selection);
+        print_quotes(&quote_plan);
+    }
+
+    // Emit the result
+    emit_feed_updates(
+        &function_runner,
+        &receiver_contract,
+        &selection,
+        &quoter,
+        &quote_plan,
+        expiration_time_seconds,
+        gas_limit.into(),
+    )
+    .unwrap();
+}
+
+// Build the callbacks for the selected feeds and the planned quotes and emit them through the
+// runner, resolving only the quote calls the plan handled
+fn emit_feed_updates<R: FunctionRunner>(
+    runner: &R,
+    receiver_contract: &Receiver<Client>,
+    selection: &FeedSelection,
+    quoter: &Quoter<Client>,
+    quote_plan: &QuotePlan,
+    expiration_time_seconds: u64,
+    gas_limit: U256,
+) -> Result<(), RunnerError> {
+    let mut callbacks = feed_update_calls(receiver_contract, selection, expiration_time_seconds);
+    callbacks.extend(quote_calls(quoter, quote_plan));
+    runner.emit_resolve(
+        receiver_contract.address(),
+        expiration_time_seconds.into(),
+        gas_limit,
+        callbacks,
+        quote_plan.resolved_call_ids(),
+    )
+}
+
+// The callbacks that send the selected feeds
+fn feed_update_calls(
+    receiver_contract: &Receiver<Client>,
+    selection: &FeedSelection,
+    expiration_time_seconds: u64,
+) -> Vec<Callback> {
+    let feed_names: Vec<[u8; 32]> = selection.updates.iter().map(|x| x.0).collect();
+    let feed_values: Vec<I256> = selection.updates.iter().map(|x| x.1.value).collect();
+
     // send the callback to the contract
     let callback = receiver_contract.callback(
-        feed_names.clone(),
-        feed_values.clone(),
+        feed_names,
+        feed_values,
         expiration_time_seconds.into(),
     );
 
     // get the calls from the output results
     let mut callbacks = vec![callback];
 
+    // record the decimals of feeds that were just registered or reconfigured
+    if selection.decimals_changes.len() > 0 {
+        let callback_decimals = receiver_contract.decimals_callback(
+            selection.decimals_changes.iter().map(|x| x.0).collect(),
+            selection.decimals_changes.iter().map(|x| x.1).collect(),
+        );
+        callbacks.push(callback_decimals);
+    }
+
     // add the missing feeds to the callback to mark them as stale
-    if !registering_feeds && missing_feeds.len() > 0 {
+    if !selection.registering_feeds && selection.missing_feeds.len() > 0 {
         let callback_missing_feeds = receiver_contract.failure_callback(
-            missing_feeds.clone()
+            selection.missing_feeds.clone()
         );
         callbacks.push(callback_missing_feeds);
     }
 
+    // and the refused feeds, with the reason they weren't updated
+    if !selection.registering_feeds && !selection.failures.is_empty() {
+        let callback_failures = receiver_contract.failure_reason_callback(
+            selection.failures.iter().map(|x| x.0).collect(),
+            selection.failures.iter().map(|x| x.1.code()).collect(),
+        );
+        callbacks.push(callback_failures);
+    }
+    callbacks
+}
 
-    // Emit the result
-    function_runner
-        .emit(
-            contract_address,
-            expiration_time_seconds.try_into().unwrap(),
-            gas_limit.into(),
-            callbacks,
-        )
-        .unwrap();
+// Report each selected feed's on-chain and new value for dry runs
+fn print_selection(feed_map: &HashMap<[u8; 32], FixedPoint>, selection: &FeedSelection) {
+    println!(
+        "DRY RUN: {} feed update(s), registering feeds: {}",
+        selection.updates.len(),
+        selection.registering_feeds
+    );
+    for (key, value, reason) in &selection.updates {
+        let current = feed_map
+            .get(key)
+            .map(|x| match x.to_decimal() {
+                Ok(d) => d.to_string(),
+                Err(e) => e.to_string(),
+            })
+            .unwrap_or("none".to_string());
+        let new = match value.to_decimal() {
+            Ok(d) => d.to_string(),
+            Err(e) => e.to_string(),
+        };
+        println!(
+            "  {}: {} -> {} ({})",
+            feed_name_to_string(key),
+            current,
+            new,
+            reason
+        );
+    }
+    if !selection.registering_feeds {
+        for key in &selection.missing_feeds {
+            println!(
+                "  {}: marked failed (no samples this run)",
+                feed_name_to_string(key)
+            );
+        }
+        for (key, reason) in &selection.failures {
+            println!("  {}: marked failed ({})", feed_name_to_string(key), reason);
+        }
+    }
 }
 
-// Get all feed data from various exchanges and return a hashmap of feed names and medianized values
-async fn get_feed_data() -> HashMap<[u8; 32], I256> {
+// Get every pair's ticker from various exchanges, grouped by pair
+async fn fetch_samples(dex_quotes: &[DexQuote]) -> HashMap<Pair, Vec<Sample>> {
     use crate::Sample::*;
     let empty_vec: Vec<Sample> = Vec::new();
     let mut aggregates = HashMap::<Pair, Vec<Sample>>::new();
@@ -409,114 +743,199 @@ async fn get_feed_data() -> HashMap<[u8; 32], I256> {
         samples.push(Poloniex(v.clone()));
         aggregates.insert(symbol, samples.to_vec());
     }
-    // Only retain more than 2 samples
-    aggregates.retain(|k, v| v.len() > 2 && k.quote.contains("USD"));
 
-    let mut feed_map = HashMap::<[u8; 32], I256>::new();
+    for quote in dex_quotes {
+        aggregates
+            .entry(quote.pair.clone())
+            .or_default()
+            .push(Dex(quote.clone()));
+    }
+    aggregates
+}
 
-    // go through each pair and calculate the average
-    for (k, v) in &aggregates {
-        let _sum = 0.0;
+// Tag each sample with its venue and weight
+fn venue_samples(samples: &[Sample]) -> Vec<VenueSample> {
+    samples
+        .iter()
+        .map(|x| {
+            let normalized: NormalizedTicker = (*x).clone().into();
+            VenueSample {
+                venue: x.venue(),
+                price: normalized.price,
+                weight: x.weight(),
+            }
+        })
+        .collect()
+}
 
-        // get the median price
-        let mut prices: Vec<Decimal> = v
-            .iter()
-            .map(|x| {
-                let normalized: NormalizedTicker = (*x).clone().into();
-                normalized.price
-            })
-            .collect();
-        prices.sort_by(|a, b| a.partial_cmp(b).unwrap());
+// Drop the samples of venues whose prices are off the consensus across the pairs they share,
+// before they skew every pair they list
+fn exclude_unhealthy_venues(samples: &mut HashMap<Pair, Vec<Sample>>, config: &HealthConfig) {
+    let tagged: HashMap<Pair, Vec<VenueSample>> = samples
+        .iter()
+        .map(|(k, v)| (k.clone(), venue_samples(v)))
+        .collect();
+    let exclusions = unhealthy_venues(&tagged, config);
+    for exclusion in &exclusions {
+        println!("{}", exclusion);
+    }
+    for v in samples.values_mut() {
+        v.retain(|x| !exclusions.iter().any(|e| e.venue == x.venue()));
+    }
+}
 
-        let mut median: Decimal;
+// The weighted median of each venue's normalized price
+fn aggregate_samples(samples: &[Sample]) -> Option<Aggregate> {
+    let prices = one_per_venue(venue_samples(samples))
+        .into_iter()
+        .map(|x| (x.price, x.weight))
+        .collect();
+    aggregate_weighted(prices)
+}
 
-        // handle even and odd cases
-        if prices.len() % 2 == 0 {
-            let mid = prices.len() / 2;
-            median = (prices[mid] + prices[mid - 1]) / Decimal::from(2);
-        } else {
-            median = prices[prices.len() / 2];
-        }
+// Aggregate just the requested pairs. Unlike feeds, quotes aren't limited to USD pairs.
+fn get_quote_data(
+    pairs: &[Pair],
+    samples: &HashMap<Pair, Vec<Sample>>,
+) -> HashMap<Pair, Aggregate> {
+    pairs
+        .iter()
+        .filter_map(|pair| {
+            let aggregate = aggregate_samples(samples.get(pair)?)?;
+            println!("{} -> {} ({} samples)", pair_name(pair), aggregate.median, aggregate.samples);
+            Some((pair.clone(), aggregate))
+        })
+        .collect()
+}
 
-        // get pair name as string
-        let name = format!("{}/{}", k.base, k.quote);
+// Get all feed data from various exchanges and return a hashmap of feed names and medianized values,
+// along with how many venues each value was taken from
+fn get_feed_data(
+    config: &OracleConfig,
+    mut aggregates: HashMap<Pair, Vec<Sample>>,
+) -> (HashMap<[u8; 32], FixedPoint>, HashMap<[u8; 32], usize>) {
+    // sum up the venues' volume before pairs with too few samples for a price are dropped
+    let tickers: HashMap<Pair, Vec<(String, NormalizedTicker)>> = aggregates
+        .iter()
+        .map(|(k, v)| {
+            let tickers = v.iter().map(|x| (x.venue(), x.clone().into())).collect();
+            (k.clone(), tickers)
+        })
+        .collect();
+    let (volumes, volume_venues) = volume_feeds(&tickers, config);
 
-        // get mean
-        let sum: Decimal = prices.iter().sum();
-        let count = Decimal::from(prices.len() as i32);
-        let mean = sum / count;
+    // Only retain USD pairs listed by enough distinct venues
+    aggregates.retain(|k, v| {
+        let feed = config.feed(&pair_name(k));
+        let venues: Vec<String> = v.iter().map(|x| x.venue()).collect();
+        k.quote.contains("USD") && has_quorum(&venues, feed.min_venues, &feed.required_venues)
+    });
 
-        // get variance
-        let squared_deviations: Decimal = prices
-            .iter()
-            .map(|&x| (x - mean).powi(2))
-            .sum();
-        let variance = squared_deviations / count;
-
-        // get standard deviation
-        let std_dev = variance.sqrt().unwrap();
-
-        // filter out prices that are not within 1 std dev of the mean
-        let prices = if prices.len() > 3 {
-            prices
-                .iter()
-                .filter(|x| {
-                    let lower_bound = median - std_dev;
-                    let upper_bound = median + std_dev;
-                    let x_is_in_range = x > &&lower_bound && x < &&upper_bound;
-                    // for debugging:
-                    // if !x_is_in_range {
-                    //     // get index in prices
-                    //     println!("Feed Name {},  {} is not in range {} - {}", name, x, lower_bound, upper_bound);
-                    // }
-                    x_is_in_range
-                })
-                .map(|x| *x)
-                .collect()
-        } else {
-            prices
-        };
+    let mut feed_map = HashMap::<[u8; 32], FixedPoint>::new();
+    let mut venues = HashMap::<[u8; 32], usize>::new();
 
-        // recalculate median
-        if prices.len() % 2 == 0 {
-            let mid = prices.len() / 2;
-            median = (prices[mid] + prices[mid - 1]) / Decimal::from(2);
-        } else {
-            median = prices[prices.len() / 2];
-        }
+    // go through each pair and calculate the median
+    for (k, v) in &aggregates {
+        let aggregate = match aggregate_samples(v) {
+            Some(aggregate) => aggregate,
+            None => continue,
+        };
 
+        // get pair name as string
+        let name = format!("{}/{}", k.base, k.quote);
 
         // add to vectors
         let mut bytes32 = [0u8; 32];
         bytes32[..name.as_bytes().len()].copy_from_slice(name.as_bytes());
 
-        // get median with fixed decimals at 18 as I256
-        median.rescale(18);
-        let median = I256::from(median.mantissa());
-        println!("{} -> {}", name, median);
+        // get median as a fixed point value with the feed's configured decimals
+        let median = match FixedPoint::from_decimal(aggregate.median, config.feed(&name).decimals) {
+            Ok(median) => median,
+            Err(e) => {
+                println!("{} -> skipped, {}", name, e);
+                continue;
+            }
+        };
+        println!("{} -> {}", name, median.value);
 
         // add to map
         feed_map.insert(bytes32, median);
+        venues.insert(bytes32, aggregate.venues);
     }
     println!("{} feeds populated", aggregates.len());
+    feed_map.extend(volumes);
+    venues.extend(volume_venues);
 
     // return the medians and names
-    feed_map
-}
-
-fn get_percentage_diff(a: I256, b: I256) -> Decimal {
-    let a = Decimal::from(a.as_i128());
-    let b = Decimal::from(b.as_i128());
-    (Decimal::min(a, b) / Decimal::max(a, b)).abs()
+    (feed_map, venues)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ethers::types::Address;
 
     #[tokio::test]
     async fn test() {
-        let feed_map = get_feed_data().await;
+        let (feed_map, _) = get_feed_data(&OracleConfig::load(), fetch_samples(&[]).await);
         // println!("{:#?}", feed_map);
     }
+
+    #[test]
+    fn emits_updates_through_the_runner() {
+        let runner = RecordingRunner::new();
+        let receiver_contract = Receiver::new(Address::random(), runner.client());
+        let mut name = [0u8; 32];
+        name[..7].copy_from_slice(b"BTC/USD");
+        let selection = FeedSelection {
+            registering_feeds: false,
+            updates: vec![(
+                name,
+                FixedPoint::new(I256::from(26_000), 8),
+                UpdateReason::DecimalsChanged(18),
+            )],
+            decimals_changes: vec![(name, 8)],
+            missing_feeds: vec![[1u8; 32]],
+            failures: vec![([2u8; 32], FeedFailure::PriceJump)],
+        };
+        // quotes go out in the same transaction, only the calls they answer are resolved
+        let quoter = Quoter::new(receiver_contract.address(), runner.client());
+        let refused = Address::random();
+        let quote_plan = QuotePlan {
+            failed: vec![(refused, QuoteFailure::UnknownPair, String::new())],
+            deferred: vec![Address::random()],
+            ..Default::default()
+        };
+
+        emit_feed_updates(
+            &runner,
+            &receiver_contract,
+            &selection,
+            &quoter,
+            &quote_plan,
+            1_000,
+            5_500_000.into(),
+        )
+        .unwrap();
+
+        let emitted = runner.emitted.borrow();
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(emitted[0].to, receiver_contract.address());
+        assert_eq!(emitted[0].call_ids, Some(vec![refused]));
+        let selectors: Vec<[u8; 4]> = emitted[0]
+            .calls
+            .iter()
+            .map(|x| x.calldata().unwrap()[..4].try_into().unwrap())
+            .collect();
+        assert_eq!(
+            selectors,
+            vec![
+                CallbackCall::selector(),
+                DecimalsCallbackCall::selector(),
+                FailureCallbackCall::selector(),
+                FailureReasonCallbackCall::selector(),
+                QuoteFailureCallbackCall::selector()
+            ]
+        );
+    }
 }
@@ -33,6 +33,8 @@ impl Into<NormalizedTicker> for OkexTicker {
         let book = self;
         let mut res = NormalizedTicker::default();
         res.price = book.last;
+        // for spot, volCcy24h is in the quote currency
+        res.volume = Some(book.volCcy24h);
         res
     }
 }
@@ -26,6 +26,8 @@ impl Into<NormalizedTicker> for PoloniexTicker {
         let book = self;
         let mut res = NormalizedTicker::default();
         res.price = book.last;
+        // quoteVolume is in the traded coin, priced at the last trade for the quote currency
+        res.volume = Some(book.quoteVolume * book.last);
         res
     }
 }
@@ -0,0 +1,430 @@
+use crate::*;
+
+use ethers::prelude::{abigen, EthAbiCodec, EthAbiType};
+use ethers::types::Address;
+
+// the Quote facet's callbacks, it's cut into the same diamond as the Receiver
+// -- "quoteCallback" expects the request's call id, price, confidence, decimals and data timestamp
+// -- "quoteFailureCallback" refuses a call with one of the QuoteFailure codes
+abigen!(
+    Quoter,
+    r#"[
+        function quoteCallback(address,int256,uint256,uint8,uint256)
+        function quoteFailureCallback(address,uint8)
+    ]"#,
+);
+
+// Rough gas of each quote callback. A fill writes the price, confidence, decimals, timestamp and
+// filled flag to fresh slots, a refusal only marks the request failed.
+pub const QUOTE_GAS: u64 = 120_000;
+pub const QUOTE_FAILURE_GAS: u64 = 30_000;
+// calldata of each quote callback, its selector and up to five words
+pub const QUOTE_BYTES: u64 = 164;
+
+// Quotes are served in the same run as the feed updates. Anyone can call the function, so quotes
+// get at most this share of the gas limit and the feeds always keep the rest.
+pub const QUOTE_GAS_SHARE_PERCENT: u64 = 25;
+
+// A quote takes a while to land on-chain after its samples are fetched. Requests asking for
+// fresher data than this can't be met, so they're refused up front.
+pub const MIN_QUOTE_MAX_AGE: u64 = 30;
+
+// Params of a quote request, as the Quote facet encodes them
+#[derive(Debug, Clone, EthAbiType, EthAbiCodec)]
+pub struct QuoteParams {
+    pub request_id: U256,
+    // e.g. "BTC/USD", any of the separators Pair understands work
+    pub pair: String,
+    // the oldest data the requester accepts when the quote lands, in seconds
+    pub max_age: U256,
+}
+
+// Why a quote request was refused. The codes match QuoteLib.FailureReason in the Quote facet, 0
+// is reserved there for requests that haven't failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuoteFailure {
+    InvalidParams,
+    // too few exchanges list the pair
+    UnknownPair,
+    // the requested freshness can't be met
+    Stale,
+}
+
+impl QuoteFailure {
+    pub fn code(&self) -> u8 {
+        match self {
+            QuoteFailure::InvalidParams => 1,
+            QuoteFailure::UnknownPair => 2,
+            QuoteFailure::Stale => 3,
+        }
+    }
+}
+
+impl fmt::Display for QuoteFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuoteFailure::InvalidParams => write!(f, "invalid params"),
+            QuoteFailure::UnknownPair => write!(f, "unknown pair"),
+            QuoteFailure::Stale => write!(f, "stale"),
+        }
+    }
+}
+
+// A pair's price as of `timestamp`, with the spread of the samples it was taken from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quote {
+    pub price: FixedPoint,
+    // one standard deviation of the samples, at the price's decimals
+    pub confidence: FixedPoint,
+    pub timestamp: u64,
+}
+
+// What a run does with each pending quote request
+#[derive(Debug, Clone, Default)]
+pub struct QuotePlan {
+    // oldest request first
+    pub quotes: Vec<(QuoteParams, Address, Quote)>,
+    pub failed: Vec<(Address, QuoteFailure, String)>,
+    // left pending for a later run
+    pub deferred: Vec<Address>,
+}
+
+impl QuotePlan {
+    // The execution gas and calldata of the plan's callbacks
+    pub fn size(&self) -> RunSize {
+        let quotes = self.quotes.len() as u64;
+        let failed = self.failed.len() as u64;
+        RunSize {
+            gas: quotes * QUOTE_GAS + failed * QUOTE_FAILURE_GAS,
+            bytes: (quotes + failed) * QUOTE_BYTES,
+        }
+    }
+
+    // the call ids this run resolves
+    pub fn resolved_call_ids(&self) -> Vec<Address> {
+        self.quotes
+            .iter()
+            .map(|(_, call_id, _)| *call_id)
+            .chain(self.failed.iter().map(|(call_id, _, _)| *call_id))
+            .collect()
+    }
+}
+
+// Name a pair the way feeds are named, e.g. "BTC/USD"
+pub fn pair_name(pair: &Pair) -> String {
+    format!("{}/{}", pair.base, pair.quote)
+}
+
+// The gas a run with this gas limit spends on quotes at most
+pub fn quote_gas_budget(gas_limit: u64) -> u64 {
+    gas_limit * QUOTE_GAS_SHARE_PERCENT / 100
+}
+
+// The pairs this run's requests ask for
+pub fn requested_pairs(params: &[(Result<QuoteParams, RunnerError>, Address)]) -> Vec<Pair> {
+    let mut pairs: Vec<Pair> = vec![];
+    for (param, _) in params {
+        if let Ok(param) = param {
+            let pair = Pair::from(param.pair.as_str());
+            if !pairs.contains(&pair) {
+                pairs.push(pair);
+            }
+        }
+    }
+    pairs
+}
+
+fn quote_pair(
+    param: &QuoteParams,
+    aggregates: &HashMap<Pair, Aggregate>,
+    config: &OracleConfig,
+    timestamp: u64,
+) -> Result<Quote, (QuoteFailure, String)> {
+    if param.max_age < U256::from(MIN_QUOTE_MAX_AGE) {
+        return Err((
+            QuoteFailure::Stale,
+            format!(
+                "max age {}s is under the minimum of {}s",
+                param.max_age, MIN_QUOTE_MAX_AGE
+            ),
+        ));
+    }
+    let pair = Pair::from(param.pair.as_str());
+    let aggregate = aggregates.get(&pair).ok_or((
+        QuoteFailure::UnknownPair,
+        format!("not enough samples for {}", param.pair),
+    ))?;
+
+    let decimals = config.feed(&pair_name(&pair)).decimals;
+    let price = FixedPoint::from_decimal(aggregate.median, decimals)
+        .map_err(|e| (QuoteFailure::UnknownPair, e.to_string()))?;
+    // a spread too small to show at these decimals is no spread at all
+    let confidence = match FixedPoint::from_decimal(aggregate.std_dev, decimals) {
+        Err(ConversionError::Underflow(..)) => FixedPoint::new(I256::zero(), decimals),
+        x => x.map_err(|e| (QuoteFailure::UnknownPair, e.to_string()))?,
+    };
+    Ok(Quote {
+        price,
+        confidence,
+        timestamp,
+    })
+}
+
+// Quote each pending request from this run's aggregates, oldest request first, spending at most
+// `gas_budget` on the callbacks. Requests for pairs without enough samples are refused, requests
+// past the budget wait for the next run.
+pub fn plan_quotes(
+    params: Vec<(Result<QuoteParams, RunnerError>, Address)>,
+    aggregates: &HashMap<Pair, Aggregate>,
+    config: &OracleConfig,
+    timestamp: u64,
+    gas_budget: u64,
+) -> QuotePlan {
+    let mut plan = QuotePlan::default();
+    let mut requests = vec![];
+    let mut invalid = vec![];
+    for (param, call_id) in params {
+        match param {
+            Ok(param) => requests.push((param, call_id)),
+            Err(e) => invalid.push((
+                call_id,
+                QuoteFailure::InvalidParams,
+                format!("failed to decode params, {}", e),
+            )),
+        }
+    }
+    requests.sort_by_key(|(param, _)| param.request_id);
+
+    let mut remaining = gas_budget;
+    for (param, call_id) in requests {
+        // leave room for a fill, whichever callback the request ends up with
+        if remaining < QUOTE_GAS {
+            plan.deferred.push(call_id);
+            continue;
+        }
+        match quote_pair(&param, aggregates, config, timestamp) {
+            Ok(quote) => {
+                remaining -= QUOTE_GAS;
+                plan.quotes.push((param, call_id, quote));
+            }
+            Err((reason, detail)) => {
+                remaining -= QUOTE_FAILURE_GAS;
+                plan.failed.push((call_id, reason, detail));
+            }
+        }
+    }
+
+    // calls without a request id can't be ranked, they go last
+    for failure in invalid {
+        match remaining >= QUOTE_FAILURE_GAS {
+            true => {
+                remaining -= QUOTE_FAILURE_GAS;
+                plan.failed.push(failure);
+            }
+            false => plan.deferred.push(failure.0),
+        }
+    }
+    plan
+}
+
+// The callbacks for the planned quotes, they go out with the run's feed updates
+pub fn quote_calls(quoter: &Quoter<Client>, plan: &QuotePlan) -> Vec<Callback> {
+    let mut calls: Vec<Callback> = plan
+        .quotes
+        .iter()
+        .map(|(_, call_id, quote)| {
+            quoter.quote_callback(
+                *call_id,
+                quote.price.value,
+                quote.confidence.value.into_raw(),
+                quote.price.decimals,
+                quote.timestamp.into(),
+            )
+        })
+        .collect();
+
+    // tell the receiver about every request we refuse, rather than resolving it silently
+    for (call_id, reason, _) in &plan.failed {
+        calls.push(quoter.quote_failure_callback(*call_id, reason.code()));
+    }
+    calls
+}
+
+// Report each planned quote for dry runs
+pub fn print_quotes(plan: &QuotePlan) {
+    println!(
+        "DRY RUN: {} quote(s), {} refused, {} deferred",
+        plan.quotes.len(),
+        plan.failed.len(),
+        plan.deferred.len()
+    );
+    for (param, call_id, quote) in &plan.quotes {
+        let price = match quote.price.to_decimal() {
+            Ok(d) => d.to_string(),
+            Err(e) => e.to_string(),
+        };
+        let confidence = match quote.confidence.to_decimal() {
+            Ok(d) => d.to_string(),
+            Err(e) => e.to_string(),
+        };
+        println!(
+            "  request {} (call id {:?}): {} = {} +/- {}",
+            param.request_id, call_id, param.pair, price, confidence
+        );
+    }
+    for (call_id, reason, detail) in &plan.failed {
+        println!("  call id {:?}: refused ({}), {}", call_id, reason, detail);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::{AbiDecode, AbiEncode};
+
+    const GAS_BUDGET: u64 = 1_000_000;
+
+    fn request(request_id: u64, pair: &str) -> (Result<QuoteParams, RunnerError>, Address) {
+        let param = QuoteParams {
+            request_id: U256::from(request_id),
+            pair: pair.to_string(),
+            max_age: U256::from(60),
+        };
+        (Ok(param), Address::random())
+    }
+
+    fn btc_usd() -> HashMap<Pair, Aggregate> {
+        HashMap::from([(
+            Pair::from("BTC/USD"),
+            Aggregate {
+                median: Decimal::from(26_000),
+                std_dev: Decimal::new(125, 1),
+                samples: 5,
+                venues: 5,
+            },
+        )])
+    }
+
+    #[test]
+    fn quotes_known_pairs_and_refuses_the_rest() {
+        let params = vec![
+            request(2, "DOGE-XYZ"),
+            request(1, "btc_usd"),
+            (
+                Err(RunnerError("bad params".to_string())),
+                Address::random(),
+            ),
+        ];
+        let call_ids: Vec<Address> = params.iter().map(|x| x.1).collect();
+        let config = OracleConfig::default();
+
+        let plan = plan_quotes(params, &btc_usd(), &config, 1_000, GAS_BUDGET);
+        assert_eq!(plan.quotes.len(), 1);
+        let (param, call_id, quote) = &plan.quotes[0];
+        assert_eq!((param.request_id, *call_id), (U256::from(1), call_ids[1]));
+        assert_eq!(
+            quote,
+            &Quote {
+                price: FixedPoint::from_decimal(Decimal::from(26_000), DEFAULT_DECIMALS).unwrap(),
+                confidence: FixedPoint::from_decimal(Decimal::new(125, 1), DEFAULT_DECIMALS)
+                    .unwrap(),
+                timestamp: 1_000,
+            }
+        );
+        let failed: Vec<(Address, QuoteFailure)> = plan.failed.iter().map(|x| (x.0, x.1)).collect();
+        assert_eq!(
+            failed,
+            vec![
+                (call_ids[0], QuoteFailure::UnknownPair),
+                (call_ids[2], QuoteFailure::InvalidParams)
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_freshness_that_cant_be_met() {
+        let (param, call_id) = request(1, "BTC/USD");
+        let param = QuoteParams {
+            max_age: U256::from(MIN_QUOTE_MAX_AGE - 1),
+            ..param.unwrap()
+        };
+        let plan = plan_quotes(
+            vec![(Ok(param), call_id)],
+            &btc_usd(),
+            &OracleConfig::default(),
+            1_000,
+            GAS_BUDGET,
+        );
+        assert_eq!(plan.failed[0].1, QuoteFailure::Stale);
+    }
+
+    #[test]
+    fn defers_requests_past_the_gas_budget() {
+        let mut params: Vec<_> = (0..5).map(|x| request(x, "BTC/USD")).collect();
+        params.push((
+            Err(RunnerError("bad params".to_string())),
+            Address::random(),
+        ));
+        let plan = plan_quotes(
+            params,
+            &btc_usd(),
+            &OracleConfig::default(),
+            1_000,
+            3 * QUOTE_GAS,
+        );
+        assert_eq!(plan.quotes.len(), 3);
+        assert_eq!(plan.deferred.len(), 3);
+        assert_eq!(plan.size().gas, 3 * QUOTE_GAS);
+
+        // the feeds keep most of the gas limit, however many quotes are pending
+        assert!(quote_gas_budget(5_500_000) < 5_500_000 / 2);
+    }
+
+    #[test]
+    fn calls_back_for_handled_requests_only() {
+        let encode = |request_id: u64, pair: &str| {
+            let param = QuoteParams {
+                request_id: U256::from(request_id),
+                pair: pair.to_string(),
+                max_age: U256::from(60),
+            };
+            (param.encode().into(), Address::random())
+        };
+        let params = vec![
+            encode(1, "BTC/USD"),
+            encode(2, "XYZ/USD"),
+            (vec![1u8, 2, 3].into(), Address::random()),
+            encode(3, "BTC/USD"),
+        ];
+        let call_ids: Vec<Address> = params.iter().map(|x| x.1).collect();
+        let runner = RecordingRunner::with_params(params);
+        let quoter = Quoter::new(Address::random(), runner.client());
+        let mut plan = plan_quotes(
+            runner.params::<QuoteParams>(),
+            &btc_usd(),
+            &OracleConfig::default(),
+            1_000,
+            GAS_BUDGET,
+        );
+        // pretend the last request didn't fit
+        let (_, call_id, _) = plan.quotes.pop().unwrap();
+        plan.deferred.push(call_id);
+
+        assert_eq!(plan.resolved_call_ids(), call_ids[..3].to_vec());
+        let calls = quote_calls(&quoter, &plan);
+        assert_eq!(calls.len(), 3);
+        assert!(calls
+            .iter()
+            .all(|x| x.tx.to_addr() == Some(&quoter.address())));
+        let filled = QuoteCallbackCall::decode(calls[0].calldata().unwrap()).unwrap();
+        assert_eq!((filled.0, filled.4), (call_ids[0], U256::from(1_000)));
+        assert_eq!(
+            calls[1].calldata().unwrap().to_vec(),
+            QuoteFailureCallbackCall(call_ids[1], QuoteFailure::UnknownPair.code()).encode()
+        );
+        assert_eq!(
+            calls[2].calldata().unwrap().to_vec(),
+            QuoteFailureCallbackCall(call_ids[2], QuoteFailure::InvalidParams.code()).encode()
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use crate::*;
+
+use ethers::{
+    prelude::abigen,
+    providers::Middleware,
+    types::{Address, I256},
+};
+use futures::future::join_all;
+use serde::Deserialize;
+
+// The part of AggregatorV3Interface we read, the same interface our Aggregator adapter implements
+abigen!(
+    AggregatorV3,
+    r#"[
+        function decimals() external view returns (uint8)
+        function latestRoundData() external view returns (uint80,int256,uint256,uint256,uint80)
+    ]"#,
+);
+
+#[derive(Debug)]
+pub enum ReferenceError {
+    Provider(String),
+    // the reference has no positive answer
+    NoAnswer,
+    // the reference's answer is older than `max_age_seconds`
+    Stale(u64),
+    Conversion(ConversionError),
+}
+
+impl fmt::Display for ReferenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReferenceError::Provider(err) => write!(f, "failed to read the reference: {}", err),
+            ReferenceError::NoAnswer => write!(f, "the reference has no answer"),
+            ReferenceError::Stale(age) => {
+                write!(f, "the reference's answer is {} seconds old", age)
+            }
+            ReferenceError::Conversion(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ReferenceError {}
+
+// What happens to an update that diverges from its reference
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferenceAction {
+    // don't send it, the feed is marked failed like a feed without samples
+    #[default]
+    Refuse,
+    // send it anyway, the divergence is only reported
+    Flag,
+}
+
+// Third-party AggregatorV3 feeds our medians are checked against before they're pushed
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ReferenceConfig {
+    // keyed by feed name, e.g. "BTC/USD", the address of an AggregatorV3 on this chain
+    pub feeds: HashMap<String, Address>,
+    pub tolerance_bps: u32,
+    // older reference answers aren't compared against
+    pub max_age_seconds: u64,
+    pub action: ReferenceAction,
+}
+
+impl Default for ReferenceConfig {
+    fn default() -> Self {
+        ReferenceConfig {
+            feeds: HashMap::new(),
+            tolerance_bps: 200,
+            max_age_seconds: 3600,
+            action: ReferenceAction::Refuse,
+        }
+    }
+}
+
+// One feed's comparison against its reference
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReferenceCheck {
+    pub feed: [u8; 32],
+    pub value: Decimal,
+    pub reference: Decimal,
+    // relative to the reference, 0.01 is 1%
+    pub divergence: Decimal,
+    pub within_tolerance: bool,
+    // the update was dropped
+    pub refused: bool,
+}
+
+impl fmt::Display for ReferenceCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} vs reference {}, {}% apart",
+            feed_name_to_string(&self.feed),
+            self.value,
+            self.reference,
+            (self.divergence * Decimal::ONE_HUNDRED).round_dp(4)
+        )?;
+        match (self.within_tolerance, self.refused) {
+            (true, _) => Ok(()),
+            (false, true) => write!(f, " (refused)"),
+            (false, false) => write!(f, " (flagged)"),
+        }
+    }
+}
+
+// Read a reference's latest answer, as long as it's positive and fresh
+pub async fn read_reference<M: Middleware + 'static>(
+    client: Arc<M>,
+    address: Address,
+    max_age_seconds: u64,
+    now: u64,
+) -> Result<Decimal, ReferenceError> {
+    let aggregator = AggregatorV3::new(address, client);
+    let decimals = aggregator
+        .decimals()
+        .call()
+        .await
+        .map_err(|e| ReferenceError::Provider(e.to_string()))?;
+    let (_, answer, _, updated_at, _) = aggregator
+        .latest_round_data()
+        .call()
+        .await
+        .map_err(|e| ReferenceError::Provider(e.to_string()))?;
+    if answer <= I256::zero() {
+        return Err(ReferenceError::NoAnswer);
+    }
+    let age = now.saturating_sub(updated_at.low_u64());
+    if age > max_age_seconds {
+        return Err(ReferenceError::Stale(age));
+    }
+    FixedPoint::new(answer, decimals)
+        .to_decimal()
+        .map_err(ReferenceError::Conversion)
+}
+
+// Read every configured reference, the ones that can't be read are left out of the check
+pub async fn read_references<M: Middleware + 'static>(
+    client: Arc<M>,
+    config: &ReferenceConfig,
+    now: u64,
+) -> HashMap<[u8; 32], Decimal> {
+    let feeds: Vec<(&String, &Address)> = config.feeds.iter().collect();
+    let answers = join_all(feeds.iter().map(|(_, address)| {
+        read_reference(client.clone(), **address, config.max_age_seconds, now)
+    }))
+    .await;
+    let mut references = HashMap::new();
+    for ((name, address), answer) in feeds.into_iter().zip(answers) {
+        match answer {
+            Ok(answer) => {
+                references.insert(feed_name_bytes(name), answer);
+            }
+            Err(e) => println!("{} reference {:?} -> not checked, {}", name, address, e),
+        }
+    }
+    references
+}
+
+// Compare each fresh value with a reference against it, and with `Refuse` drop the ones that
+// diverge by more than the tolerance
+pub fn check_references(
+    feed_updates: &mut HashMap<[u8; 32], FixedPoint>,
+    references: &HashMap<[u8; 32], Decimal>,
+    config: &ReferenceConfig,
+) -> Vec<ReferenceCheck> {
+    let tolerance = Decimal::new(config.tolerance_bps as i64, 4);
+    let mut checks = vec![];
+    for (key, reference) in references {
+        let value = match feed_updates.get(key).map(|x| x.to_decimal()) {
+            Some(Ok(value)) => value,
+            _ => continue,
+        };
+        let divergence = ((value - reference) / reference).abs();
+        let within_tolerance = divergence <= tolerance;
+        let refused = !within_tolerance && config.action == ReferenceAction::Refuse;
+        if refused {
+            feed_updates.remove(key);
+        }
+        checks.push(ReferenceCheck {
+            feed: *key,
+            value,
+            reference: *reference,
+            divergence,
+            within_tolerance,
+            refused,
+        });
+    }
+    checks.sort_by_key(|x| x.feed);
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_updates_that_diverge_from_their_reference() {
+        let value = |x: i64| FixedPoint::from_decimal(Decimal::from(x), 8).unwrap();
+        let mut feed_updates = HashMap::from([
+            (feed_name_bytes("BTC/USD"), value(30_100)),
+            (feed_name_bytes("ETH/USD"), value(1_500)),
+            (feed_name_bytes("SOL/USD"), value(20)),
+        ]);
+        let references = HashMap::from([
+            (feed_name_bytes("BTC/USD"), Decimal::from(30_000)),
+            (feed_name_bytes("ETH/USD"), Decimal::from(2_000)),
+            (feed_name_bytes("DOGE/USD"), Decimal::ONE),
+        ]);
+        let mut config = ReferenceConfig::default();
+
+        let mut flagged = feed_updates.clone();
+        config.action = ReferenceAction::Flag;
+        let checks = check_references(&mut flagged, &references, &config);
+        assert_eq!(flagged, feed_updates);
+        assert!(checks.iter().all(|x| !x.refused));
+
+        config.action = ReferenceAction::Refuse;
+        let checks = check_references(&mut feed_updates, &references, &config);
+        assert_eq!(checks.len(), 2);
+        // 0.33% apart
+        assert!(checks[0].within_tolerance);
+        // 25% apart
+        assert_eq!(checks[1].divergence, Decimal::new(25, 2));
+        assert!(checks[1].refused);
+        assert!(!feed_updates.contains_key(&feed_name_bytes("ETH/USD")));
+        assert_eq!(feed_updates.len(), 2);
+    }
+}
@@ -0,0 +1,292 @@
+use crate::*;
+
+use futures::future::join_all;
+use rust_decimal::MathematicalOps;
+use serde::Deserialize;
+
+const SECONDS_PER_YEAR: u64 = 365 * 86400;
+
+// A venue needs returns for at least this share of the window's candles, in percent
+const MIN_RETURNS_PERCENT: u64 = 50;
+
+// Pairs published as realized volatility and 24h volume feeds, for risk engines
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RiskConfig {
+    pub pairs: Vec<String>,
+    // windows volatility is realized over, e.g. "24h"
+    pub volatility_windows: Vec<String>,
+    // decimals of feeds without their own entry in `feeds`
+    pub volatility_decimals: u8,
+    pub volume_decimals: u8,
+}
+
+impl Default for RiskConfig {
+    fn default() -> Self {
+        RiskConfig {
+            pairs: vec![],
+            volatility_windows: vec![],
+            volatility_decimals: 8,
+            volume_decimals: 2,
+        }
+    }
+}
+
+impl RiskConfig {
+    // e.g. "BTC/USD:RVOL24h", annualized, 0.5 is 50%
+    pub fn volatility_feed_name(pair: &str, window: &TwapWindow) -> String {
+        format!("{}:RVOL{}", pair, window.label)
+    }
+
+    // e.g. "BTC/USD:VOL24h", in the quote currency
+    pub fn volume_feed_name(pair: &str) -> String {
+        format!("{}:VOL24h", pair)
+    }
+}
+
+// Decimals a risk feed is published with, its own entry in `feeds` wins over the risk default
+fn risk_decimals(config: &OracleConfig, name: &str, default: u8) -> u8 {
+    config
+        .feeds
+        .get(name)
+        .map(|x| x.decimals)
+        .unwrap_or(default)
+}
+
+// Annualized realized volatility of the closes of the candles opened since `start`: the root of
+// the summed squared log returns, scaled from the window to a year. None if too few candles.
+pub fn realized_volatility(
+    candles: &[Candle],
+    granularity: u64,
+    start: u64,
+    end: u64,
+) -> Option<Decimal> {
+    let mut closes: Vec<(u64, Decimal)> = candles
+        .iter()
+        .filter(|x| x.open_time >= start && x.open_time < end && x.close > Decimal::ZERO)
+        .map(|x| (x.open_time, x.close))
+        .collect();
+    closes.sort_by_key(|x| x.0);
+    closes.dedup_by_key(|x| x.0);
+
+    let expected = (end - start) / granularity;
+    let returns = closes.len().saturating_sub(1) as u64;
+    if returns == 0 || returns * 100 < expected * MIN_RETURNS_PERCENT {
+        return None;
+    }
+    let variance: Decimal = closes
+        .windows(2)
+        .map(|x| (x[1].1 / x[0].1).ln().powi(2))
+        .sum();
+    let scale = Decimal::from(SECONDS_PER_YEAR) / Decimal::from(end - start);
+    (variance * scale).sqrt()
+}
+
+// What every venue that reports it traded in the last 24h, None if none do
+pub fn total_volume(tickers: &[NormalizedTicker]) -> Option<Decimal> {
+    let volumes: Vec<Decimal> = tickers.iter().filter_map(|x| x.volume).collect();
+    match volumes.is_empty() {
+        true => None,
+        false => Some(volumes.iter().sum()),
+    }
+}
+
+// The 24h volume feed of each configured pair, summed across the venues' tickers, along with how
+// many venues reported volume. Tickers are tagged with their venue, and a venue listing the pair
+// under several symbols only counts its busiest one, the others mostly trade the same book.
+pub fn volume_feeds(
+    tickers: &HashMap<Pair, Vec<(String, NormalizedTicker)>>,
+    config: &OracleConfig,
+) -> (HashMap<[u8; 32], FixedPoint>, HashMap<[u8; 32], usize>) {
+    let mut feed_map = HashMap::<[u8; 32], FixedPoint>::new();
+    let mut venues = HashMap::<[u8; 32], usize>::new();
+    for name in &config.risk.pairs {
+        let feed_name = RiskConfig::volume_feed_name(name);
+        let tickers = tickers
+            .get(&Pair::from_string(name.clone()))
+            .cloned()
+            .unwrap_or_default();
+        let mut busiest = HashMap::<String, NormalizedTicker>::new();
+        for (venue, ticker) in tickers {
+            match busiest.get(&venue) {
+                Some(kept) if kept.volume >= ticker.volume => {}
+                _ => {
+                    busiest.insert(venue, ticker);
+                }
+            }
+        }
+        let reporting: Vec<String> = busiest
+            .iter()
+            .filter(|x| x.1.volume.is_some())
+            .map(|x| x.0.clone())
+            .collect();
+        let feed = config.feed(&feed_name);
+        if !has_quorum(&reporting, feed.min_venues, &feed.required_venues) {
+            println!("{} -> skipped, too few venues report volume", feed_name);
+            continue;
+        }
+        let tickers: Vec<NormalizedTicker> = busiest.into_values().collect();
+        let volume = match total_volume(&tickers) {
+            Some(volume) => volume,
+            None => {
+                println!("{} -> skipped, no venue reports volume", feed_name);
+                continue;
+            }
+        };
+        let decimals = risk_decimals(config, &feed_name, config.risk.volume_decimals);
+        match FixedPoint::from_decimal(volume, decimals) {
+            Ok(volume) => {
+                println!("{} -> {}", feed_name, volume.value);
+                feed_map.insert(feed_name_bytes(&feed_name), volume);
+                venues.insert(feed_name_bytes(&feed_name), distinct_venues(&reporting));
+            }
+            Err(e) => println!("{} -> skipped, {}", feed_name, e),
+        }
+    }
+    (feed_map, venues)
+}
+
+// Realize each configured pair's volatility over each window from every candle venue, the median
+// across venues like spot, along with how many venues each volatility was realized from
+pub async fn get_volatility_data(
+    config: &OracleConfig,
+) -> (HashMap<[u8; 32], FixedPoint>, HashMap<[u8; 32], usize>) {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    let client = reqwest::Client::new();
+    let mut feed_map = HashMap::<[u8; 32], FixedPoint>::new();
+    let mut venues = HashMap::<[u8; 32], usize>::new();
+    for name in &config.risk.pairs {
+        let pair = Pair::from_string(name.clone());
+        for window in &config.risk.volatility_windows {
+            // windows were checked when the config was loaded
+            let window = TwapWindow::parse(window).unwrap();
+            let feed_name = RiskConfig::volatility_feed_name(name, &window);
+            let granularity = window.granularity();
+            let start = now.saturating_sub(window.seconds);
+            let candles = join_all(
+                CANDLE_VENUES
+                    .iter()
+                    .map(|venue| fetch_candles(&client, *venue, &pair, granularity, start, now)),
+            )
+            .await;
+
+            let mut volatilities = vec![];
+            let mut listing = vec![];
+            for (venue, candles) in CANDLE_VENUES.iter().zip(candles) {
+                match candles.map(|x| realized_volatility(&x, granularity, start, now)) {
+                    Ok(Some(volatility)) => {
+                        volatilities.push(volatility);
+                        listing.push(venue.name());
+                    }
+                    Ok(None) => {}
+                    Err(e) => println!("{} {:?} -> {}", feed_name, venue, e),
+                }
+            }
+
+            let feed = config.feed(&feed_name);
+            if !has_quorum(&listing, feed.min_venues, &feed.required_venues) {
+                println!("{} -> skipped, too few venues", feed_name);
+                continue;
+            }
+
+            let aggregate = match aggregate_prices(volatilities) {
+                Some(aggregate) => aggregate,
+                None => {
+                    println!("{} -> skipped, not enough venues", feed_name);
+                    continue;
+                }
+            };
+            let decimals = risk_decimals(config, &feed_name, config.risk.volatility_decimals);
+            match FixedPoint::from_decimal(aggregate.median, decimals) {
+                Ok(volatility) => {
+                    println!("{} -> {}", feed_name, volatility.value);
+                    feed_map.insert(feed_name_bytes(&feed_name), volatility);
+                    venues.insert(feed_name_bytes(&feed_name), aggregate.venues);
+                }
+                Err(e) => println!("{} -> skipped, {}", feed_name, e),
+            }
+        }
+    }
+    (feed_map, venues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candle(open_time: u64, close: i64) -> Candle {
+        let close = Decimal::from(close);
+        Candle {
+            open_time,
+            open: close,
+            high: close,
+            low: close,
+            close,
+        }
+    }
+
+    #[test]
+    fn annualizes_the_squared_log_returns() {
+        let flat: Vec<Candle> = (0..60).map(|i| candle(i * 60, 100)).collect();
+        assert_eq!(realized_volatility(&flat, 60, 0, 3600), Some(Decimal::ZERO));
+
+        // every candle moves 10% up or back down
+        let choppy: Vec<Candle> = (0..60)
+            .map(|i| candle(i * 60, if i % 2 == 0 { 100 } else { 110 }))
+            .collect();
+        let volatility = realized_volatility(&choppy, 60, 0, 3600)
+            .unwrap()
+            .to_f64()
+            .unwrap();
+        let expected = (59.0 * 1.1f64.ln().powi(2) * (SECONDS_PER_YEAR as f64 / 3600.0)).sqrt();
+        assert!((volatility - expected).abs() < 1e-6);
+
+        // a handful of candles isn't enough to tell
+        assert_eq!(realized_volatility(&choppy[..10], 60, 0, 3600), None);
+    }
+
+    #[test]
+    fn sums_the_volume_of_venues_that_report_it() {
+        let ticker = |volume: Option<i64>| NormalizedTicker {
+            price: Decimal::ONE,
+            volume: volume.map(Decimal::from),
+        };
+        assert_eq!(
+            total_volume(&[ticker(Some(100)), ticker(None), ticker(Some(50))]),
+            Some(Decimal::from(150))
+        );
+        assert_eq!(total_volume(&[ticker(None)]), None);
+
+        // venues listing the pair under several symbols count once
+        let mut config = OracleConfig::default();
+        config.risk.pairs = vec!["BTC/USD".to_string()];
+        let tickers = HashMap::from([(
+            Pair::from("BTC/USD"),
+            vec![
+                ("kraken".to_string(), ticker(Some(100))),
+                ("kraken".to_string(), ticker(Some(10))),
+                ("okx".to_string(), ticker(Some(50))),
+                ("coinbase".to_string(), ticker(Some(25))),
+                ("bittrex".to_string(), ticker(None)),
+            ],
+        )]);
+        let (volumes, venues) = volume_feeds(&tickers, &config);
+        let key = feed_name_bytes("BTC/USD:VOL24h");
+        assert_eq!(volumes[&key].to_decimal().unwrap(), Decimal::from(175));
+        assert_eq!(venues[&key], 3);
+
+        // like spot, the volume needs every required venue to report it
+        config.feeds.insert(
+            "BTC/USD:VOL24h".to_string(),
+            FeedConfig {
+                required_venues: vec!["bittrex".to_string()],
+                ..Default::default()
+            },
+        );
+        let (volumes, _) = volume_feeds(&tickers, &config);
+        assert!(volumes.is_empty());
+    }
+}
@@ -0,0 +1,322 @@
+use crate::*;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+// registering a feed is significantly more expensive in gas than updating one
+pub const MAX_REGISTRATIONS: usize = 20;
+pub const MAX_UPDATES: usize = 100;
+
+// Why a feed made it into the update set, reported by dry runs
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpdateReason {
+    NewFeed,
+    // decimals the receiver had on record
+    DecimalsChanged(u8),
+    // min/max ratio against the on-chain value, see get_deviation_ratio
+    Deviation(Decimal),
+    // the on-chain value couldn't be converted for comparison
+    Uncomparable(ConversionError),
+}
+
+impl fmt::Display for UpdateReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateReason::NewFeed => write!(f, "new feed"),
+            UpdateReason::DecimalsChanged(from) => write!(f, "decimals changed from {}", from),
+            UpdateReason::Deviation(ratio) => write!(f, "ratio to on-chain value {}", ratio),
+            UpdateReason::Uncomparable(e) => write!(f, "on-chain value not comparable, {}", e),
+        }
+    }
+}
+
+// Why a feed was refused an update this run. The codes match ReceiverLib.FailureReason in the
+// receiver, 0 is reserved there for feeds marked failed without a reason.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedFailure {
+    // the value jumped further than the feed allows and too few venues confirmed it
+    PriceJump,
+    // the value diverged from the feed's reference, see src/reference.rs
+    ReferenceDivergence,
+}
+
+impl FeedFailure {
+    pub fn code(&self) -> u8 {
+        match self {
+            FeedFailure::PriceJump => 1,
+            FeedFailure::ReferenceDivergence => 2,
+        }
+    }
+}
+
+impl fmt::Display for FeedFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedFailure::PriceJump => write!(f, "unconfirmed price jump"),
+            FeedFailure::ReferenceDivergence => write!(f, "diverged from reference"),
+        }
+    }
+}
+
+// The feeds picked for a run, in the order they'll be sent
+#[derive(Debug, Clone, Default)]
+pub struct FeedSelection {
+    // still registering feeds, only a few fit in a run
+    pub registering_feeds: bool,
+    pub updates: Vec<([u8; 32], FixedPoint, UpdateReason)>,
+    // feeds whose configured decimals differ from what the receiver has on record
+    pub decimals_changes: Vec<([u8; 32], u8)>,
+    // feeds on-chain that got no samples this run
+    pub missing_feeds: Vec<[u8; 32]>,
+    // feeds on-chain whose value was refused this run, marked failed with the reason
+    pub failures: Vec<([u8; 32], FeedFailure)>,
+}
+
+impl FeedSelection {
+    // How many updates a run can send, registering feeds fits far fewer
+    pub fn limit(&self) -> usize {
+        match self.registering_feeds {
+            true => MAX_REGISTRATIONS,
+            false => MAX_UPDATES,
+        }
+    }
+
+    // Keep only the first `len` updates, along with their decimals changes
+    pub fn truncate(&mut self, len: usize) {
+        self.updates.truncate(len);
+        let updates = &self.updates;
+        self.decimals_changes
+            .retain(|(key, _)| updates.iter().any(|x| x.0 == *key));
+    }
+
+    // Mark refused feeds failed with their reason rather than as missing. Feeds that aren't
+    // on-chain yet have nothing to mark.
+    pub fn fail(&mut self, refusals: &[([u8; 32], FeedFailure)]) {
+        for (key, reason) in refusals {
+            if let Some(i) = self.missing_feeds.iter().position(|x| x == key) {
+                self.missing_feeds.remove(i);
+                self.failures.push((*key, *reason));
+            }
+        }
+    }
+}
+
+// Compare fresh values against what the receiver has on-chain and pick the feeds to send, in a
+// random order. Every candidate is kept, rank them and truncate to the selection's limit before
+// sending.
+pub fn select_feed_updates<R: Rng>(
+    feed_map: &HashMap<[u8; 32], FixedPoint>,
+    feed_updates: &HashMap<[u8; 32], FixedPoint>,
+    rng: &mut R,
+) -> FeedSelection {
+    // check if we're still registering feeds (significantly more expensive in gas cost)
+    // allow up to 1 registration alongside updates so we don't block updates for an entire run if a feed is added
+    let registering_feeds = feed_map.len() + 1 < feed_updates.len();
+
+    // get list of feed names that weren't received in get_feed_data
+    let mut missing_feeds: Vec<[u8; 32]> = feed_map
+        .keys()
+        .filter(|key| !feed_updates.contains_key(*key))
+        .copied()
+        .collect();
+    missing_feeds.sort();
+
+    // drop all entries with a diff less than 0.1
+    let mut candidates = Vec::<([u8; 32], FixedPoint, UpdateReason)>::new();
+    for (key, value) in feed_updates {
+        let current = match feed_map.get(key) {
+            Some(current) => current,
+            None => {
+                candidates.push((*key, *value, UpdateReason::NewFeed));
+                continue;
+            }
+        };
+        if registering_feeds {
+            continue;
+        }
+        // the on-chain value of a feed changing decimals is in the old scale, always update it
+        if current.decimals != value.decimals {
+            candidates.push((
+                *key,
+                *value,
+                UpdateReason::DecimalsChanged(current.decimals),
+            ));
+            continue;
+        }
+        match get_deviation_ratio(key, *current, *value) {
+            // %0.01 diff should triger an update
+            Ok(diff) if diff < Decimal::from_str("0.1").unwrap() => {}
+            Ok(diff) => candidates.push((*key, *value, UpdateReason::Deviation(diff))),
+            // keep the update if we can't compare against the on-chain value
+            Err(e) => {
+                println!("{} -> {}", feed_name_to_string(key), e);
+                candidates.push((*key, *value, UpdateReason::Uncomparable(e)));
+            }
+        }
+    }
+
+    // start from a stable order so the rng alone decides which feeds make the cut
+    candidates.sort_by_key(|x| x.0);
+
+    // only shuffle feeds if we're at the stage where we're submitting results
+    if !registering_feeds {
+        candidates.shuffle(rng);
+    }
+
+    let decimals_changes = candidates
+        .iter()
+        .filter(|(key, value, _)| {
+            let onchain_decimals = feed_map
+                .get(key)
+                .map(|x| x.decimals)
+                .unwrap_or(DEFAULT_DECIMALS);
+            onchain_decimals != value.decimals
+        })
+        .map(|(key, value, _)| (*key, value.decimals))
+        .collect();
+
+    FeedSelection {
+        registering_feeds,
+        updates: candidates,
+        decimals_changes,
+        missing_feeds,
+        failures: vec![],
+    }
+}
+
+pub fn get_percentage_diff(a: FixedPoint, b: FixedPoint) -> Result<Decimal, ConversionError> {
+    let a = a.to_decimal()?;
+    let b = b.to_decimal()?;
+    // feeds like volatility can sit at zero, don't divide by it
+    if a == b {
+        return Ok(Decimal::ONE);
+    }
+    Ok((Decimal::min(a, b) / Decimal::max(a, b)).abs())
+}
+
+// How far a feed moved between `a` and `b`, as the ratio deviation_of reads. Signed feeds like
+// funding rates cross zero, where a min/max ratio reads a sign flip as no move at all. Their
+// values are already fractions of notional, so their ratio is one less the absolute move, and
+// thresholds in bps apply to the notional.
+pub fn get_deviation_ratio(
+    key: &[u8; 32],
+    a: FixedPoint,
+    b: FixedPoint,
+) -> Result<Decimal, ConversionError> {
+    match DerivativesConfig::is_funding_feed(&feed_name_to_string(key)) {
+        true => Ok(Decimal::ONE - absolute_move(a, b)?),
+        false => get_percentage_diff(a, b),
+    }
+}
+
+// How far a signed feed moved, in its own units
+pub fn absolute_move(a: FixedPoint, b: FixedPoint) -> Result<Decimal, ConversionError> {
+    Ok((b.to_decimal()? - a.to_decimal()?).abs())
+}
+
+// How far a value moved, as a fraction, given the ratio get_deviation_ratio returns
+pub fn deviation_of(ratio: Decimal) -> Decimal {
+    Decimal::ONE - ratio
+}
+
+// Feed names are stored as bytes32, zero padded
+pub fn feed_name_bytes(name: &str) -> [u8; 32] {
+    let mut bytes32 = [0u8; 32];
+    bytes32[..name.len()].copy_from_slice(name.as_bytes());
+    bytes32
+}
+
+pub fn feed_name_to_string(name: &[u8; 32]) -> String {
+    String::from_utf8_lossy(name)
+        .trim_end_matches(char::from(0))
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn price(value: i64, decimals: u8) -> FixedPoint {
+        FixedPoint::new(I256::from(value), decimals)
+    }
+
+    #[test]
+    fn measures_funding_moves_against_notional() {
+        let funding = feed_name_bytes("BTC/USDT:FUND8h");
+        // funding flipped from paying 0.01% to receiving 0.01%, a 2 bps move
+        let ratio = get_deviation_ratio(&funding, price(1, 4), price(-1, 4)).unwrap();
+        assert_eq!(deviation_of(ratio), Decimal::new(2, 4));
+        // a price halving is still a 50% move
+        let mark = feed_name_bytes("BTC/USDT:MARK");
+        let ratio = get_deviation_ratio(&mark, price(2, 0), price(1, 0)).unwrap();
+        assert_eq!(deviation_of(ratio), Decimal::new(5, 1));
+    }
+
+    #[test]
+    fn registers_a_limited_batch_of_new_feeds() {
+        let feed_map = HashMap::new();
+        let feed_updates: HashMap<_, _> = (0..30)
+            .map(|i| (feed_name_bytes(&format!("FEED{}/USD", i)), price(1, 18)))
+            .collect();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+
+        let mut selection = select_feed_updates(&feed_map, &feed_updates, &mut rng);
+        assert!(selection.registering_feeds);
+        assert_eq!(selection.updates.len(), 30);
+        selection.truncate(selection.limit());
+        assert_eq!(selection.updates.len(), MAX_REGISTRATIONS);
+        assert!(selection
+            .updates
+            .iter()
+            .all(|(_, _, reason)| *reason == UpdateReason::NewFeed));
+        // feeds registered at the default decimals don't need a decimals callback
+        assert_eq!(selection.decimals_changes.len(), 0);
+    }
+
+    #[test]
+    fn reports_missing_and_reconfigured_feeds() {
+        let feed_map = HashMap::from([
+            (feed_name_bytes("BTC/USD"), price(26_000, 18)),
+            (feed_name_bytes("ETH/USD"), price(1_600, 18)),
+            (feed_name_bytes("DOGE/USD"), price(6, 18)),
+        ]);
+        let feed_updates = HashMap::from([
+            (feed_name_bytes("BTC/USD"), price(26_100, 18)),
+            (feed_name_bytes("ETH/USD"), price(1_600, 8)),
+        ]);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+
+        let selection = select_feed_updates(&feed_map, &feed_updates, &mut rng);
+        assert!(!selection.registering_feeds);
+        assert_eq!(selection.missing_feeds, vec![feed_name_bytes("DOGE/USD")]);
+        assert_eq!(
+            selection.decimals_changes,
+            vec![(feed_name_bytes("ETH/USD"), 8)]
+        );
+        assert_eq!(selection.updates.len(), 2);
+        assert!(selection.updates.contains(&(
+            feed_name_bytes("ETH/USD"),
+            price(1_600, 8),
+            UpdateReason::DecimalsChanged(18)
+        )));
+    }
+
+    #[test]
+    fn marks_refused_feeds_failed_with_their_reason() {
+        let mut selection = FeedSelection {
+            missing_feeds: vec![feed_name_bytes("BTC/USD"), feed_name_bytes("ETH/USD")],
+            ..Default::default()
+        };
+        selection.fail(&[
+            (feed_name_bytes("ETH/USD"), FeedFailure::PriceJump),
+            // not on-chain yet, nothing to mark
+            (feed_name_bytes("NEW/USD"), FeedFailure::ReferenceDivergence),
+        ]);
+        assert_eq!(selection.missing_feeds, vec![feed_name_bytes("BTC/USD")]);
+        assert_eq!(
+            selection.failures,
+            vec![(feed_name_bytes("ETH/USD"), FeedFailure::PriceJump)]
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use ethers::{
+    prelude::abigen,
+    providers::Middleware,
+    types::{Address, Bytes},
+};
+use std::fmt;
+use std::sync::Arc;
+
+// The push receiver is an EIP-2535 diamond, so its selectors live on the facets rather than in
+// the proxy bytecode. The loupe facet tells us which facet (if any) serves a given selector.
+abigen!(
+    DiamondLoupe,
+    r#"[
+        function facetAddress(bytes4) external view returns (address)
+    ]"#,
+);
+
+#[derive(Debug)]
+pub enum TargetError {
+    MissingAddress(&'static str),
+    InvalidAddress(String),
+    Provider(String),
+    NoCode(Address),
+    MissingSelector(Address, [u8; 4]),
+}
+
+impl fmt::Display for TargetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetError::MissingAddress(key) => write!(
+                f,
+                "no receiver address configured, set {} at runtime or pin it at build time",
+                key
+            ),
+            TargetError::InvalidAddress(value) => {
+                write!(f, "receiver address {:?} is not a valid address", value)
+            }
+            TargetError::Provider(err) => write!(f, "failed to query the receiver: {}", err),
+            TargetError::NoCode(address) => write!(f, "no contract code at {:?}", address),
+            TargetError::MissingSelector(address, selector) => write!(
+                f,
+                "contract at {:?} does not expose selector 0x{}",
+                address,
+                ethers::utils::hex::encode(selector)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TargetError {}
+
+// Resolve the receiver address from the runtime environment, falling back to the value pinned
+// at build time (if any). Keeping the runtime value first means one enclave build/measurement
+// can serve every deployment of the receiver.
+pub fn resolve_receiver_address(
+    env_key: &'static str,
+    pinned: Option<&'static str>,
+) -> Result<Address, TargetError> {
+    let value = match std::env::var(env_key) {
+        Ok(value) if !value.trim().is_empty() => value,
+        _ => pinned
+            .map(|x| x.to_string())
+            .ok_or(TargetError::MissingAddress(env_key))?,
+    };
+    value
+        .trim()
+        .parse::<Address>()
+        .map_err(|_| TargetError::InvalidAddress(value.clone()))
+}
+
+// Make sure there is a diamond at the address and that every selector we're about to call is
+// routed to a facet. This runs before anything is emitted so a misconfigured address fails loudly
+// instead of burning the function's escrow on reverting callbacks.
+pub async fn verify_receiver<M: Middleware + 'static>(
+    client: Arc<M>,
+    address: Address,
+    selectors: &[[u8; 4]],
+) -> Result<(), TargetError> {
+    let code: Bytes = client
+        .get_code(address, None)
+        .await
+        .map_err(|e| TargetError::Provider(e.to_string()))?;
+    if code.as_ref().is_empty() {
+        return Err(TargetError::NoCode(address));
+    }
+
+    let loupe = DiamondLoupe::new(address, client);
+    for selector in selectors {
+        let facet = loupe
+            .facet_address(*selector)
+            .call()
+            .await
+            .map_err(|e| TargetError::Provider(e.to_string()))?;
+        if facet == Address::zero() {
+            return Err(TargetError::MissingSelector(address, *selector));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,463 @@
+use crate::*;
+
+use futures::future::join_all;
+use serde::Deserialize;
+
+// Candles asked of a venue in one request, every venue serves at least this many
+const MAX_CANDLES: u64 = 300;
+
+// Candle sizes every venue serves, in seconds
+const GRANULARITIES: [u64; 4] = [60, 300, 900, 3600];
+
+// A venue's candles have to cover at least this share of the window, in percent. Venues leave out
+// candles for intervals without trades.
+const MIN_COVERAGE_PERCENT: u64 = 50;
+
+#[derive(Debug)]
+pub enum TwapError {
+    InvalidWindow(String),
+    WindowTooLong(String),
+    Request(String),
+    Decode(String),
+}
+
+impl fmt::Display for TwapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TwapError::InvalidWindow(label) => write!(
+                f,
+                "invalid TWAP window {:?}, expected a number followed by m, h or d",
+                label
+            ),
+            TwapError::WindowTooLong(label) => write!(
+                f,
+                "TWAP window {:?} is too long, {} hourly candles must cover it",
+                label, MAX_CANDLES
+            ),
+            TwapError::Request(err) => write!(f, "failed to fetch candles: {}", err),
+            TwapError::Decode(err) => write!(f, "failed to decode candles: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TwapError {}
+
+// Which pairs get TWAP feeds and over which windows, e.g. "30m" or "24h"
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct TwapConfig {
+    pub pairs: Vec<String>,
+    pub windows: Vec<String>,
+}
+
+// The span a TWAP is taken over
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TwapWindow {
+    // as configured, it's part of the feed name
+    pub label: String,
+    pub seconds: u64,
+}
+
+impl TwapWindow {
+    pub fn parse(label: &str) -> Result<Self, TwapError> {
+        let invalid = || TwapError::InvalidWindow(label.to_string());
+        // the unit is the last character, which needn't be a single byte in a bad label
+        let last = label.char_indices().last().map_or(0, |(i, _)| i);
+        let (amount, unit) = label.split_at(last);
+        let amount: u64 = amount.parse().map_err(|_| invalid())?;
+        let unit = match unit {
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86400,
+            _ => return Err(invalid()),
+        };
+        if amount == 0 {
+            return Err(invalid());
+        }
+        // one request of the largest candles has to cover the window
+        let largest = GRANULARITIES[GRANULARITIES.len() - 1];
+        if amount.saturating_mul(unit) / largest >= MAX_CANDLES {
+            return Err(TwapError::WindowTooLong(label.to_string()));
+        }
+        Ok(TwapWindow {
+            label: label.to_string(),
+            seconds: amount * unit,
+        })
+    }
+
+    // The smallest candle that covers the window in one request, parse makes sure one does
+    pub fn granularity(&self) -> u64 {
+        GRANULARITIES
+            .iter()
+            .copied()
+            .find(|x| self.seconds / x < MAX_CANDLES)
+            .unwrap_or(GRANULARITIES[GRANULARITIES.len() - 1])
+    }
+
+    // e.g. "BTC/USD:TWAP30m"
+    pub fn feed_name(&self, pair: &str) -> String {
+        format!("{}:TWAP{}", pair, self.label)
+    }
+}
+
+// One candle, its times in seconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candle {
+    pub open_time: u64,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+}
+
+impl Candle {
+    // the candle's OHLC average, a closer stand-in for its prices than the close alone
+    fn average_price(&self) -> Decimal {
+        (self.open + self.high + self.low + self.close) / Decimal::from(4)
+    }
+}
+
+// Average each candle's price, weighted by how much of it falls between `start` and `end`. None
+// if the candles cover too little of the window.
+pub fn twap_of(candles: &[Candle], granularity: u64, start: u64, end: u64) -> Option<Decimal> {
+    let mut weighted = Decimal::ZERO;
+    let mut covered = 0u64;
+    for candle in candles {
+        let from = candle.open_time.max(start);
+        let to = (candle.open_time + granularity).min(end);
+        if to <= from {
+            continue;
+        }
+        weighted += candle.average_price() * Decimal::from(to - from);
+        covered += to - from;
+    }
+    if covered == 0 || covered * 100 < (end - start) * MIN_COVERAGE_PERCENT {
+        return None;
+    }
+    Some(weighted / Decimal::from(covered))
+}
+
+// The venues TWAPs are taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CandleVenue {
+    Okx,
+    Binance,
+    Kraken,
+    Coinbase,
+}
+
+pub const CANDLE_VENUES: [CandleVenue; 4] = [
+    CandleVenue::Okx,
+    CandleVenue::Binance,
+    CandleVenue::Kraken,
+    CandleVenue::Coinbase,
+];
+
+impl CandleVenue {
+    // The venue's name, as in `required_venues`
+    pub fn name(&self) -> String {
+        match self {
+            CandleVenue::Okx => "okx",
+            CandleVenue::Binance => "binance",
+            CandleVenue::Kraken => "kraken",
+            CandleVenue::Coinbase => "coinbase",
+        }
+        .to_string()
+    }
+
+    // https://www.okx.com/docs-v5/en/#order-book-trading-market-data-get-candlesticks
+    // https://github.com/binance/binance-spot-api-docs/blob/master/rest-api.md#klinecandlestick-data
+    // https://docs.kraken.com/rest/#tag/Market-Data/operation/getOHLCData
+    // https://docs.cloud.coinbase.com/exchange/reference/exchangerestapi_getproductcandles
+    fn url(&self, pair: &Pair, granularity: u64, start: u64, end: u64) -> String {
+        let count = (end - start) / granularity + 1;
+        match self {
+            CandleVenue::Okx => {
+                let bar = match granularity {
+                    3600 => "1H".to_string(),
+                    x => format!("{}m", x / 60),
+                };
+                format!(
+                    "https://www.okx.com/api/v5/market/candles?instId={}-{}&bar={}&limit={}",
+                    pair.base,
+                    pair.quote,
+                    bar,
+                    count.min(MAX_CANDLES)
+                )
+            }
+            CandleVenue::Binance => {
+                let interval = match granularity {
+                    3600 => "1h".to_string(),
+                    x => format!("{}m", x / 60),
+                };
+                format!(
+                    "https://api.binance.us/api/v3/klines?symbol={}{}&interval={}&startTime={}&limit={}",
+                    pair.base,
+                    pair.quote,
+                    interval,
+                    start * 1000,
+                    count.min(MAX_CANDLES)
+                )
+            }
+            CandleVenue::Kraken => {
+                // kraken still lists bitcoin as XBT
+                let base = match pair.base.as_str() {
+                    "BTC" => "XBT",
+                    base => base,
+                };
+                format!(
+                    "https://api.kraken.com/0/public/OHLC?pair={}{}&interval={}&since={}",
+                    base,
+                    pair.quote,
+                    granularity / 60,
+                    start
+                )
+            }
+            CandleVenue::Coinbase => format!(
+                "https://api.exchange.coinbase.com/products/{}-{}/candles?granularity={}&start={}&end={}",
+                pair.base, pair.quote, granularity, start, end
+            ),
+        }
+    }
+
+    fn parse(&self, body: &Value) -> Result<Vec<Candle>, TwapError> {
+        let rows = match self {
+            CandleVenue::Okx => body.get("data"),
+            CandleVenue::Binance | CandleVenue::Coinbase => Some(body),
+            CandleVenue::Kraken => {
+                if let Some(error) = body
+                    .get("error")
+                    .and_then(|x| x.as_array())
+                    .and_then(|x| x.first())
+                {
+                    return Err(TwapError::Decode(error.to_string()));
+                }
+                // the result is keyed by kraken's own name for the pair, next to "last"
+                body.get("result")
+                    .and_then(|x| x.as_object())
+                    .and_then(|x| x.iter().find(|(k, _)| *k != "last").map(|(_, v)| v))
+            }
+        };
+        let rows = rows
+            .and_then(|x| x.as_array())
+            .ok_or(TwapError::Decode(format!("unexpected response {}", body)))?;
+        rows.iter()
+            .map(|row| {
+                let field = |i: usize| {
+                    row.get(i)
+                        .and_then(decimal_of)
+                        .ok_or(TwapError::Decode(format!("unexpected candle {}", row)))
+                };
+                let time = field(0)?.to_u64().unwrap_or_default();
+                Ok(match self {
+                    CandleVenue::Okx | CandleVenue::Binance => Candle {
+                        open_time: time / 1000,
+                        open: field(1)?,
+                        high: field(2)?,
+                        low: field(3)?,
+                        close: field(4)?,
+                    },
+                    CandleVenue::Kraken => Candle {
+                        open_time: time,
+                        open: field(1)?,
+                        high: field(2)?,
+                        low: field(3)?,
+                        close: field(4)?,
+                    },
+                    // [time, low, high, open, close, volume]
+                    CandleVenue::Coinbase => Candle {
+                        open_time: time,
+                        open: field(3)?,
+                        high: field(2)?,
+                        low: field(1)?,
+                        close: field(4)?,
+                    },
+                })
+            })
+            .collect()
+    }
+}
+
+// Venues send prices as strings or as numbers
+pub fn decimal_of(value: &Value) -> Option<Decimal> {
+    match value {
+        Value::String(x) => Decimal::from_str(x).ok(),
+        Value::Number(x) => Decimal::from_str(&x.to_string()).ok(),
+        _ => None,
+    }
+}
+
+pub async fn fetch_candles(
+    client: &reqwest::Client,
+    venue: CandleVenue,
+    pair: &Pair,
+    granularity: u64,
+    start: u64,
+    end: u64,
+) -> Result<Vec<Candle>, TwapError> {
+    let body: Value = client
+        .get(venue.url(pair, granularity, start, end))
+        // coinbase refuses requests without a user agent
+        .header("User-Agent", "switchboard-function")
+        .send()
+        .await
+        .map_err(|e| TwapError::Request(e.to_string()))?
+        .json()
+        .await
+        .map_err(|e| TwapError::Decode(e.to_string()))?;
+    venue.parse(&body)
+}
+
+// Take each configured pair's TWAP over each configured window, the median across venues like
+// spot, along with how many venues each TWAP was taken from
+pub async fn get_twap_data(
+    config: &OracleConfig,
+) -> (HashMap<[u8; 32], FixedPoint>, HashMap<[u8; 32], usize>) {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    let client = reqwest::Client::new();
+    let mut feed_map = HashMap::<[u8; 32], FixedPoint>::new();
+    let mut venues = HashMap::<[u8; 32], usize>::new();
+    for name in &config.twap.pairs {
+        let pair = Pair::from_string(name.clone());
+        for window in &config.twap.windows {
+            // windows were checked when the config was loaded
+            let window = TwapWindow::parse(window).unwrap();
+            let granularity = window.granularity();
+            let start = now.saturating_sub(window.seconds);
+            let candles = join_all(
+                CANDLE_VENUES
+                    .iter()
+                    .map(|venue| fetch_candles(&client, *venue, &pair, granularity, start, now)),
+            )
+            .await;
+
+            let mut prices = vec![];
+            let mut listing = vec![];
+            for (venue, candles) in CANDLE_VENUES.iter().zip(candles) {
+                match candles.map(|x| twap_of(&x, granularity, start, now)) {
+                    Ok(Some(price)) => {
+                        prices.push(price);
+                        listing.push(venue.name());
+                    }
+                    Ok(None) => {}
+                    Err(e) => println!("{} {:?} -> {}", window.feed_name(name), venue, e),
+                }
+            }
+
+            // like spot, a TWAP needs enough distinct venues and every required one
+            let feed_name = window.feed_name(name);
+            let feed = config.feed(&feed_name);
+            if !has_quorum(&listing, feed.min_venues, &feed.required_venues) {
+                println!("{} -> skipped, too few venues", feed_name);
+                continue;
+            }
+            let aggregate = match aggregate_prices(prices) {
+                Some(aggregate) => aggregate,
+                None => {
+                    println!("{} -> skipped, not enough venues", feed_name);
+                    continue;
+                }
+            };
+            let twap = match FixedPoint::from_decimal(
+                aggregate.median,
+                config.feed(&feed_name).decimals,
+            ) {
+                Ok(twap) => twap,
+                Err(e) => {
+                    println!("{} -> skipped, {}", feed_name, e);
+                    continue;
+                }
+            };
+            println!("{} -> {}", feed_name, twap.value);
+
+            feed_map.insert(feed_name_bytes(&feed_name), twap);
+            venues.insert(feed_name_bytes(&feed_name), aggregate.venues);
+        }
+    }
+    (feed_map, venues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candle(open_time: u64, price: i64) -> Candle {
+        let price = Decimal::from(price);
+        Candle {
+            open_time,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+        }
+    }
+
+    #[test]
+    fn parses_windows() {
+        let window = TwapWindow::parse("30m").unwrap();
+        assert_eq!(window.seconds, 1800);
+        assert_eq!(window.granularity(), 60);
+        assert_eq!(window.feed_name("BTC/USD"), "BTC/USD:TWAP30m");
+        assert_eq!(TwapWindow::parse("24h").unwrap().granularity(), 300);
+        assert!(TwapWindow::parse("30").is_err());
+        assert!(TwapWindow::parse("0h").is_err());
+        assert!(TwapWindow::parse("").is_err());
+        assert!(matches!(
+            TwapWindow::parse("30µ"),
+            Err(TwapError::InvalidWindow(_))
+        ));
+        // past 300 hourly candles
+        assert_eq!(TwapWindow::parse("12d").unwrap().granularity(), 3600);
+        assert!(matches!(
+            TwapWindow::parse("30d"),
+            Err(TwapError::WindowTooLong(_))
+        ));
+        assert!(TwapWindow::parse("300h").is_err());
+    }
+
+    #[test]
+    fn weights_candles_by_their_time_in_the_window() {
+        // the first candle only has 30 of its 60 seconds in the window
+        let candles = vec![candle(0, 100), candle(60, 200), candle(120, 400)];
+        assert_eq!(
+            twap_of(&candles, 60, 30, 180),
+            // (100 * 30 + 200 * 60 + 400 * 60) / 150
+            Some(Decimal::from(260))
+        );
+        // a single candle doesn't cover enough of a 10 minute window
+        assert_eq!(twap_of(&candles[..1], 60, 0, 600), None);
+    }
+
+    #[test]
+    fn parses_each_venues_candles() {
+        let okx: Value = serde_json::from_str(
+            r#"{"code":"0","data":[["60000","1","4","1","2","10","10","10","1"]]}"#,
+        )
+        .unwrap();
+        let binance: Value =
+            serde_json::from_str(r#"[[60000,"1","4","1","2","10",119999,"10",1,"5","5","0"]]"#)
+                .unwrap();
+        let kraken: Value = serde_json::from_str(
+            r#"{"error":[],"result":{"XXBTZUSD":[[60,"1","4","1","2","2","10",1]],"last":60}}"#,
+        )
+        .unwrap();
+        let coinbase: Value = serde_json::from_str(r#"[[60,1,4,1,2,10]]"#).unwrap();
+        let expected = Candle {
+            open_time: 60,
+            open: Decimal::from(1),
+            high: Decimal::from(4),
+            low: Decimal::from(1),
+            close: Decimal::from(2),
+        };
+        for (venue, body) in CANDLE_VENUES.iter().zip([okx, binance, kraken, coinbase]) {
+            assert_eq!(venue.parse(&body).unwrap(), vec![expected], "{:?}", venue);
+        }
+
+        let error: Value =
+            serde_json::from_str(r#"{"error":["EQuery:Unknown asset pair"]}"#).unwrap();
+        assert!(CandleVenue::Kraken.parse(&error).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+//SPDX-License-Identifier: UNLICENSED
+pragma solidity ^0.8.9;
+
+// Expands a VRF output into the values a request asked for. The Switchboard Function runs the same
+// steps (switchboard-function/src/requests.rs), so the receiver can check the values it's given.
+library RandomnessRequests {
+    // `count` values in [min, max], dice are a range from 1 to the number of sides
+    uint8 internal constant RANGE = 0;
+    // a random permutation of the indices 0..count
+    uint8 internal constant SHUFFLE = 1;
+    // `count` indices into `weights`, each picked with probability weight / sum(weights)
+    uint8 internal constant WEIGHTED = 2;
+
+    // the receiver recomputes every value, at these bounds the costliest request still fits in a
+    // run next to the round's callback, see switchboard-function/src/budget.rs
+    uint256 internal constant MAX_COUNT = 32;
+    uint256 internal constant MAX_WEIGHTS = 32;
+
+    // These are the params the function decodes, keep them in sync with RandomnessRequest
+    struct Request {
+        uint256 requestId;
+        uint8 kind;
+        uint32 count;
+        // only used by range requests
+        uint256 min;
+        uint256 max;
+        // only used by weighted requests
+        uint256[] weights;
+    }
+
+    error UnknownKind(uint8 kind);
+    error InvalidCount(uint32 count);
+    error InvalidRange(uint256 min, uint256 max);
+    error InvalidWeights();
+
+    function validate(Request memory request) internal pure {
+        if (request.kind > WEIGHTED) {
+            revert UnknownKind(request.kind);
+        }
+        if (request.count == 0 || request.count > MAX_COUNT) {
+            revert InvalidCount(request.count);
+        }
+        if (request.kind == RANGE && request.min > request.max) {
+            revert InvalidRange(request.min, request.max);
+        }
+        if (request.kind == WEIGHTED) {
+            totalWeight(request.weights);
+        }
+    }
+
+    function expand(
+        Request memory request,
+        uint256 output
+    ) internal pure returns (uint256[] memory values) {
+        validate(request);
+        values = new uint256[](request.count);
+        uint256 counter = 0;
+
+        if (request.kind == RANGE) {
+            // max - min + 1 only wraps to zero for the full uint256 range, where any word will do
+            uint256 span;
+            unchecked {
+                span = request.max - request.min + 1;
+            }
+            for (uint256 i = 0; i < values.length; i++) {
+                if (span == 0) {
+                    values[i] = word(output, counter++);
+                } else {
+                    uint256 value;
+                    (value, counter) = uniform(output, counter, span);
+                    values[i] = request.min + value;
+                }
+            }
+        } else if (request.kind == SHUFFLE) {
+            // Fisher-Yates, swapping each position with one at or below it
+            for (uint256 i = 0; i < values.length; i++) {
+                values[i] = i;
+            }
+            for (uint256 i = values.length - 1; i > 0; i--) {
+                uint256 j;
+                (j, counter) = uniform(output, counter, i + 1);
+                (values[i], values[j]) = (values[j], values[i]);
+            }
+        } else {
+            uint256 total = totalWeight(request.weights);
+            for (uint256 i = 0; i < values.length; i++) {
+                uint256 target;
+                (target, counter) = uniform(output, counter, total);
+                uint256 cumulative = 0;
+                for (uint256 j = 0; j < request.weights.length; j++) {
+                    cumulative += request.weights[j];
+                    if (target < cumulative) {
+                        values[i] = j;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    function totalWeight(
+        uint256[] memory weights
+    ) private pure returns (uint256 total) {
+        if (weights.length == 0 || weights.length > MAX_WEIGHTS) {
+            revert InvalidWeights();
+        }
+        for (uint256 i = 0; i < weights.length; i++) {
+            // a total that overflows is invalid, not a panic
+            if (weights[i] > type(uint256).max - total) {
+                revert InvalidWeights();
+            }
+            total += weights[i];
+        }
+        if (total == 0) {
+            revert InvalidWeights();
+        }
+    }
+
+    function word(
+        uint256 output,
+        uint256 counter
+    ) private pure returns (uint256) {
+        return uint256(keccak256(abi.encode(output, counter)));
+    }
+
+    // A value in [0, bound). Words below 2^256 mod bound are skipped so that every remainder is hit
+    // by the same number of words, which removes the modulo bias.
+    function uniform(
+        uint256 output,
+        uint256 counter,
+        uint256 bound
+    ) private pure returns (uint256, uint256) {
+        uint256 threshold = (type(uint256).max - bound + 1) % bound;
+        while (true) {
+            uint256 w = word(output, counter++);
+            if (w >= threshold) {
+                return (w % bound, counter);
+            }
+        }
+    }
+}
@@ -15,13 +15,252 @@ import {Switchboard} from "@switchboard-xyz/evm.js/contracts/core/testnet/Switch
  * etc...
  */
 
+import {ISwitchboard} from "@switchboard-xyz/evm.js/contracts/ISwitchboard.sol";
+import {VRF} from "./VRF.sol";
+import {RandomnessRequests} from "./RandomnessRequests.sol";
+
 contract SwitchboardReceiver {
+    // A user's request along with the call that will answer it
+    struct Request {
+        address callId;
+        address sender;
+        RandomnessRequests.Request params;
+        bool fulfilled;
+    }
+
+    uint256 public constant EXPECTED_FUNCTION_GAS_COST = 300_000;
+    // how long a proposed VRF key waits before it replaces the current one
+    uint256 public constant VRF_KEY_ROTATION_DELAY = 2 days;
+
     uint256 public randomValue;
+    // number of values delivered so far, each one is proven against the seed for its round
+    uint256 public round;
     address functionId;
+    // the function's VRF public key, only the owner can change it, see proposeVrfKey
+    uint256[2] vrfPublicKey;
+    // proofs aren't accepted in the block the key changed in
+    uint256 vrfKeyBlock;
+    // a key waiting out VRF_KEY_ROTATION_DELAY, and when it can be activated
+    uint256[2] pendingVrfPublicKey;
+    uint256 public pendingVrfKeyActivation;
+    address public owner;
+    address switchboardAddress;
+    uint256 nextRequestId = 1;
+    mapping(uint256 => Request) requests;
 
     event NewRandomValue(uint256 value);
+    event VrfKeyProposed(uint256[2] publicKey, uint256 activatesAt);
+    event VrfKeyRotated(uint256 round, uint256[2] publicKey);
+    event RandomnessRequested(uint256 requestId, address callId, address sender);
+    event RequestFulfilled(uint256 requestId, uint256[] values);
+
+    constructor(address _switchboard) {
+        switchboardAddress = _switchboard;
+        owner = msg.sender;
+    }
+
+    function callback(VRF.Proof memory proof) external {
+        checkFunctionId();
+        checkVrfKey(proof.pk);
+
+        // reverts unless the value is the only one the key could have produced for this round
+        uint256 value = VRF.verify(proof, seed(round));
+        round++;
+
+        // set the random value
+        randomValue = value;
+
+        // emit an event
+        emit NewRandomValue(value);
+    }
+
+    // Ask the function for `count` values in [min, max], a shuffle of `count` items or `count`
+    // weighted picks. The caller pays for the run, the values arrive through fulfillRequest.
+    function requestRandomness(
+        uint8 kind,
+        uint32 count,
+        uint256 min,
+        uint256 max,
+        uint256[] memory weights
+    ) public payable returns (uint256 requestId) {
+        if (functionId == address(0)) {
+            revert("Function not initialized");
+        }
+        if (msg.value < EXPECTED_FUNCTION_GAS_COST * tx.gasprice) {
+            revert("Insufficient fee");
+        }
+
+        requestId = nextRequestId++;
+        RandomnessRequests.Request memory params = RandomnessRequests.Request({
+            requestId: requestId,
+            kind: kind,
+            count: count,
+            min: min,
+            max: max,
+            weights: weights
+        });
+        // reject requests the function would skip before paying for a run
+        RandomnessRequests.validate(params);
+
+        // the function decodes these params, see RandomnessRequest in switchboard-function/src/requests.rs
+        address callId = ISwitchboard(switchboardAddress).callFunction{
+            value: msg.value
+        }(functionId, abi.encode(params));
+
+        Request storage request = requests[requestId];
+        request.callId = callId;
+        request.sender = msg.sender;
+        request.params = params;
+
+        emit RandomnessRequested(requestId, callId, msg.sender);
+    }
+
+    // dice are a range from 1 to the number of sides
+    function requestDice(
+        uint32 count,
+        uint256 sides
+    ) external payable returns (uint256) {
+        return
+            requestRandomness(
+                RandomnessRequests.RANGE,
+                count,
+                1,
+                sides,
+                new uint256[](0)
+            );
+    }
+
+    // Deliver the values for a request along with the proof they were derived from. The values
+    // are recomputed from the proven output, so they're only accepted if the function derived
+    // them honestly.
+    function fulfillRequest(
+        uint256 requestId,
+        VRF.Proof memory proof,
+        uint256[] memory values
+    ) external {
+        checkFunctionId();
+        checkVrfKey(proof.pk);
+
+        Request storage request = requests[requestId];
+        if (request.sender == address(0) || request.fulfilled) {
+            revert("Invalid request");
+        }
+
+        // the seed is bound to the call and the request, so a proof for one can't answer another
+        uint256 output = VRF.verify(
+            proof,
+            requestSeed(request.callId, requestId)
+        );
+        uint256[] memory expected = RandomnessRequests.expand(
+            request.params,
+            output
+        );
+        if (keccak256(abi.encode(values)) != keccak256(abi.encode(expected))) {
+            revert("Invalid values");
+        }
+
+        // the values only live in the event, storing them would cost more than the rest of the
+        // fulfillment
+        request.fulfilled = true;
+
+        emit RequestFulfilled(requestId, values);
+    }
 
-    function callback(uint256 value) external {
+    // set the function id ahead of the first callback so requests can be made - can only be called once!
+    // @NOTE: do not do this in production, this is just for ease of testing
+    function initialize(address _functionId) external {
+        if (functionId != address(0)) {
+            revert("Already initialized");
+        }
+        functionId = _functionId;
+    }
+
+    function getRequest(
+        uint256 requestId
+    ) external view returns (Request memory) {
+        return requests[requestId];
+    }
+
+    // The VRF key is derived from the enclave's sealing key, which differs per machine and per
+    // build. Whoever can swap it could pick whichever machine's output they like, so only the
+    // owner can, and a new key only takes effect VRF_KEY_ROTATION_DELAY after it's proposed. The
+    // first key takes effect right away, nothing has been proven before it.
+    function proposeVrfKey(uint256[2] memory publicKey) external {
+        if (msg.sender != owner) {
+            revert("Not owner");
+        }
+        if (vrfPublicKey[0] == 0 && vrfPublicKey[1] == 0) {
+            setVrfPublicKey(publicKey);
+            return;
+        }
+        pendingVrfPublicKey = publicKey;
+        pendingVrfKeyActivation = block.timestamp + VRF_KEY_ROTATION_DELAY;
+        emit VrfKeyProposed(publicKey, pendingVrfKeyActivation);
+    }
+
+    // swap in the proposed key once its delay is up, anyone can call this
+    function activateVrfKey() external {
+        if (
+            pendingVrfKeyActivation == 0 ||
+            block.timestamp < pendingVrfKeyActivation
+        ) {
+            revert("No key to activate");
+        }
+        setVrfPublicKey(pendingVrfPublicKey);
+        delete pendingVrfPublicKey;
+        pendingVrfKeyActivation = 0;
+    }
+
+    function transferOwnership(address newOwner) external {
+        if (msg.sender != owner) {
+            revert("Not owner");
+        }
+        owner = newOwner;
+    }
+
+    function getVrfPublicKey() external view returns (uint256[2] memory) {
+        return vrfPublicKey;
+    }
+
+    function getPendingVrfPublicKey() external view returns (uint256[2] memory) {
+        return pendingVrfPublicKey;
+    }
+
+    // ERC-165, the function also asks for the selector of each call it makes before emitting them
+    function supportsInterface(bytes4 interfaceId) external view returns (bool) {
+        return
+            interfaceId == this.supportsInterface.selector ||
+            interfaceId == this.callback.selector ||
+            interfaceId == this.fulfillRequest.selector ||
+            interfaceId == this.round.selector ||
+            interfaceId == this.getVrfPublicKey.selector;
+    }
+
+    // keccak256(abi.encode(chainId, receiver, callId, orderId)) with no call and the round as the
+    // order, see switchboard-function/src/randomness.rs
+    function seed(uint256 _round) public view returns (uint256) {
+        return
+            uint256(
+                keccak256(
+                    abi.encode(block.chainid, address(this), address(0), _round)
+                )
+            );
+    }
+
+    // same derivation as seed() with the request's call id and the request id as the order
+    function requestSeed(
+        address callId,
+        uint256 requestId
+    ) public view returns (uint256) {
+        return
+            uint256(
+                keccak256(
+                    abi.encode(block.chainid, address(this), callId, requestId)
+                )
+            );
+    }
+
+    function checkFunctionId() internal {
         // extract the sender from the callback, this validates that the switchboard contract called this function
         address encodedFunctionId = Switchboard.getEncodedFunctionId();
 
@@ -34,11 +273,24 @@ contract SwitchboardReceiver {
         if (encodedFunctionId != functionId) {
             revert("Invalid sender");
         }
+    }
 
-        // set the random value
-        randomValue = value;
+    function checkVrfKey(uint256[2] memory publicKey) internal view {
+        if (vrfPublicKey[0] == 0 && vrfPublicKey[1] == 0) {
+            revert("No VRF key");
+        }
+        // a key can't be swapped in and proven with in the same transaction
+        if (block.number == vrfKeyBlock) {
+            revert("VRF key just changed");
+        }
+        if (publicKey[0] != vrfPublicKey[0] || publicKey[1] != vrfPublicKey[1]) {
+            revert("Invalid VRF key");
+        }
+    }
 
-        // emit an event
-        emit NewRandomValue(value);
+    function setVrfPublicKey(uint256[2] memory publicKey) internal {
+        vrfPublicKey = publicKey;
+        vrfKeyBlock = block.number;
+        emit VrfKeyRotated(round, publicKey);
     }
 }
@@ -0,0 +1,211 @@
+//SPDX-License-Identifier: UNLICENSED
+pragma solidity ^0.8.9;
+
+// Verifies the ECVRF proofs produced by the Switchboard Function (switchboard-function/src/vrf.rs).
+// Scalar multiplication on secp256k1 is far too expensive to do in Solidity, so the proof carries
+// the intermediate points and each of them is checked with the ecrecover precompile instead.
+library VRF {
+    // secp256k1 field modulus and group order
+    uint256 internal constant FIELD_SIZE =
+        0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC2F;
+    uint256 internal constant GROUP_ORDER =
+        0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141;
+
+    // domain separators, these must match the function
+    uint256 internal constant HASH_TO_CURVE_PREFIX = 1;
+    uint256 internal constant CHALLENGE_PREFIX = 2;
+    uint256 internal constant OUTPUT_PREFIX = 3;
+
+    struct Proof {
+        // the function's VRF public key
+        uint256[2] pk;
+        uint256[2] gamma;
+        uint256 c;
+        uint256 s;
+        // address of c * pk + s * G
+        address uWitness;
+        // c * gamma
+        uint256[2] cGammaWitness;
+        // s * hashToCurve(pk, seed)
+        uint256[2] sHashWitness;
+    }
+
+    error InvalidPoint(uint256 x, uint256 y);
+    error InvalidProof();
+
+    // Verify `proof` for `seed` and return its random output, reverting if it doesn't hold
+    function verify(
+        Proof memory proof,
+        uint256 seed
+    ) internal view returns (uint256) {
+        requireOnCurve(proof.pk);
+        requireOnCurve(proof.gamma);
+        requireOnCurve(proof.cGammaWitness);
+        requireOnCurve(proof.sHashWitness);
+
+        // u = c * pk + s * G, only its address is needed for the challenge
+        if (
+            !verifyLinearCombinationWithGenerator(
+                proof.c,
+                proof.pk,
+                proof.s,
+                proof.uWitness
+            )
+        ) {
+            revert InvalidProof();
+        }
+
+        // v = c * gamma + s * hash
+        uint256[2] memory hash = hashToCurve(proof.pk, seed);
+        if (
+            !ecmulVerify(proof.gamma, proof.c, proof.cGammaWitness) ||
+            !ecmulVerify(hash, proof.s, proof.sHashWitness) ||
+            proof.cGammaWitness[0] == proof.sHashWitness[0]
+        ) {
+            revert InvalidProof();
+        }
+        uint256[2] memory v = affineAdd(proof.cGammaWitness, proof.sHashWitness);
+
+        uint256 c = uint256(
+            keccak256(
+                abi.encodePacked(
+                    CHALLENGE_PREFIX,
+                    hash,
+                    proof.pk,
+                    proof.gamma,
+                    v,
+                    proof.uWitness
+                )
+            )
+        );
+        if (c != proof.c) {
+            revert InvalidProof();
+        }
+
+        return uint256(keccak256(abi.encodePacked(OUTPUT_PREFIX, proof.gamma)));
+    }
+
+    // Try-and-increment: hash (pk, seed, counter) until it's the x coordinate of a point, taking
+    // the even y
+    function hashToCurve(
+        uint256[2] memory pk,
+        uint256 seed
+    ) internal view returns (uint256[2] memory) {
+        for (uint256 counter = 0; ; counter++) {
+            uint256 x = uint256(
+                keccak256(
+                    abi.encodePacked(HASH_TO_CURVE_PREFIX, pk, seed, counter)
+                )
+            );
+            if (x >= FIELD_SIZE) {
+                continue;
+            }
+            uint256 ySquared = addmod(
+                mulmod(mulmod(x, x, FIELD_SIZE), x, FIELD_SIZE),
+                7,
+                FIELD_SIZE
+            );
+            // FIELD_SIZE = 3 mod 4, so this is the square root if there is one
+            uint256 y = expmod(ySquared, (FIELD_SIZE + 1) / 4);
+            if (mulmod(y, y, FIELD_SIZE) != ySquared) {
+                continue;
+            }
+            if (y % 2 == 1) {
+                y = FIELD_SIZE - y;
+            }
+            return [x, y];
+        }
+    }
+
+    function requireOnCurve(uint256[2] memory p) private pure {
+        bool onCurve = p[0] < FIELD_SIZE &&
+            p[1] < FIELD_SIZE &&
+            mulmod(p[1], p[1], FIELD_SIZE) ==
+            addmod(
+                mulmod(mulmod(p[0], p[0], FIELD_SIZE), p[0], FIELD_SIZE),
+                7,
+                FIELD_SIZE
+            );
+        if (!onCurve) {
+            revert InvalidPoint(p[0], p[1]);
+        }
+    }
+
+    // ecrecover(h, v, r, s) returns the address of r^-1 * (s * R - h * G). With r = p.x,
+    // s = c * p.x and h = -(s * p.x) that's the address of c * p + s * G.
+    function verifyLinearCombinationWithGenerator(
+        uint256 c,
+        uint256[2] memory p,
+        uint256 s,
+        address lcWitness
+    ) private pure returns (bool) {
+        if (lcWitness == address(0)) {
+            return false;
+        }
+        uint8 v = p[1] % 2 == 0 ? 27 : 28;
+        bytes32 pseudoHash = bytes32(
+            GROUP_ORDER - mulmod(p[0], s, GROUP_ORDER)
+        );
+        bytes32 pseudoSignature = bytes32(mulmod(c, p[0], GROUP_ORDER));
+        return ecrecover(pseudoHash, v, bytes32(p[0]), pseudoSignature) == lcWitness;
+    }
+
+    // With h = 0 ecrecover returns the address of r^-1 * s * R, so passing s = scalar * p.x
+    // checks that `product` is scalar * p
+    function ecmulVerify(
+        uint256[2] memory multiplicand,
+        uint256 scalar,
+        uint256[2] memory product
+    ) private pure returns (bool) {
+        if (scalar % GROUP_ORDER == 0) {
+            return false;
+        }
+        uint8 v = multiplicand[1] % 2 == 0 ? 27 : 28;
+        bytes32 scalarTimesX = bytes32(
+            mulmod(scalar, multiplicand[0], GROUP_ORDER)
+        );
+        address actual = ecrecover(
+            bytes32(0),
+            v,
+            bytes32(multiplicand[0]),
+            scalarTimesX
+        );
+        address expected = address(
+            uint160(uint256(keccak256(abi.encodePacked(product))))
+        );
+        return actual == expected;
+    }
+
+    // p1 + p2 for points with distinct x coordinates
+    function affineAdd(
+        uint256[2] memory p1,
+        uint256[2] memory p2
+    ) private view returns (uint256[2] memory) {
+        uint256 dx = addmod(p2[0], FIELD_SIZE - p1[0], FIELD_SIZE);
+        uint256 dy = addmod(p2[1], FIELD_SIZE - p1[1], FIELD_SIZE);
+        uint256 lambda = mulmod(dy, expmod(dx, FIELD_SIZE - 2), FIELD_SIZE);
+        uint256 x3 = addmod(
+            mulmod(lambda, lambda, FIELD_SIZE),
+            FIELD_SIZE - addmod(p1[0], p2[0], FIELD_SIZE),
+            FIELD_SIZE
+        );
+        uint256 y3 = addmod(
+            mulmod(lambda, addmod(p1[0], FIELD_SIZE - x3, FIELD_SIZE), FIELD_SIZE),
+            FIELD_SIZE - p1[1],
+            FIELD_SIZE
+        );
+        return [x3, y3];
+    }
+
+    // base^exponent mod FIELD_SIZE via the modexp precompile
+    function expmod(
+        uint256 base,
+        uint256 exponent
+    ) private view returns (uint256) {
+        (bool success, bytes memory output) = address(0x05).staticcall(
+            abi.encode(32, 32, 32, base, exponent, FIELD_SIZE)
+        );
+        require(success, "expmod failed");
+        return abi.decode(output, (uint256));
+    }
+}
@@ -0,0 +1,113 @@
+//SPDX-License-Identifier: UNLICENSED
+pragma solidity ^0.8.9;
+
+import {Test} from "forge-std/Test.sol";
+import {RandomnessRequests} from "../src/RandomnessRequests.sol";
+
+// Checks the expansion against the vectors in switchboard-function/src/requests.rs
+contract RandomnessRequestsTest is Test {
+    // output of the VRF test vector, see VRF.t.sol
+    uint256 constant OUTPUT =
+        0x2a57d1ad5fa87911f3254d044a0fe6349c45569e0aa102193be26b24d6d4ecfa;
+
+    function request(
+        uint8 kind,
+        uint32 count,
+        uint256 min,
+        uint256 max,
+        uint256[] memory weights
+    ) internal pure returns (RandomnessRequests.Request memory) {
+        return
+            RandomnessRequests.Request({
+                requestId: 1,
+                kind: kind,
+                count: count,
+                min: min,
+                max: max,
+                weights: weights
+            });
+    }
+
+    // external so expectRevert can catch the library's reverts
+    function expand(
+        RandomnessRequests.Request memory r
+    ) external pure returns (uint256[] memory) {
+        return RandomnessRequests.expand(r, OUTPUT);
+    }
+
+    function testExpandsDice() public {
+        uint256[] memory values = this.expand(
+            request(RandomnessRequests.RANGE, 5, 1, 6, new uint256[](0))
+        );
+        uint256[5] memory expected = [uint256(1), 1, 1, 4, 1];
+        assertEq(values.length, expected.length);
+        for (uint256 i = 0; i < expected.length; i++) {
+            assertEq(values[i], expected[i]);
+        }
+    }
+
+    function testExpandsShuffles() public {
+        uint256[] memory values = this.expand(
+            request(RandomnessRequests.SHUFFLE, 8, 0, 0, new uint256[](0))
+        );
+        uint256[8] memory expected = [uint256(7), 3, 5, 2, 4, 0, 1, 6];
+        assertEq(values.length, expected.length);
+        for (uint256 i = 0; i < expected.length; i++) {
+            assertEq(values[i], expected[i]);
+        }
+    }
+
+    function testExpandsWeightedPicks() public {
+        uint256[] memory weights = new uint256[](3);
+        weights[0] = 1;
+        weights[2] = 3;
+        uint256[] memory values = this.expand(
+            request(RandomnessRequests.WEIGHTED, 4, 0, 0, weights)
+        );
+        uint256[4] memory expected = [uint256(2), 0, 2, 2];
+        assertEq(values.length, expected.length);
+        for (uint256 i = 0; i < expected.length; i++) {
+            assertEq(values[i], expected[i]);
+        }
+    }
+
+    function testExpandsTheFullRange() public {
+        uint256[] memory values = this.expand(
+            request(
+                RandomnessRequests.RANGE,
+                1,
+                0,
+                type(uint256).max,
+                new uint256[](0)
+            )
+        );
+        assertEq(values[0], uint256(keccak256(abi.encode(OUTPUT, uint256(0)))));
+    }
+
+    function testRejectsInvalidRequests() public {
+        vm.expectRevert(
+            abi.encodeWithSelector(RandomnessRequests.UnknownKind.selector, 3)
+        );
+        this.expand(request(3, 1, 0, 0, new uint256[](0)));
+
+        vm.expectRevert(
+            abi.encodeWithSelector(RandomnessRequests.InvalidCount.selector, 0)
+        );
+        this.expand(
+            request(RandomnessRequests.SHUFFLE, 0, 0, 0, new uint256[](0))
+        );
+
+        vm.expectRevert(
+            abi.encodeWithSelector(RandomnessRequests.InvalidRange.selector, 2, 1)
+        );
+        this.expand(
+            request(RandomnessRequests.RANGE, 1, 2, 1, new uint256[](0))
+        );
+
+        uint256[] memory weights = new uint256[](2);
+        weights[0] = type(uint256).max;
+        weights[1] = 1;
+        vm.expectRevert(RandomnessRequests.InvalidWeights.selector);
+        this.expand(request(RandomnessRequests.WEIGHTED, 1, 0, 0, weights));
+    }
+}
@@ -0,0 +1,55 @@
+//SPDX-License-Identifier: UNLICENSED
+pragma solidity ^0.8.9;
+
+import {Test} from "forge-std/Test.sol";
+import {SwitchboardReceiver} from "../src/SwitchboardReceiver.sol";
+
+// Checks who can change the VRF key and when a new one takes effect
+contract SwitchboardReceiverTest is Test {
+    SwitchboardReceiver receiver;
+
+    function setUp() public {
+        receiver = new SwitchboardReceiver(address(0x5b));
+    }
+
+    function key(uint256 x) internal pure returns (uint256[2] memory) {
+        return [x, x + 1];
+    }
+
+    function testFirstKeyTakesEffectRightAway() public {
+        receiver.proposeVrfKey(key(1));
+        assertEq(receiver.getVrfPublicKey()[0], 1);
+        assertEq(receiver.pendingVrfKeyActivation(), 0);
+    }
+
+    function testRotationWaitsOutTheDelay() public {
+        receiver.proposeVrfKey(key(1));
+        receiver.proposeVrfKey(key(5));
+        assertEq(receiver.getVrfPublicKey()[0], 1);
+        assertEq(receiver.getPendingVrfPublicKey()[0], 5);
+
+        vm.expectRevert("No key to activate");
+        receiver.activateVrfKey();
+
+        vm.warp(block.timestamp + receiver.VRF_KEY_ROTATION_DELAY());
+        // anyone can activate it once the delay is up
+        vm.prank(address(0xbeef));
+        receiver.activateVrfKey();
+        assertEq(receiver.getVrfPublicKey()[0], 5);
+        assertEq(receiver.pendingVrfKeyActivation(), 0);
+
+        vm.expectRevert("No key to activate");
+        receiver.activateVrfKey();
+    }
+
+    function testOnlyTheOwnerProposesKeys() public {
+        vm.prank(address(0xbeef));
+        vm.expectRevert("Not owner");
+        receiver.proposeVrfKey(key(1));
+
+        receiver.transferOwnership(address(0xbeef));
+        vm.prank(address(0xbeef));
+        receiver.proposeVrfKey(key(1));
+        assertEq(receiver.getVrfPublicKey()[0], 1);
+    }
+}
@@ -0,0 +1,83 @@
+//SPDX-License-Identifier: UNLICENSED
+pragma solidity ^0.8.9;
+
+import {Test} from "forge-std/Test.sol";
+import {VRF} from "../src/VRF.sol";
+
+// Checks the verifier against the test vector in switchboard-function/src/vrf.rs
+contract VRFTest is Test {
+    uint256 constant SEED = 42;
+    uint256 constant OUTPUT =
+        0x2a57d1ad5fa87911f3254d044a0fe6349c45569e0aa102193be26b24d6d4ecfa;
+
+    function vector() internal pure returns (VRF.Proof memory proof) {
+        proof.pk = [
+            0xa93d9079cc37018c49832874543e7d2148e7da02f01412e5752cabe98158624f,
+            0xe6f9559a1ea7293f8181942b90654d78dfa6890293fbd701109df5e6bef3032b
+        ];
+        proof.gamma = [
+            0xf552af11815735decfbe774525a566bd4644d6d05dd302e581a15a77a62913f3,
+            0xdb63759933d9b07adbfa1c2d0f635aed372f77b25c6a2d1d40e42b700effac71
+        ];
+        proof.c = 0xba1eccc86a237901b5c354320deb8d639fa7beeccb8c1bd161c0eb868557d109;
+        proof.s = 0x6f5f71f4598aa0973df45eab8e139181a5281679b932effd2ee81b9e83036c50;
+        proof.uWitness = 0x45D8e2E26a25DF27A64Bec3fb4B381941bAF39c2;
+        proof.cGammaWitness = [
+            0xd77c9bde841e538cca9bfc15386f0c3bd194fdc5f05aa33755e7cb62dfb6b8b5,
+            0xcfe3a095d6d24b561f3b264b49d3723f3b4b7b73d03b6886570ff828dc0f891d
+        ];
+        proof.sHashWitness = [
+            0x3750243f104e006190229e671c4b969a2b2a74fe1358dcfa02f8dcb916e1fc76,
+            0xed90d245e8d0ed387c722fe241a0c71f8ff58ebdf05994e54595e400bc954459
+        ];
+    }
+
+    // external so expectRevert can catch the library's reverts
+    function verify(
+        VRF.Proof memory proof,
+        uint256 seed
+    ) external view returns (uint256) {
+        return VRF.verify(proof, seed);
+    }
+
+    function testHashToCurve() public {
+        uint256[2] memory hash = VRF.hashToCurve(vector().pk, SEED);
+        assertEq(
+            hash[0],
+            0x4c6d90dc8bad820c248a65361a828b06d5477f5d7c2d8270afdf5650a55e3357
+        );
+        assertEq(
+            hash[1],
+            0xb789667dd73a92d796fec0577b2f74351be8a554fb2584e8068db7178fba1ad4
+        );
+    }
+
+    function testVerifiesTestVector() public {
+        assertEq(this.verify(vector(), SEED), OUTPUT);
+    }
+
+    function testRejectsOtherSeeds() public {
+        vm.expectRevert(VRF.InvalidProof.selector);
+        this.verify(vector(), SEED + 1);
+    }
+
+    function testRejectsTamperedProofs() public {
+        VRF.Proof memory proof = vector();
+        proof.s += 1;
+        vm.expectRevert(VRF.InvalidProof.selector);
+        this.verify(proof, SEED);
+    }
+
+    function testRejectsPointsOffTheCurve() public {
+        VRF.Proof memory proof = vector();
+        proof.gamma[1] += 1;
+        vm.expectRevert(
+            abi.encodeWithSelector(
+                VRF.InvalidPoint.selector,
+                proof.gamma[0],
+                proof.gamma[1]
+            )
+        );
+        this.verify(proof, SEED);
+    }
+}
@@ -4,7 +4,9 @@ async function main() {
   const diamondAddress =
     process.env.SWITCHBOARD_ADDRESS ?? process.env.DIAMOND_ADDRESS ?? "";
 
-  const contract = await ethers.deployContract("SwitchboardReceiver");
+  const contract = await ethers.deployContract("SwitchboardReceiver", [
+    diamondAddress,
+  ]);
   await contract.deployed();
   console.log("SwitchboardReceiver deployed to:", contract.address);
   console.log(
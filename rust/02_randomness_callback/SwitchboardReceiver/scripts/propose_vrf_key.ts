@@ -0,0 +1,47 @@
+import { ethers } from "hardhat";
+
+// Proposes the VRF public key the function printed as the receiver's next key, or activates the
+// pending one once VRF_KEY_ROTATION_DELAY is up. Must be run by the receiver's owner.
+async function main() {
+  const [deployer] = await ethers.getSigners();
+
+  const receiver = process.env.SWITCHBOARD_RECEIVER_ADDRESS ?? "";
+  const x = process.env.VRF_PUBLIC_KEY_X ?? "";
+  const y = process.env.VRF_PUBLIC_KEY_Y ?? "";
+
+  if (!receiver) {
+    throw new Error(
+      "Please set the receiver address with: export SWITCHBOARD_RECEIVER_ADDRESS=..."
+    );
+  }
+
+  console.log("Account:", deployer.address);
+  const receiverContract = await ethers.getContractAt(
+    "SwitchboardReceiver",
+    receiver
+  );
+
+  // without a key, activate the pending one
+  if (!x || !y) {
+    const activation = await receiverContract.pendingVrfKeyActivation();
+    console.log("Pending key activates at:", activation.toString());
+    const tx = await receiverContract.activateVrfKey();
+    await tx.wait();
+    console.log("Activated:", await receiverContract.getVrfPublicKey());
+    return;
+  }
+
+  const tx = await receiverContract.proposeVrfKey([x, y]);
+  await tx.wait();
+  console.log("Current key:", await receiverContract.getVrfPublicKey());
+  console.log("Pending key:", await receiverContract.getPendingVrfPublicKey());
+  console.log(
+    "Pending key activates at:",
+    (await receiverContract.pendingVrfKeyActivation()).toString()
+  );
+}
+
+main().catch((error) => {
+  console.error(error);
+  process.exitCode = 1;
+});
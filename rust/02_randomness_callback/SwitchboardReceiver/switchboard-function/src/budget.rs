@@ -0,0 +1,80 @@
+use crate::*;
+
+// Rough gas costs used to pack callbacks under the gas limit. The round's callback verifies a
+// proof and stores the value. A fulfillment verifies its own proof and marks the request, then
+// each value pays for its calldata, its share of the event and the draws that recompute it, and a
+// weighted pick walks the weights once per value. The overhead covers the Switchboard contract's
+// verification of the emitted transaction.
+pub const CALLBACK_GAS: u64 = 150_000;
+pub const FULFILL_REQUEST_GAS: u64 = 150_000;
+pub const GAS_PER_VALUE: u64 = 2_000;
+pub const GAS_PER_WEIGHT: u64 = 150;
+pub const EMIT_OVERHEAD_GAS: u64 = 150_000;
+
+// The gas a request's fulfillRequest costs at most
+pub fn request_gas(request: &RandomnessRequest) -> u64 {
+    let count = request.count as u64;
+    let weights = match RequestKind::try_from(request.kind) {
+        Ok(RequestKind::Weighted) => request.weights.len() as u64,
+        _ => 0,
+    };
+    FULFILL_REQUEST_GAS + count * (GAS_PER_VALUE + weights * GAS_PER_WEIGHT)
+}
+
+// Gas left for callbacks once the transaction's overhead is paid for
+#[derive(Debug, Clone)]
+pub struct GasBudget {
+    remaining: U256,
+}
+
+impl GasBudget {
+    pub fn new(gas_limit: U256) -> Self {
+        GasBudget {
+            remaining: gas_limit.saturating_sub(EMIT_OVERHEAD_GAS.into()),
+        }
+    }
+
+    pub fn fits(&self, gas: u64) -> bool {
+        self.remaining >= gas.into()
+    }
+
+    // Take `gas` from the budget if there's enough left
+    pub fn spend(&mut self, gas: u64) -> bool {
+        if !self.fits(gas) {
+            return false;
+        }
+        self.remaining -= gas.into();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_costliest_request_fits_next_to_the_callback() {
+        let weights = vec![U256::one(); MAX_WEIGHTS];
+        let request = RandomnessRequest::weighted(U256::one(), MAX_COUNT, weights);
+        assert!(request.validate().is_ok());
+
+        // the gas limit main.rs emits with
+        let mut budget = GasBudget::new(U256::from(1_000_000));
+        assert!(budget.spend(CALLBACK_GAS));
+        assert!(budget.spend(request_gas(&request)));
+    }
+
+    #[test]
+    fn only_weighted_requests_pay_for_weights() {
+        let mut request = RandomnessRequest::dice(U256::one(), 4, 6);
+        assert_eq!(
+            request_gas(&request),
+            FULFILL_REQUEST_GAS + 4 * GAS_PER_VALUE
+        );
+        request.weights = vec![U256::one(); 10];
+        assert_eq!(
+            request_gas(&request),
+            FULFILL_REQUEST_GAS + 4 * GAS_PER_VALUE
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use crate::runner::*;
+
+use ethers::{
+    abi::{AbiDecode, AbiType, Detokenize, Tokenizable},
+    prelude::{ContractCall, LocalWallet},
+    providers::Middleware,
+    types::{Address, Bytes, U256},
+    utils::hex,
+};
+
+// Dry runs read chain state and build every callback as usual, but print them instead of emitting.
+// Enable with DRY_RUN=1 or by passing --dry-run. DRY_RUN isn't passed through to the enclave, so
+// this can't be switched on for a deployed function. A dry run doesn't need the enclave or the
+// inputs the Switchboard runtime hands a function (FUNCTION_KEY, VERIFIER, ...), see DryRunner.
+pub fn is_dry_run() -> bool {
+    let from_env = std::env::var("DRY_RUN")
+        .map(|x| x == "1" || x.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    from_env || std::env::args().any(|x| x == "--dry-run")
+}
+
+// Print the calls that would have been emitted along with their raw ABI encoded calldata
+pub fn print_calls<M: Middleware, D: Detokenize>(
+    to: Address,
+    expiration_time_seconds: U256,
+    gas_limit: U256,
+    calls: &[ContractCall<M, D>],
+) {
+    println!(
+        "DRY RUN: {} call(s) to {:?}, gas limit {}, expiring at {}",
+        calls.len(),
+        to,
+        gas_limit,
+        expiration_time_seconds
+    );
+    for (i, call) in calls.iter().enumerate() {
+        let calldata = call.calldata().unwrap_or_default();
+        println!(
+            "  [{}] {} ({} bytes)",
+            i,
+            call.function.signature(),
+            calldata.len()
+        );
+        println!("      0x{}", hex::encode(&calldata));
+    }
+}
+
+// Stands in for the SGX runner on dry runs. Nothing gets signed, so the wallet is a throwaway.
+// Params of user triggered calls can be previewed by setting FUNCTION_PARAMS and
+// FUNCTION_CALL_IDS, JSON arrays of the hex encoded params and their call ids, the same way the
+// runtime passes them.
+pub struct DryRunner {
+    wallet: LocalWallet,
+    params: Vec<(Bytes, Address)>,
+}
+
+impl DryRunner {
+    pub fn from_env() -> Result<Self, RunnerError> {
+        let params: Vec<Bytes> = match std::env::var("FUNCTION_PARAMS") {
+            Ok(value) => serde_json::from_str(&value)
+                .map_err(|e| RunnerError(format!("FUNCTION_PARAMS is invalid: {}", e)))?,
+            Err(_) => vec![],
+        };
+        let call_ids: Vec<Address> = match std::env::var("FUNCTION_CALL_IDS") {
+            Ok(value) => serde_json::from_str(&value)
+                .map_err(|e| RunnerError(format!("FUNCTION_CALL_IDS is invalid: {}", e)))?,
+            Err(_) => vec![],
+        };
+        if params.len() != call_ids.len() {
+            return Err(RunnerError(format!(
+                "{} FUNCTION_PARAMS for {} FUNCTION_CALL_IDS",
+                params.len(),
+                call_ids.len()
+            )));
+        }
+        Ok(DryRunner {
+            wallet: LocalWallet::new(&mut rand::thread_rng()),
+            params: params.into_iter().zip(call_ids).collect(),
+        })
+    }
+}
+
+impl FunctionRunner for DryRunner {
+    fn enclave_wallet(&self) -> LocalWallet {
+        self.wallet.clone()
+    }
+
+    fn params<T: AbiDecode + AbiType + Tokenizable>(
+        &self,
+    ) -> Vec<(Result<T, RunnerError>, Address)> {
+        self.params
+            .iter()
+            .map(|(param, call_id)| {
+                (
+                    T::decode(param).map_err(|e| RunnerError(e.to_string())),
+                    *call_id,
+                )
+            })
+            .collect()
+    }
+
+    fn emit(
+        &self,
+        to: Address,
+        expiration_time_seconds: U256,
+        gas_limit: U256,
+        calls: Vec<Callback>,
+    ) -> Result<(), RunnerError> {
+        print_calls(to, expiration_time_seconds, gas_limit, &calls);
+        Ok(())
+    }
+
+    fn emit_resolve(
+        &self,
+        to: Address,
+        expiration_time_seconds: U256,
+        gas_limit: U256,
+        calls: Vec<Callback>,
+        call_ids: Vec<Address>,
+    ) -> Result<(), RunnerError> {
+        print_calls(to, expiration_time_seconds, gas_limit, &calls);
+        println!("DRY RUN: resolving {} call id(s)", call_ids.len());
+        for call_id in call_ids {
+            println!("  {:?}", call_id);
+        }
+        Ok(())
+    }
+}
@@ -1,26 +1,62 @@
+pub mod target;
+pub use target::*;
+pub mod dry_run;
+pub use dry_run::*;
+pub mod runner;
+pub use runner::*;
+pub mod randomness;
+pub use randomness::*;
+pub mod vrf;
+pub use vrf::*;
+pub mod requests;
+pub use requests::*;
+pub mod budget;
+pub use budget::*;
+
 use ethers::{
-    prelude::{abigen, ContractCall, SignerMiddleware},
+    contract::EthCall,
+    prelude::{abigen, SignerMiddleware},
     providers::{Http, Provider},
+    signers::Signer,
     types::U256,
 };
-use rand;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
-use switchboard_evm::sdk::{EVMFunctionRunner, EVMMiddleware};
+
+// define the abi for the functions in the contract you'll be calling
+// -- "callback" takes a VRF proof, the receiver verifies it and derives the random value itself
+// -- "fulfillRequest" answers a user's request with a proof and the values expanded from it
+// -- the views tell us which round we're proving for and which VRF key the receiver accepts
+abigen!(
+    Receiver,
+    r#"[
+        struct Proof { uint256[2] pk; uint256[2] gamma; uint256 c; uint256 s; address uWitness; uint256[2] cGammaWitness; uint256[2] sHashWitness; }
+        function callback(Proof)
+        function fulfillRequest(uint256, Proof, uint256[])
+        function round() view returns (uint256)
+        function getVrfPublicKey() view returns (uint256[2])
+    ]"#,
+);
+
+impl From<VrfProof> for Proof {
+    fn from(proof: VrfProof) -> Self {
+        Proof {
+            pk: proof.pk,
+            gamma: proof.gamma,
+            c: proof.c,
+            s: proof.s,
+            u_witness: proof.u_witness,
+            c_gamma_witness: proof.c_gamma_witness,
+            s_hash_witness: proof.s_hash_witness,
+        }
+    }
+}
 
 #[tokio::main(worker_threads = 12)]
 async fn main() {
-    // define the abi for the functions in the contract you'll be calling
-    // -- here it's just a function named "callback", expecting a random u256
-    abigen!(
-        Receiver,
-        r#"[
-            function callback(uint256)
-        ]"#,
-    );
-
     // Generates a new enclave wallet, pulls in relevant environment variables
-    let function_runner = EVMFunctionRunner::new().unwrap();
+    // -- dry runs don't need the enclave, see src/dry_run.rs
+    let function_runner = Runner::new().unwrap();
 
     // set the gas limit and expiration date
     let gas_limit = 1000000;
@@ -33,41 +69,328 @@ async fn main() {
 
     // create a client, wallet and middleware. This is just so we can create the contract instance and sign the txn.
     // @TODO: update the provider to whichever network you're using
-    let provider = Provider::<Http>::try_from("https://rpc.test.btcs.network").unwrap();
+    // -- RPC_URL overrides the default network, e.g. to point the function at a local dev chain
+    let rpc_url = std::env::var("RPC_URL").unwrap_or("https://rpc.test.btcs.network".to_string());
+    let provider = Provider::<Http>::try_from(rpc_url).unwrap();
     let client = Arc::new(
         SignerMiddleware::new_with_provider_chain(
             provider.clone(),
-            function_runner.enclave_wallet.clone(),
+            function_runner.enclave_wallet(),
         )
         .await
         .unwrap(),
     );
 
-    // @TODO: your target contract address here
-    // get contract address from docker env
-    let contract_address = env!("SWITCHBOARD_RECEIVER_ADDRESS")
-        .parse::<ethers::types::Address>()
-        .unwrap();
+    // resolve the receiver at runtime so one enclave build can serve any deployment
+    // -- a SWITCHBOARD_RECEIVER_ADDRESS set at build time is only used as a pinned default
+    let contract_address = resolve_receiver_address(
+        "SWITCHBOARD_RECEIVER_ADDRESS",
+        option_env!("SWITCHBOARD_RECEIVER_ADDRESS"),
+    )
+    .unwrap();
 
+    // make sure the receiver is deployed and exposes the calls we're about to make
+    verify_receiver(
+        client.clone(),
+        contract_address,
+        &[
+            CallbackCall::selector(),
+            FulfillRequestCall::selector(),
+            RoundCall::selector(),
+            GetVrfPublicKeyCall::selector(),
+        ],
+    )
+    .await
+    .unwrap();
+
+    let chain_id = client.signer().chain_id();
     let receiver_contract = Receiver::new(contract_address, client);
 
-    // generate a random number U256
-    let random: [u64; 4] = rand::random();
-    let random = U256(random);
+    // the long-lived VRF key, derived from the enclave's sealing key
+    // -- outside the enclave set VRF_TEST_KEY to prove with a test key, see src/vrf.rs. Dry runs
+    //    without one prove with a throwaway key
+    let vrf_key = match VrfSecretKey::load() {
+        Err(e) if is_dry_run() => {
+            println!("DRY RUN: {}, proving with a throwaway key", e);
+            VrfSecretKey::random()
+        }
+        key => key.unwrap(),
+    };
 
-    // call function
-    let contract_fn_call: ContractCall<EVMMiddleware<_>, _> = receiver_contract.callback(random);
+    // the receiver derives the seed from its current round, so the function can't choose it
+    let round = receiver_contract.round().call().await.unwrap();
+    let domain = RandomnessDomain::new(chain_id, contract_address).with_order(round);
+    let pinned_key = receiver_contract.get_vrf_public_key().call().await.unwrap();
 
-    // create a vec of contract calls to pass to the function runner
-    let calls = vec![contract_fn_call.clone()];
+    // a dry run's runner prints the calls instead of emitting them
+    if is_dry_run() {
+        let proof = vrf_key.prove(domain.seed());
+        println!(
+            "DRY RUN: round {}, seed {:#x}, random value {}",
+            round,
+            domain.seed(),
+            proof.output()
+        );
+        println!(
+            "DRY RUN: vrf public key ({:#x}, {:#x})",
+            proof.pk[0], proof.pk[1]
+        );
+    }
 
     // Emit the result
-    function_runner
-        .emit(
-            contract_address,
-            expiration_time.try_into().unwrap(),
+    emit_randomness(
+        &function_runner,
+        &receiver_contract,
+        &vrf_key,
+        domain,
+        pinned_key,
+        expiration_time,
+        gas_limit.into(),
+    )
+    .unwrap();
+}
+
+// Prove the random value for the round in `domain` and every request made since the last run,
+// then emit the callbacks through the runner
+fn emit_randomness<R: FunctionRunner>(
+    runner: &R,
+    receiver_contract: &Receiver<Client>,
+    vrf_key: &VrfSecretKey,
+    domain: RandomnessDomain,
+    pinned_key: [U256; 2],
+    expiration_time: u64,
+    gas_limit: U256,
+) -> Result<(), RunnerError> {
+    let proof = vrf_key.prove(domain.seed());
+
+    // only the receiver's owner can change its key, so a new build or machine can't pick which
+    // key proves a value. Until they propose this one, leave every call pending rather than
+    // emitting proofs the receiver would reject.
+    if pinned_key != proof.pk {
+        println!(
+            "the receiver doesn't accept this function's vrf key ({:#x}, {:#x}) yet, its owner has to propose it with proposeVrfKey",
+            proof.pk[0], proof.pk[1]
+        );
+        return Ok(());
+    }
+
+    // create a vec of contract calls to pass to the function runner
+    let mut calls = Vec::<Callback>::new();
+    let mut budget = GasBudget::new(gas_limit);
+
+    // call function
+    budget.spend(CALLBACK_GAS);
+    calls.push(receiver_contract.callback(proof.into()));
+
+    // answer each request with its own proof, seeded by its call id and request id so the receiver
+    // can check it against the request it stored
+    // -- invalid requests are resolved without a callback, the receiver validates them before
+    //    calling the function so they can never be answered
+    // -- the oldest requests go first, those past the gas budget stay pending for the next run
+    let dry_run = is_dry_run();
+    let mut requests = vec![];
+    let mut resolved = vec![];
+    for (param, call_id) in runner.params::<RandomnessRequest>() {
+        match param {
+            Ok(request) => requests.push((request, call_id)),
+            Err(e) => {
+                println!("call {:?}: failed to decode request, {}", call_id, e);
+                resolved.push(call_id);
+            }
+        }
+    }
+    requests.sort_by_key(|(request, _)| request.request_id);
+
+    let mut deferred = 0;
+    for (request, call_id) in requests {
+        if let Err(e) = request.validate() {
+            println!("request {}: {}", request.request_id, e);
+            resolved.push(call_id);
+            continue;
+        }
+        if !budget.spend(request_gas(&request)) {
+            deferred += 1;
+            continue;
+        }
+        let proof = vrf_key.prove(
+            domain
+                .with_call(call_id)
+                .with_order(request.request_id)
+                .seed(),
+        );
+        let values = request.expand(proof.output()).unwrap();
+        if dry_run {
+            println!(
+                "DRY RUN: request {} (call id {:?}) -> {:?}",
+                request.request_id, call_id, values
+            );
+        }
+        calls.push(receiver_contract.fulfill_request(request.request_id, proof.into(), values));
+        resolved.push(call_id);
+    }
+    if deferred > 0 {
+        println!(
+            "{} request(s) don't fit in the gas limit, leaving them for the next run",
+            deferred
+        );
+    }
+
+    runner.emit_resolve(
+        receiver_contract.address(),
+        expiration_time.into(),
+        gas_limit,
+        calls,
+        resolved,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::{AbiDecode, AbiEncode};
+    use ethers::types::{Address, Bytes};
+    use ethers::utils::keccak256;
+
+    #[test]
+    fn emits_a_verifiable_proof() {
+        let runner = RecordingRunner::new();
+        let receiver_contract = Receiver::new(Address::random(), runner.client());
+        let vrf_key = VrfSecretKey::from_bytes(keccak256("vrf key")).unwrap();
+        let domain =
+            RandomnessDomain::new(1, receiver_contract.address()).with_order(U256::from(7));
+        let seed = domain.seed();
+
+        emit_randomness(
+            &runner,
+            &receiver_contract,
+            &vrf_key,
+            domain,
+            vrf_key.public_key(),
+            1_000,
+            1_000_000.into(),
+        )
+        .unwrap();
+
+        let emitted = runner.emitted.borrow();
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(emitted[0].to, receiver_contract.address());
+        assert_eq!(emitted[0].expiration_time_seconds, U256::from(1_000));
+        assert_eq!(emitted[0].calls.len(), 1);
+        let callback = CallbackCall::decode(emitted[0].calls[0].calldata().unwrap()).unwrap();
+        let proof = vrf_key.prove(seed);
+        assert!(verify(vrf_key.public_key(), seed, &proof).is_ok());
+        assert_eq!(callback.0, Proof::from(proof));
+    }
+
+    #[test]
+    fn emits_nothing_until_the_receiver_accepts_the_key() {
+        let vrf_key = VrfSecretKey::from_bytes(keccak256("vrf key")).unwrap();
+        let old_key = VrfSecretKey::from_bytes(keccak256("old vrf key")).unwrap();
+        for pinned_key in [[U256::zero(), U256::zero()], old_key.public_key()] {
+            let runner = RecordingRunner::new();
+            let receiver_contract = Receiver::new(Address::random(), runner.client());
+            emit_randomness(
+                &runner,
+                &receiver_contract,
+                &vrf_key,
+                RandomnessDomain::new(1, receiver_contract.address()),
+                pinned_key,
+                1_000,
+                1_000_000.into(),
+            )
+            .unwrap();
+            assert!(runner.emitted.borrow().is_empty());
+        }
+    }
+
+    #[test]
+    fn fulfills_each_valid_request() {
+        let call_id = Address::random();
+        let request = RandomnessRequest::dice(U256::from(3), 2, 6);
+        let params = vec![
+            (request.clone().encode().into(), call_id),
+            // a request the receiver should never have accepted is resolved without a callback
+            (
+                RandomnessRequest::shuffle(U256::from(4), 0).encode().into(),
+                Address::random(),
+            ),
+            (vec![1u8, 2, 3].into(), Address::random()),
+        ];
+        let call_ids: Vec<Address> = params.iter().map(|x| x.1).collect();
+        let runner = RecordingRunner::with_params(params);
+        let receiver_contract = Receiver::new(Address::random(), runner.client());
+        let vrf_key = VrfSecretKey::from_bytes(keccak256("vrf key")).unwrap();
+
+        emit_randomness(
+            &runner,
+            &receiver_contract,
+            &vrf_key,
+            RandomnessDomain::new(1, receiver_contract.address()),
+            vrf_key.public_key(),
+            1_000,
+            1_000_000.into(),
+        )
+        .unwrap();
+
+        let emitted = runner.emitted.borrow();
+        let calls = &emitted[0].calls;
+        assert_eq!(calls.len(), 2);
+        let fulfilled = FulfillRequestCall::decode(calls[1].calldata().unwrap()).unwrap();
+        let seed = RandomnessDomain::new(1, receiver_contract.address())
+            .with_call(call_id)
+            .with_order(request.request_id)
+            .seed();
+        let proof = vrf_key.prove(seed);
+        assert_eq!(fulfilled.0, request.request_id);
+        assert_eq!(fulfilled.2, request.expand(proof.output()).unwrap());
+        assert_eq!(fulfilled.1, Proof::from(proof));
+        assert_eq!(
+            emitted[0].call_ids,
+            Some(vec![call_ids[2], call_ids[0], call_ids[1]])
+        );
+    }
+
+    #[test]
+    fn leaves_requests_past_the_gas_limit_pending() {
+        let weights = vec![U256::one(); MAX_WEIGHTS];
+        let params: Vec<(Bytes, Address)> = [3, 1, 2]
+            .into_iter()
+            .map(|request_id| {
+                let request =
+                    RandomnessRequest::weighted(U256::from(request_id), MAX_COUNT, weights.clone());
+                (request.encode().into(), Address::random())
+            })
+            .collect();
+        let call_ids: Vec<Address> = params.iter().map(|x| x.1).collect();
+        let runner = RecordingRunner::with_params(params);
+        let receiver_contract = Receiver::new(Address::random(), runner.client());
+        let vrf_key = VrfSecretKey::from_bytes(keccak256("vrf key")).unwrap();
+
+        // room for the callback and two of the costliest requests
+        let request_gas = request_gas(&RandomnessRequest::weighted(
+            U256::one(),
+            MAX_COUNT,
+            weights,
+        ));
+        let gas_limit = EMIT_OVERHEAD_GAS + CALLBACK_GAS + 2 * request_gas;
+        emit_randomness(
+            &runner,
+            &receiver_contract,
+            &vrf_key,
+            RandomnessDomain::new(1, receiver_contract.address()),
+            vrf_key.public_key(),
+            1_000,
             gas_limit.into(),
-            calls,
         )
         .unwrap();
+
+        // the oldest two are fulfilled, the newest waits for the next run
+        let emitted = runner.emitted.borrow();
+        let fulfilled: Vec<U256> = emitted[0].calls[1..]
+            .iter()
+            .map(|x| FulfillRequestCall::decode(x.calldata().unwrap()).unwrap().0)
+            .collect();
+        assert_eq!(fulfilled, vec![U256::from(1), U256::from(2)]);
+        assert_eq!(emitted[0].call_ids, Some(vec![call_ids[1], call_ids[2]]));
+    }
 }
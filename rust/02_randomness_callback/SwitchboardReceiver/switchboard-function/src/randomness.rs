@@ -0,0 +1,78 @@
+use ethers::{
+    abi::{encode, Token},
+    types::{Address, U256},
+    utils::keccak256,
+};
+
+// What a random value is for. Every field goes into the VRF seed, so a proof made for one request
+// can never be replayed as the value for another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RandomnessDomain {
+    pub chain_id: u64,
+    pub receiver: Address,
+    // zero for scheduled runs that aren't answering a call
+    pub call_id: Address,
+    // zero when the request has no order, scheduled VRF runs use the receiver's round
+    pub order_id: U256,
+}
+
+impl RandomnessDomain {
+    pub fn new(chain_id: u64, receiver: Address) -> Self {
+        RandomnessDomain {
+            chain_id,
+            receiver,
+            call_id: Address::zero(),
+            order_id: U256::zero(),
+        }
+    }
+
+    pub fn with_call(self, call_id: Address) -> Self {
+        RandomnessDomain { call_id, ..self }
+    }
+
+    pub fn with_order(self, order_id: U256) -> Self {
+        RandomnessDomain { order_id, ..self }
+    }
+
+    // keccak256(abi.encode(chainId, receiver, callId, orderId)), the seed a VRF proves for. A
+    // receiver that verifies proofs computes the same seed on-chain so the function can't pick
+    // its own.
+    pub fn seed(&self) -> U256 {
+        let preimage = encode(&[
+            Token::Uint(self.chain_id.into()),
+            Token::Address(self.receiver),
+            Token::Address(self.call_id),
+            Token::Uint(self.order_id),
+        ]);
+        U256::from_big_endian(&keccak256(preimage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seed_is_bound_to_every_field() {
+        let receiver = Address::repeat_byte(1);
+        let base = RandomnessDomain::new(1, receiver)
+            .with_call(Address::repeat_byte(2))
+            .with_order(U256::from(3));
+
+        let others = [
+            RandomnessDomain {
+                chain_id: 2,
+                ..base
+            },
+            RandomnessDomain {
+                receiver: Address::repeat_byte(9),
+                ..base
+            },
+            base.with_call(Address::repeat_byte(9)),
+            base.with_order(U256::from(4)),
+        ];
+        for other in others {
+            assert_ne!(base.seed(), other.seed());
+        }
+    }
+}
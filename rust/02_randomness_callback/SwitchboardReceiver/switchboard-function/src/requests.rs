@@ -0,0 +1,334 @@
+use ethers::{
+    abi::{encode, Token},
+    prelude::{EthAbiCodec, EthAbiType},
+    types::U256,
+    utils::keccak256,
+};
+use std::fmt;
+
+// Upper bounds on what a single request can ask for. The receiver recomputes every value, at these
+// bounds the costliest request still fits in a run next to the round's callback, see budget.rs.
+// Must match contracts/src/RandomnessRequests.sol.
+pub const MAX_COUNT: u32 = 32;
+pub const MAX_WEIGHTS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestKind {
+    // `count` values in [min, max], dice are a range from 1 to the number of sides
+    Range,
+    // a random permutation of the indices 0..count
+    Shuffle,
+    // `count` indices into `weights`, each picked with probability weight / sum(weights)
+    Weighted,
+}
+
+impl TryFrom<u8> for RequestKind {
+    type Error = RequestError;
+
+    fn try_from(kind: u8) -> Result<Self, Self::Error> {
+        match kind {
+            0 => Ok(RequestKind::Range),
+            1 => Ok(RequestKind::Shuffle),
+            2 => Ok(RequestKind::Weighted),
+            _ => Err(RequestError::UnknownKind(kind)),
+        }
+    }
+}
+
+impl From<RequestKind> for u8 {
+    fn from(kind: RequestKind) -> Self {
+        match kind {
+            RequestKind::Range => 0,
+            RequestKind::Shuffle => 1,
+            RequestKind::Weighted => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestError {
+    UnknownKind(u8),
+    // zero or more than MAX_COUNT values
+    InvalidCount(u32),
+    // min is above max
+    InvalidRange(U256, U256),
+    // no weights, too many of them, or a total that's zero or overflows
+    InvalidWeights,
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::UnknownKind(kind) => write!(f, "unknown request kind {}", kind),
+            RequestError::InvalidCount(count) => {
+                write!(f, "count {} is not between 1 and {}", count, MAX_COUNT)
+            }
+            RequestError::InvalidRange(min, max) => write!(f, "empty range [{}, {}]", min, max),
+            RequestError::InvalidWeights => write!(f, "weights must have a non-zero total"),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+// Params of a user triggered randomness request, encoded by the receiver's requestRandomness
+#[derive(Debug, Clone, PartialEq, Eq, EthAbiType, EthAbiCodec)]
+pub struct RandomnessRequest {
+    pub request_id: U256,
+    pub kind: u8,
+    pub count: u32,
+    // only used by range requests
+    pub min: U256,
+    pub max: U256,
+    // only used by weighted requests
+    pub weights: Vec<U256>,
+}
+
+impl RandomnessRequest {
+    pub fn range(request_id: U256, count: u32, min: U256, max: U256) -> Self {
+        RandomnessRequest {
+            request_id,
+            kind: RequestKind::Range.into(),
+            count,
+            min,
+            max,
+            weights: vec![],
+        }
+    }
+
+    pub fn dice(request_id: U256, count: u32, sides: u64) -> Self {
+        Self::range(request_id, count, U256::one(), U256::from(sides))
+    }
+
+    pub fn shuffle(request_id: U256, count: u32) -> Self {
+        RandomnessRequest {
+            kind: RequestKind::Shuffle.into(),
+            ..Self::range(request_id, count, U256::zero(), U256::zero())
+        }
+    }
+
+    pub fn weighted(request_id: U256, count: u32, weights: Vec<U256>) -> Self {
+        RandomnessRequest {
+            kind: RequestKind::Weighted.into(),
+            weights,
+            ..Self::range(request_id, count, U256::zero(), U256::zero())
+        }
+    }
+
+    pub fn validate(&self) -> Result<RequestKind, RequestError> {
+        let kind = RequestKind::try_from(self.kind)?;
+        if self.count == 0 || self.count > MAX_COUNT {
+            return Err(RequestError::InvalidCount(self.count));
+        }
+        match kind {
+            RequestKind::Range if self.min > self.max => {
+                Err(RequestError::InvalidRange(self.min, self.max))
+            }
+            RequestKind::Weighted => self.total_weight().map(|_| kind),
+            _ => Ok(kind),
+        }
+    }
+
+    // Expand a VRF output into the requested values. Each draw hashes the output with a counter
+    // and rejection sampling keeps every value in range equally likely, the receiver runs the
+    // same steps on-chain to check the result.
+    pub fn expand(&self, output: U256) -> Result<Vec<U256>, RequestError> {
+        let mut draws = Draws { output, counter: 0 };
+        let count = self.count as usize;
+        match self.validate()? {
+            RequestKind::Range => {
+                // max - min + 1 only wraps to zero for the full uint256 range, where any word will do
+                let span = (self.max - self.min).overflowing_add(U256::one()).0;
+                Ok((0..count)
+                    .map(|_| match span.is_zero() {
+                        true => draws.next_word(),
+                        false => self.min + draws.uniform(span),
+                    })
+                    .collect())
+            }
+            RequestKind::Shuffle => {
+                // Fisher-Yates, swapping each position with one at or below it
+                let mut items: Vec<U256> = (0..count).map(U256::from).collect();
+                for i in (1..count).rev() {
+                    let j = draws.uniform(U256::from(i + 1)).as_usize();
+                    items.swap(i, j);
+                }
+                Ok(items)
+            }
+            RequestKind::Weighted => {
+                let total = self.total_weight()?;
+                Ok((0..count)
+                    .map(|_| {
+                        let target = draws.uniform(total);
+                        let mut cumulative = U256::zero();
+                        let index = self
+                            .weights
+                            .iter()
+                            .position(|weight| {
+                                cumulative += *weight;
+                                target < cumulative
+                            })
+                            .unwrap();
+                        U256::from(index)
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    fn total_weight(&self) -> Result<U256, RequestError> {
+        if self.weights.is_empty() || self.weights.len() > MAX_WEIGHTS {
+            return Err(RequestError::InvalidWeights);
+        }
+        let total = self
+            .weights
+            .iter()
+            .try_fold(U256::zero(), |total, weight| total.checked_add(*weight))
+            .ok_or(RequestError::InvalidWeights)?;
+        match total.is_zero() {
+            true => Err(RequestError::InvalidWeights),
+            false => Ok(total),
+        }
+    }
+}
+
+// The stream of words a request's values are drawn from, keccak256(abi.encode(output, counter))
+struct Draws {
+    output: U256,
+    counter: u64,
+}
+
+impl Draws {
+    fn next_word(&mut self) -> U256 {
+        let word = keccak256(encode(&[
+            Token::Uint(self.output),
+            Token::Uint(self.counter.into()),
+        ]));
+        self.counter += 1;
+        U256::from_big_endian(&word)
+    }
+
+    // A value in [0, bound). Words below 2^256 mod bound are skipped so that every remainder is
+    // hit by the same number of words, which removes the modulo bias.
+    fn uniform(&mut self, bound: U256) -> U256 {
+        let threshold = (U256::MAX - bound + 1) % bound;
+        loop {
+            let word = self.next_word();
+            if word >= threshold {
+                return word % bound;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::{AbiDecode, AbiEncode};
+
+    // output of the test vector in vrf.rs
+    fn output() -> U256 {
+        U256::from_str_radix(
+            "2a57d1ad5fa87911f3254d044a0fe6349c45569e0aa102193be26b24d6d4ecfa",
+            16,
+        )
+        .unwrap()
+    }
+
+    fn expand(request: RandomnessRequest) -> Vec<u64> {
+        request
+            .expand(output())
+            .unwrap()
+            .iter()
+            .map(|x| x.as_u64())
+            .collect()
+    }
+
+    #[test]
+    fn decodes_the_receivers_encoding() {
+        let request = RandomnessRequest::weighted(U256::from(4), 2, vec![U256::from(1); 3]);
+        let decoded = RandomnessRequest::decode(request.clone().encode()).unwrap();
+        assert_eq!(decoded, request);
+    }
+
+    // the same vectors are checked against the contract in contracts/test/RandomnessRequests.t.sol
+    #[test]
+    fn expands_the_test_vector() {
+        assert_eq!(
+            expand(RandomnessRequest::dice(U256::one(), 5, 6)),
+            vec![1, 1, 1, 4, 1]
+        );
+        assert_eq!(
+            expand(RandomnessRequest::shuffle(U256::one(), 8)),
+            vec![7, 3, 5, 2, 4, 0, 1, 6]
+        );
+        assert_eq!(
+            expand(RandomnessRequest::weighted(
+                U256::one(),
+                4,
+                vec![U256::from(1), U256::from(0), U256::from(3)]
+            )),
+            vec![2, 0, 2, 2]
+        );
+    }
+
+    #[test]
+    fn stays_in_range() {
+        let request =
+            RandomnessRequest::range(U256::one(), MAX_COUNT, U256::from(10), U256::from(12));
+        let values = request.expand(output()).unwrap();
+        assert_eq!(values.len(), MAX_COUNT as usize);
+        for value in [10, 11, 12] {
+            assert!(values.contains(&U256::from(value)));
+        }
+        assert!(values.iter().all(|x| (10..=12).contains(&x.as_u64())));
+
+        // a single value range and the full uint256 range are both fine
+        let single = RandomnessRequest::range(U256::one(), 3, U256::from(5), U256::from(5));
+        assert_eq!(single.expand(output()).unwrap(), vec![U256::from(5); 3]);
+        let full = RandomnessRequest::range(U256::one(), 1, U256::zero(), U256::MAX);
+        assert!(full.expand(output()).is_ok());
+    }
+
+    #[test]
+    fn shuffles_are_permutations() {
+        let mut values = RandomnessRequest::shuffle(U256::one(), MAX_COUNT)
+            .expand(output())
+            .unwrap();
+        values.sort();
+        let expected: Vec<U256> = (0..MAX_COUNT).map(U256::from).collect();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn never_picks_zero_weights() {
+        let weights = vec![U256::zero(), U256::from(5), U256::zero(), U256::from(1)];
+        let values = RandomnessRequest::weighted(U256::one(), MAX_COUNT, weights)
+            .expand(output())
+            .unwrap();
+        assert!(values
+            .iter()
+            .all(|x| *x == U256::from(1) || *x == U256::from(3)));
+    }
+
+    #[test]
+    fn rejects_invalid_requests() {
+        let mut unknown = RandomnessRequest::shuffle(U256::one(), 1);
+        unknown.kind = 3;
+        assert_eq!(unknown.validate(), Err(RequestError::UnknownKind(3)));
+        assert_eq!(
+            RandomnessRequest::shuffle(U256::one(), MAX_COUNT + 1).validate(),
+            Err(RequestError::InvalidCount(MAX_COUNT + 1))
+        );
+        assert_eq!(
+            RandomnessRequest::range(U256::one(), 1, U256::from(2), U256::one()).validate(),
+            Err(RequestError::InvalidRange(U256::from(2), U256::one()))
+        );
+        for weights in [vec![], vec![U256::zero()], vec![U256::MAX, U256::one()]] {
+            assert_eq!(
+                RandomnessRequest::weighted(U256::one(), 1, weights).validate(),
+                Err(RequestError::InvalidWeights)
+            );
+        }
+    }
+}
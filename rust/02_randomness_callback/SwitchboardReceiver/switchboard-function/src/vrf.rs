@@ -0,0 +1,467 @@
+use ethers::{
+    core::k256::{
+        elliptic_curve::{
+            ops::Reduce,
+            point::DecompressPoint,
+            sec1::{FromEncodedPoint, ToEncodedPoint},
+            subtle::Choice,
+            PrimeField,
+        },
+        AffinePoint, EncodedPoint, FieldBytes, ProjectivePoint, Scalar, U256 as ScalarBytes,
+    },
+    types::{Address, U256},
+    utils::{hex, keccak256},
+};
+use std::fmt;
+
+// ECVRF over secp256k1 with keccak256, laid out so the receiver can verify proofs with ecrecover
+// instead of general purpose curve arithmetic (see contracts/src/VRF.sol). Every hash below is
+// keccak256 over 32 byte big-endian words, prefixed with one of these domain separators.
+const HASH_TO_CURVE_PREFIX: u64 = 1;
+const CHALLENGE_PREFIX: u64 = 2;
+const OUTPUT_PREFIX: u64 = 3;
+
+// Where Gramine exposes the enclave's sealing key. It's derived from the enclave measurement, so
+// the VRF key stays the same across runs of the same build on the same machine.
+const SEALING_KEY_PATH: &str = "/dev/attestation/keys/_sgx_mrenclave";
+const SECRET_KEY_DOMAIN_TAG: &str = "switchboard.vrf.v1";
+
+// Outside the enclave there's no sealing key, so dry runs and the emulator prove with the key in
+// VRF_TEST_KEY instead (32 hex encoded bytes). It isn't passed through to the enclave, a deployed
+// function always proves with its sealing key. Anyone who knows a test key can prove any value,
+// never propose one to a receiver that serves real requests.
+pub const VRF_TEST_KEY: &str = "VRF_TEST_KEY";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VrfError {
+    // the enclave's sealing key couldn't be read
+    Key(String),
+    // a zero secret key or one outside the curve order
+    InvalidSecretKey,
+    // a coordinate pair that isn't a point on the curve
+    InvalidPoint([U256; 2]),
+    // the proof doesn't hold for this public key and seed
+    InvalidProof,
+}
+
+impl fmt::Display for VrfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VrfError::Key(err) => write!(f, "failed to derive the vrf key: {}", err),
+            VrfError::InvalidSecretKey => write!(f, "vrf secret key is out of range"),
+            VrfError::InvalidPoint(point) => {
+                write!(f, "({}, {}) is not on secp256k1", point[0], point[1])
+            }
+            VrfError::InvalidProof => write!(f, "vrf proof does not verify"),
+        }
+    }
+}
+
+impl std::error::Error for VrfError {}
+
+// A proof that `output()` is the only value the key behind `pk` could produce for `seed`.
+// `u_witness`, `c_gamma_witness` and `s_hash_witness` are intermediate values the contract
+// checks with ecrecover rather than computing itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VrfProof {
+    pub pk: [U256; 2],
+    pub gamma: [U256; 2],
+    pub c: U256,
+    pub s: U256,
+    pub seed: U256,
+    pub u_witness: Address,
+    pub c_gamma_witness: [U256; 2],
+    pub s_hash_witness: [U256; 2],
+}
+
+impl VrfProof {
+    // keccak256(3, gamma), what the receiver stores once the proof checks out
+    pub fn output(&self) -> U256 {
+        U256::from_big_endian(&hash_words(&[
+            U256::from(OUTPUT_PREFIX),
+            self.gamma[0],
+            self.gamma[1],
+        ]))
+    }
+}
+
+pub struct VrfSecretKey {
+    secret: Scalar,
+}
+
+impl VrfSecretKey {
+    // keccak256(tag || sealing key), reduced into the curve order
+    pub fn from_enclave() -> Result<Self, VrfError> {
+        let sealing_key =
+            std::fs::read(SEALING_KEY_PATH).map_err(|e| VrfError::Key(e.to_string()))?;
+        let mut preimage = SECRET_KEY_DOMAIN_TAG.as_bytes().to_vec();
+        preimage.extend_from_slice(&sealing_key);
+        let secret = reduce(&keccak256(preimage));
+        if bool::from(secret.is_zero()) {
+            return Err(VrfError::InvalidSecretKey);
+        }
+        Ok(VrfSecretKey { secret })
+    }
+
+    // VRF_TEST_KEY if it's set, otherwise the key derived from the enclave's sealing key
+    pub fn load() -> Result<Self, VrfError> {
+        match std::env::var(VRF_TEST_KEY) {
+            Ok(value) => {
+                let bytes: [u8; 32] = hex::decode(value.trim().trim_start_matches("0x"))
+                    .ok()
+                    .and_then(|x| x.try_into().ok())
+                    .ok_or(VrfError::Key(format!(
+                        "{} must be 32 hex encoded bytes",
+                        VRF_TEST_KEY
+                    )))?;
+                Self::from_bytes(bytes)
+            }
+            Err(_) => Self::from_enclave(),
+        }
+    }
+
+    // A throwaway key for dry runs, nothing it proves is ever sent
+    pub fn random() -> Self {
+        loop {
+            if let Ok(key) = Self::from_bytes(rand::random()) {
+                return key;
+            }
+        }
+    }
+
+    // Only for tests, test vectors and VRF_TEST_KEY
+    pub fn from_bytes(bytes: [u8; 32]) -> Result<Self, VrfError> {
+        let secret = Option::<Scalar>::from(Scalar::from_repr(bytes.into()))
+            .ok_or(VrfError::InvalidSecretKey)?;
+        if bool::from(secret.is_zero()) {
+            return Err(VrfError::InvalidSecretKey);
+        }
+        Ok(VrfSecretKey { secret })
+    }
+
+    pub fn public_key(&self) -> [U256; 2] {
+        to_coords(&(ProjectivePoint::GENERATOR * self.secret))
+    }
+
+    pub fn prove(&self, seed: U256) -> VrfProof {
+        let pk = self.public_key();
+        let hash = hash_to_curve(pk, seed);
+        let gamma = hash * self.secret;
+
+        // deterministic nonce, bound to the key and the hashed seed
+        let mut nonce_preimage = self.secret.to_bytes().to_vec();
+        nonce_preimage.extend_from_slice(&words(&to_coords(&hash)));
+        let nonce = reduce(&keccak256(nonce_preimage));
+
+        let u_witness = point_address(&(ProjectivePoint::GENERATOR * nonce));
+        let v = hash * nonce;
+        let c = challenge(
+            &to_coords(&hash),
+            &pk,
+            &to_coords(&gamma),
+            &to_coords(&v),
+            u_witness,
+        );
+        // s = nonce - c * secret, so that u = c * pk + s * G and v = c * gamma + s * hash
+        let s = nonce - reduce_u256(c) * self.secret;
+
+        VrfProof {
+            pk,
+            gamma: to_coords(&gamma),
+            c,
+            s: U256::from_big_endian(&s.to_bytes()),
+            seed,
+            u_witness,
+            c_gamma_witness: to_coords(&(gamma * reduce_u256(c))),
+            s_hash_witness: to_coords(&(hash * s)),
+        }
+    }
+}
+
+// Verify `proof` for `pk` and `seed` and return its output. Checks the witnesses too, so a proof
+// that verifies here also verifies on-chain.
+pub fn verify(pk: [U256; 2], seed: U256, proof: &VrfProof) -> Result<U256, VrfError> {
+    if proof.pk != pk || proof.seed != seed {
+        return Err(VrfError::InvalidProof);
+    }
+    let pk_point = from_coords(pk)?;
+    let gamma = from_coords(proof.gamma)?;
+    let hash = hash_to_curve(pk, seed);
+    let c = reduce_u256(proof.c);
+    let s = reduce_u256(proof.s);
+
+    let u = pk_point * c + ProjectivePoint::GENERATOR * s;
+    let c_gamma = gamma * c;
+    let s_hash = hash * s;
+    let v = c_gamma + s_hash;
+
+    // the contract adds the two witnesses in affine form, which needs distinct x coordinates
+    let valid = point_address(&u) == proof.u_witness
+        && proof.c_gamma_witness[0] != proof.s_hash_witness[0]
+        && to_coords(&c_gamma) == proof.c_gamma_witness
+        && to_coords(&s_hash) == proof.s_hash_witness
+        && challenge(
+            &to_coords(&hash),
+            &pk,
+            &proof.gamma,
+            &to_coords(&v),
+            proof.u_witness,
+        ) == proof.c;
+    if !valid {
+        return Err(VrfError::InvalidProof);
+    }
+    Ok(proof.output())
+}
+
+// Try-and-increment: hash (pk, seed, counter) until it's the x coordinate of a point, taking the
+// even y. The contract runs the same loop.
+pub fn hash_to_curve(pk: [U256; 2], seed: U256) -> ProjectivePoint {
+    let mut counter = U256::zero();
+    loop {
+        let x = hash_words(&[
+            U256::from(HASH_TO_CURVE_PREFIX),
+            pk[0],
+            pk[1],
+            seed,
+            counter,
+        ]);
+        let point: Option<AffinePoint> =
+            AffinePoint::decompress(&FieldBytes::from(x), Choice::from(0)).into();
+        if let Some(point) = point {
+            return point.into();
+        }
+        counter += U256::one();
+    }
+}
+
+// keccak256(2, hash, pk, gamma, v, uWitness), the address packed as 20 bytes
+fn challenge(
+    hash: &[U256; 2],
+    pk: &[U256; 2],
+    gamma: &[U256; 2],
+    v: &[U256; 2],
+    u_witness: Address,
+) -> U256 {
+    let mut preimage = words(&[U256::from(CHALLENGE_PREFIX)]);
+    for point in [hash, pk, gamma, v] {
+        preimage.extend_from_slice(&words(point));
+    }
+    preimage.extend_from_slice(u_witness.as_bytes());
+    U256::from_big_endian(&keccak256(preimage))
+}
+
+fn words(values: &[U256]) -> Vec<u8> {
+    let mut bytes = vec![0u8; 32 * values.len()];
+    for (i, value) in values.iter().enumerate() {
+        value.to_big_endian(&mut bytes[32 * i..32 * (i + 1)]);
+    }
+    bytes
+}
+
+fn hash_words(values: &[U256]) -> [u8; 32] {
+    keccak256(words(values))
+}
+
+fn reduce(bytes: &[u8; 32]) -> Scalar {
+    <Scalar as Reduce<ScalarBytes>>::reduce_bytes(&FieldBytes::from(*bytes))
+}
+
+fn reduce_u256(value: U256) -> Scalar {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    reduce(&bytes)
+}
+
+fn to_coords(point: &ProjectivePoint) -> [U256; 2] {
+    let encoded = point.to_affine().to_encoded_point(false);
+    [
+        U256::from_big_endian(encoded.x().map(|x| &x[..]).unwrap_or(&[])),
+        U256::from_big_endian(encoded.y().map(|y| &y[..]).unwrap_or(&[])),
+    ]
+}
+
+fn from_coords(coords: [U256; 2]) -> Result<ProjectivePoint, VrfError> {
+    let mut x = [0u8; 32];
+    let mut y = [0u8; 32];
+    coords[0].to_big_endian(&mut x);
+    coords[1].to_big_endian(&mut y);
+    let encoded = EncodedPoint::from_affine_coordinates(&x.into(), &y.into(), false);
+    Option::<AffinePoint>::from(AffinePoint::from_encoded_point(&encoded))
+        .map(ProjectivePoint::from)
+        .ok_or(VrfError::InvalidPoint(coords))
+}
+
+// The ethereum address of a point, as ecrecover would return it
+fn point_address(point: &ProjectivePoint) -> Address {
+    Address::from_slice(&keccak256(words(&to_coords(point)))[12..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // secp256k1 group order
+    const N: &str = "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141";
+
+    fn test_key() -> VrfSecretKey {
+        VrfSecretKey::from_bytes(keccak256("switchboard vrf test vector")).unwrap()
+    }
+
+    // ecrecover as the precompile implements it: r^-1 * (s * R - hash * G), where R is the point
+    // with x coordinate r and the parity given by v. Unlike k256's recovery it accepts high s.
+    fn ecrecover(hash: U256, v: u8, r: U256, s: U256) -> Address {
+        let n = U256::from_str_radix(N, 16).unwrap();
+        if r.is_zero() || r >= n || s.is_zero() || s >= n {
+            return Address::zero();
+        }
+        let mut r_bytes = [0u8; 32];
+        r.to_big_endian(&mut r_bytes);
+        let point: Option<AffinePoint> =
+            AffinePoint::decompress(&FieldBytes::from(r_bytes), Choice::from((v == 28) as u8))
+                .into();
+        let point = match point {
+            Some(point) => ProjectivePoint::from(point),
+            None => return Address::zero(),
+        };
+        let r_inv = Option::<Scalar>::from(reduce_u256(r).invert()).unwrap();
+        let recovered =
+            (point * reduce_u256(s) - ProjectivePoint::GENERATOR * reduce_u256(hash)) * r_inv;
+        point_address(&recovered)
+    }
+
+    fn mulmod(a: U256, b: U256) -> U256 {
+        let n = U256::from_str_radix(N, 16).unwrap();
+        U256::try_from(a.full_mul(b) % n).unwrap()
+    }
+
+    fn parity(point: &[U256; 2]) -> u8 {
+        if point[1].bit(0) {
+            28
+        } else {
+            27
+        }
+    }
+
+    // VRF.verifyLinearCombinationWithGenerator: address(c * p + s * G) == witness
+    fn contract_linear_combination(c: U256, p: [U256; 2], s: U256, witness: Address) -> bool {
+        let n = U256::from_str_radix(N, 16).unwrap();
+        let pseudo_hash = n - mulmod(p[0], s);
+        let pseudo_signature = mulmod(c, p[0]);
+        ecrecover(pseudo_hash, parity(&p), p[0], pseudo_signature) == witness
+    }
+
+    // VRF.ecmulVerify: address(scalar * multiplicand) == address(product)
+    fn contract_ecmul(multiplicand: [U256; 2], scalar: U256, product: [U256; 2]) -> bool {
+        let recovered = ecrecover(
+            U256::zero(),
+            parity(&multiplicand),
+            multiplicand[0],
+            mulmod(scalar, multiplicand[0]),
+        );
+        recovered == Address::from_slice(&keccak256(words(&product))[12..])
+    }
+
+    #[test]
+    fn proves_and_verifies() {
+        let key = test_key();
+        let seed = U256::from(42);
+        let proof = key.prove(seed);
+        assert_eq!(verify(key.public_key(), seed, &proof), Ok(proof.output()));
+
+        // the output is unique to the seed
+        let other = key.prove(U256::from(43));
+        assert_ne!(proof.output(), other.output());
+        assert_eq!(
+            verify(key.public_key(), seed, &other),
+            Err(VrfError::InvalidProof)
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_proofs() {
+        let key = test_key();
+        let seed = U256::from(42);
+        let proof = key.prove(seed);
+
+        let mut tampered = proof.clone();
+        tampered.s += U256::one();
+        assert_eq!(
+            verify(key.public_key(), seed, &tampered),
+            Err(VrfError::InvalidProof)
+        );
+
+        // a different key can't claim another key's output
+        let other_key = VrfSecretKey::from_bytes(keccak256("another key")).unwrap();
+        let mut forged = other_key.prove(seed);
+        forged.gamma = proof.gamma;
+        assert_eq!(
+            verify(other_key.public_key(), seed, &forged),
+            Err(VrfError::InvalidProof)
+        );
+
+        let mut off_curve = proof.clone();
+        off_curve.gamma[1] += U256::one();
+        assert_eq!(
+            verify(key.public_key(), seed, &off_curve),
+            Err(VrfError::InvalidPoint(off_curve.gamma))
+        );
+    }
+
+    #[test]
+    fn witnesses_pass_the_contract_checks() {
+        let key = test_key();
+        for seed in 0..8u64 {
+            let proof = key.prove(U256::from(seed));
+            let hash = to_coords(&hash_to_curve(proof.pk, proof.seed));
+            assert!(contract_linear_combination(
+                proof.c,
+                proof.pk,
+                proof.s,
+                proof.u_witness
+            ));
+            assert!(contract_ecmul(proof.gamma, proof.c, proof.c_gamma_witness));
+            assert!(contract_ecmul(hash, proof.s, proof.s_hash_witness));
+            assert!(!contract_ecmul(hash, proof.s + 1, proof.s_hash_witness));
+        }
+    }
+
+    #[test]
+    fn matches_test_vectors() {
+        // the same vector is checked against the contract in contracts/test/VRF.t.sol
+        let hex = |x: &str| U256::from_str_radix(x, 16).unwrap();
+        let key = test_key();
+        let proof = key.prove(U256::from(42));
+        assert_eq!(
+            proof.pk,
+            [
+                hex("a93d9079cc37018c49832874543e7d2148e7da02f01412e5752cabe98158624f"),
+                hex("e6f9559a1ea7293f8181942b90654d78dfa6890293fbd701109df5e6bef3032b"),
+            ]
+        );
+        assert_eq!(
+            proof.gamma,
+            [
+                hex("f552af11815735decfbe774525a566bd4644d6d05dd302e581a15a77a62913f3"),
+                hex("db63759933d9b07adbfa1c2d0f635aed372f77b25c6a2d1d40e42b700effac71"),
+            ]
+        );
+        assert_eq!(
+            proof.c,
+            hex("ba1eccc86a237901b5c354320deb8d639fa7beeccb8c1bd161c0eb868557d109")
+        );
+        assert_eq!(
+            proof.s,
+            hex("6f5f71f4598aa0973df45eab8e139181a5281679b932effd2ee81b9e83036c50")
+        );
+        assert_eq!(
+            proof.u_witness,
+            "0x45d8e2e26a25df27a64bec3fb4b381941baf39c2"
+                .parse::<Address>()
+                .unwrap()
+        );
+        assert_eq!(
+            proof.output(),
+            hex("2a57d1ad5fa87911f3254d044a0fe6349c45569e0aa102193be26b24d6d4ecfa")
+        );
+    }
+}
@@ -0,0 +1,30 @@
+//SPDX-License-Identifier: UNLICENSED
+pragma solidity ^0.8.9;
+
+// A list of senders the Switchboard Function reads its sender policy from, so senders can be
+// listed or delisted without publishing a new function. Whether listed senders are allowed or
+// blocked is up to the function's policy.json.
+contract SenderRegistry {
+    event SenderListed(address sender, bool listed);
+
+    error NotOwner(address sender);
+
+    address public owner;
+    mapping(address => bool) listed;
+
+    constructor() {
+        owner = msg.sender;
+    }
+
+    function contains(address sender) external view returns (bool) {
+        return listed[sender];
+    }
+
+    function setListed(address sender, bool _listed) external {
+        if (msg.sender != owner) {
+            revert NotOwner(msg.sender);
+        }
+        listed[sender] = _listed;
+        emit SenderListed(sender, _listed);
+    }
+}
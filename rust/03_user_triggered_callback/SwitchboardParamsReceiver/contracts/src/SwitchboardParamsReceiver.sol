@@ -10,13 +10,31 @@ contract SwitchboardParamsReceiver {
     // Events
     event OrderCreated(uint256 orderId, address callId, address sender);
     event OrderResolved(uint256 orderId, address callId, uint256 value);
+    event OrderFailed(uint256 orderId, address callId, FailureReason reason);
+    event OrderSkipped(uint256 orderId);
+    event RefundCredited(address sender, uint256 amount);
+    event RefundWithdrawn(address sender, uint256 amount);
 
     // Errors
     error InvalidValue(uint256 value);
     error InvalidSender(address expected, address actual);
     error InvalidOrder(uint256 orderId);
+    error IncorrectInputLength(uint256 orderIds, uint256 values);
     error MissingEncodedFunctionId();
     error AlreadyInitialized(address functionId);
+    error NothingToRefund(address sender);
+    error RefundFailed(address sender, uint256 amount);
+
+    // Why the function refused a call, the codes match FailureReason in the function
+    enum FailureReason {
+        None,
+        InvalidParams,
+        SenderBlocked,
+        SenderNotAllowed,
+        UnsupportedVersion,
+        UnknownKind,
+        UnknownOrder
+    }
 
     // Structs
     struct Order {
@@ -24,6 +42,19 @@ contract SwitchboardParamsReceiver {
         address sender;
         uint256 value;
         bool filled;
+        // what the sender paid to create the order
+        uint256 fee;
+        FailureReason failure;
+        // the function re-queues orders left unfilled for a while, see REQUEUE_AFTER there
+        uint256 createdAt;
+    }
+
+    // Every call's params are wrapped in an envelope, so the function can route each call to the
+    // handler for its kind of request
+    struct RequestEnvelope {
+        uint8 version;
+        uint16 kind;
+        bytes payload;
     }
 
     // Switchboard Function Parameters
@@ -36,12 +67,18 @@ contract SwitchboardParamsReceiver {
 
     // Constants
     uint256 public constant EXPECTED_FUNCTION_GAS_COST = 300_000;
+    uint8 public constant ENVELOPE_VERSION = 1;
+    uint16 public constant ORDER_REQUEST = 1;
 
     // State variables
     address switchboardAddress;
     address functionId;
-    uint256 nextOrderId;
+    uint256 public nextOrderId;
     mapping(uint256 => Order) public orders;
+    // failure callbacks only know the call id, calls that failed to decode have no order id
+    mapping(address => uint256) public orderIds;
+    // refunds credited to senders of failed orders, see onOrderFailed
+    mapping(address => uint256) public refunds;
     uint256 public latestValue;
 
     constructor(address _switchboard) {
@@ -58,9 +95,15 @@ contract SwitchboardParamsReceiver {
             revert InvalidValue(msg.value);
         }
 
-        // encode the order parameters
+        // encode the order parameters, tagged as an order request
         bytes memory encodedOrder = abi.encode(
-            OrderParams({orderId: nextOrderId, sender: msg.sender})
+            RequestEnvelope({
+                version: ENVELOPE_VERSION,
+                kind: ORDER_REQUEST,
+                payload: abi.encode(
+                    OrderParams({orderId: nextOrderId, sender: msg.sender})
+                )
+            })
         );
 
         // call out to the swithcboard function, triggering an off-chain run
@@ -71,6 +114,9 @@ contract SwitchboardParamsReceiver {
         // store the order data
         orders[nextOrderId].sender = msg.sender;
         orders[nextOrderId].callId = callId;
+        orders[nextOrderId].fee = msg.value;
+        orders[nextOrderId].createdAt = block.timestamp;
+        orderIds[callId] = nextOrderId;
 
         // emit an event
         emit OrderCreated(nextOrderId, callId, msg.sender);
@@ -81,6 +127,124 @@ contract SwitchboardParamsReceiver {
 
     // Callback into contract with value computed off-chain
     function fillOrder(uint256 orderId, uint256 value) external {
+        address encodedFunctionId = checkCallback();
+
+        if (!_fillOrder(orderId, value, encodedFunctionId)) {
+            revert InvalidOrder(orderId);
+        }
+    }
+
+    // Callback into contract with a batch of values computed off-chain, used by the function when
+    // it runs with BATCH_FILLS=1. An order that can't be filled is skipped rather than reverting
+    // the whole batch.
+    function fillOrders(
+        uint256[] calldata orderIds,
+        uint256[] calldata values
+    ) external {
+        address encodedFunctionId = checkCallback();
+
+        if (orderIds.length != values.length) {
+            revert IncorrectInputLength(orderIds.length, values.length);
+        }
+
+        for (uint256 i = 0; i < orderIds.length; i++) {
+            if (!_fillOrder(orderIds[i], values[i], encodedFunctionId)) {
+                emit OrderSkipped(orderIds[i]);
+            }
+        }
+    }
+
+    // Fill an order, false if it isn't registered or is no longer open
+    function _fillOrder(
+        uint256 orderId,
+        uint256 value,
+        address encodedFunctionId
+    ) internal returns (bool) {
+        // sanity check that the order has been registered and is still open
+        Order storage order = orders[orderId];
+        if (
+            order.sender == address(0) ||
+            order.filled ||
+            order.failure != FailureReason.None
+        ) {
+            return false;
+        }
+
+        // fill order and mark it as filled
+        order.value = value;
+        order.filled = true;
+
+        latestValue = value;
+
+        // emit an event
+        emit OrderResolved(orderId, encodedFunctionId, value);
+        return true;
+    }
+
+    // Callback into contract when the function refuses a call, e.g. because its params didn't
+    // decode or its sender is blocked. The call is resolved either way, so this is the sender's
+    // only way to get their fee back.
+    function failOrder(address callId, FailureReason reason) external {
+        checkCallback();
+
+        // calls that weren't made through createOrder have no order to fail
+        uint256 orderId = orderIds[callId];
+        if (orderId == 0) {
+            emit OrderFailed(0, callId, reason);
+            return;
+        }
+
+        Order storage order = orders[orderId];
+        if (order.filled || order.failure != FailureReason.None) {
+            revert InvalidOrder(orderId);
+        }
+        order.failure = reason;
+
+        onOrderFailed(orderId, order);
+
+        emit OrderFailed(orderId, callId, reason);
+    }
+
+    // Refund hook, called once for every failed order. By default the sender is credited the fee
+    // they paid, to be withdrawn with withdrawRefund. The fee itself was forwarded to Switchboard,
+    // so refunds are paid from whatever the contract's owner deposits. Override this to refund
+    // from your own escrow, or to not refund at all.
+    function onOrderFailed(
+        uint256 /* orderId */,
+        Order storage order
+    ) internal virtual {
+        refunds[order.sender] += order.fee;
+        emit RefundCredited(order.sender, order.fee);
+    }
+
+    function withdrawRefund() external {
+        uint256 amount = refunds[msg.sender];
+        if (amount == 0) {
+            revert NothingToRefund(msg.sender);
+        }
+        refunds[msg.sender] = 0;
+        (bool success, ) = payable(msg.sender).call{value: amount}("");
+        if (!success) {
+            revert RefundFailed(msg.sender, amount);
+        }
+        emit RefundWithdrawn(msg.sender, amount);
+    }
+
+    // ERC-165, the function also asks for the selector of each call it makes before emitting them
+    function supportsInterface(bytes4 interfaceId) external view returns (bool) {
+        return
+            interfaceId == this.supportsInterface.selector ||
+            interfaceId == this.fillOrder.selector ||
+            interfaceId == this.fillOrders.selector ||
+            interfaceId == this.failOrder.selector;
+    }
+
+    // funds refunds, see onOrderFailed
+    receive() external payable {}
+
+    // Make sure the call came from the Switchboard contract on behalf of our function, returning
+    // the function id
+    function checkCallback() internal returns (address) {
         // make sure that sender is the switchboard contract
         address signer = msg.sender;
         if (signer != switchboardAddress) {
@@ -111,19 +275,7 @@ contract SwitchboardParamsReceiver {
             revert InvalidSender(functionId, encodedFunctionId);
         }
 
-        // sanity check that the order has been registered
-        if (orders[orderId].sender == address(0)) {
-            revert InvalidOrder(orderId);
-        }
-
-        // fill order and mark it as filled
-        orders[orderId].value = value;
-        orders[orderId].filled = true;
-
-        latestValue = value;
-
-        // emit an event
-        emit OrderResolved(orderId, encodedFunctionId, value);
+        return encodedFunctionId;
     }
 
     // initialize the contract with the switchboard function id - can only be called once!
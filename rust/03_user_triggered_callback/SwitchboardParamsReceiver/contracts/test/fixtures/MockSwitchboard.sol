@@ -0,0 +1,26 @@
+//SPDX-License-Identifier: UNLICENSED
+pragma solidity ^0.8.9;
+
+// Stands in for the Switchboard contract on a local dev chain. It hands out a call id for each
+// callFunction like Switchboard does, but nothing runs the function, run it through the emulator
+// with the call ids from the receiver's OrderCreated events instead.
+contract MockSwitchboard {
+    uint256 private nonce;
+
+    event FunctionCalled(
+        address indexed functionId,
+        address indexed callId,
+        address sender,
+        bytes params
+    );
+
+    function callFunction(
+        address functionId,
+        bytes memory params
+    ) external payable returns (address callId) {
+        callId = address(
+            uint160(uint256(keccak256(abi.encode(address(this), nonce++))))
+        );
+        emit FunctionCalled(functionId, callId, msg.sender, params);
+    }
+}
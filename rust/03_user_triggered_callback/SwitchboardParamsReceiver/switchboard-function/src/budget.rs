@@ -0,0 +1,233 @@
+use crate::*;
+
+// Rough gas costs used to pack callbacks under the gas limit. A fill writes two fresh storage
+// slots, updates latestValue and emits an event. A failure marks the order and credits a refund.
+// The overhead covers the Switchboard contract's verification of the emitted transaction.
+pub const FILL_ORDER_GAS: u64 = 60_000;
+pub const FAIL_ORDER_GAS: u64 = 50_000;
+pub const EMIT_OVERHEAD_GAS: u64 = 150_000;
+
+// Batched fills skip the per-call dispatch and function id check, they pay for it once per batch
+pub const BATCHED_FILL_GAS: u64 = 45_000;
+pub const BATCH_OVERHEAD_GAS: u64 = 25_000;
+
+// What a run does with each pending call
+#[derive(Debug, Clone, Default)]
+pub struct FillPlan {
+    // filled and resolved this run, lowest order id first
+    pub fills: Vec<(OrderParams, Address)>,
+    // resolved with a failure callback
+    pub rejected: Vec<Refusal>,
+    // left pending for a later run
+    pub deferred: Vec<Address>,
+}
+
+impl FillPlan {
+    // the call ids this run resolves
+    pub fn resolved_call_ids(&self) -> Vec<Address> {
+        self.fills
+            .iter()
+            .map(|(_, call_id)| *call_id)
+            .chain(self.rejected.iter().map(|x| x.call_id))
+            .collect()
+    }
+}
+
+// Gas left for callbacks once the transaction's overhead is paid for
+#[derive(Debug, Clone)]
+pub struct GasBudget {
+    remaining: U256,
+}
+
+impl GasBudget {
+    pub fn new(gas_limit: U256) -> Self {
+        GasBudget {
+            remaining: gas_limit.saturating_sub(EMIT_OVERHEAD_GAS.into()),
+        }
+    }
+
+    pub fn fits(&self, gas: u64) -> bool {
+        self.remaining >= gas.into()
+    }
+
+    // Take `gas` from the budget if there's enough left
+    pub fn spend(&mut self, gas: u64) -> bool {
+        if !self.fits(gas) {
+            return false;
+        }
+        self.remaining -= gas.into();
+        true
+    }
+}
+
+// Rank the pending calls by order id, oldest first, and pack as many callbacks as the budget
+// allows, budgeting `fill_gas` per fill. Calls that fail to decode or that the policy rejects get
+// a failure callback, senders over their per-run limit and calls past the budget wait for the next
+// run.
+pub fn plan_fills(
+    params: Vec<(Result<OrderParams, RunnerError>, Address)>,
+    policy: &mut SenderPolicy,
+    budget: &mut GasBudget,
+    fill_gas: u64,
+) -> FillPlan {
+    let mut plan = FillPlan::default();
+    let mut orders = vec![];
+    let mut invalid = vec![];
+    for (param, call_id) in params {
+        match param {
+            Ok(param) => orders.push((param, call_id)),
+            Err(e) => invalid.push(Refusal {
+                call_id,
+                reason: FailureReason::InvalidParams,
+                detail: format!("failed to decode params, {}", e),
+            }),
+        }
+    }
+    orders.sort_by_key(|(param, _)| param.order_id);
+
+    for (param, call_id) in orders {
+        // the policy counts every order it passes against the sender's limit, so only ask once
+        // there's room for whichever callback it ends up with
+        if !budget.fits(fill_gas.max(FAIL_ORDER_GAS)) {
+            plan.deferred.push(call_id);
+            continue;
+        }
+        match policy.check(param.sender) {
+            Ok(()) => {
+                budget.spend(fill_gas);
+                plan.fills.push((param, call_id));
+            }
+            Err(rejection) => match Refusal::from_rejection(call_id, &rejection) {
+                Some(refusal) => {
+                    budget.spend(FAIL_ORDER_GAS);
+                    plan.rejected.push(refusal);
+                }
+                None => plan.deferred.push(call_id),
+            },
+        }
+    }
+
+    // calls without an order id can't be ranked, they go last
+    for refusal in invalid {
+        match budget.spend(FAIL_ORDER_GAS) {
+            true => plan.rejected.push(refusal),
+            false => plan.deferred.push(refusal.call_id),
+        }
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(order_id: u64, sender: Address) -> (Result<OrderParams, RunnerError>, Address) {
+        let param = OrderParams {
+            order_id: U256::from(order_id),
+            sender,
+        };
+        (Ok(param), Address::random())
+    }
+
+    #[test]
+    fn spends_only_what_is_left() {
+        let mut budget = GasBudget::new(U256::from(EMIT_OVERHEAD_GAS + FILL_ORDER_GAS));
+        assert!(budget.spend(FAIL_ORDER_GAS));
+        assert!(!budget.spend(FAIL_ORDER_GAS));
+        assert!(budget.spend(FILL_ORDER_GAS - FAIL_ORDER_GAS));
+        assert!(!GasBudget::new(U256::zero()).fits(1));
+    }
+
+    #[test]
+    fn fills_the_oldest_orders_first() {
+        let sender = Address::random();
+        let params = vec![
+            order(3, sender),
+            order(1, sender),
+            (
+                Err(RunnerError("bad params".to_string())),
+                Address::random(),
+            ),
+            order(2, sender),
+        ];
+        let call_ids: Vec<Address> = params.iter().map(|x| x.1).collect();
+
+        // room for two fills and the failure callback
+        let mut budget = GasBudget::new(U256::from(
+            EMIT_OVERHEAD_GAS + 2 * FILL_ORDER_GAS + FAIL_ORDER_GAS,
+        ));
+        let plan = plan_fills(
+            params,
+            &mut SenderPolicy::default(),
+            &mut budget,
+            FILL_ORDER_GAS,
+        );
+        let filled: Vec<U256> = plan.fills.iter().map(|(x, _)| x.order_id).collect();
+        assert_eq!(filled, vec![U256::from(1), U256::from(2)]);
+        assert_eq!(plan.rejected.len(), 1);
+        assert_eq!(plan.rejected[0].call_id, call_ids[2]);
+        assert_eq!(plan.rejected[0].reason, FailureReason::InvalidParams);
+        assert_eq!(plan.deferred, vec![call_ids[0]]);
+        assert_eq!(
+            plan.resolved_call_ids(),
+            vec![call_ids[1], call_ids[3], call_ids[2]]
+        );
+    }
+
+    #[test]
+    fn defers_senders_over_their_limit() {
+        let busy = Address::random();
+        let blocked = Address::random();
+        let params = vec![order(1, busy), order(2, busy), order(3, blocked)];
+        let call_ids: Vec<Address> = params.iter().map(|x| x.1).collect();
+        let mut policy = SenderPolicy::new(&PolicyConfig {
+            senders: vec![blocked],
+            max_orders_per_sender: Some(1),
+            ..Default::default()
+        });
+
+        let plan = plan_fills(
+            params,
+            &mut policy,
+            &mut GasBudget::new(U256::from(1_000_000)),
+            FILL_ORDER_GAS,
+        );
+        assert_eq!(plan.fills.len(), 1);
+        assert_eq!(plan.deferred, vec![call_ids[1]]);
+        assert_eq!(
+            plan.rejected,
+            vec![Refusal {
+                call_id: call_ids[2],
+                reason: FailureReason::SenderBlocked,
+                detail: Rejection::Blocked(blocked).to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn packs_more_orders_when_batched() {
+        let orders = || (1..=3).map(|x| order(x, Address::random())).collect();
+        // the last order needs room for a failure callback in case the policy rejects it
+        let gas_limit = U256::from(
+            EMIT_OVERHEAD_GAS + BATCH_OVERHEAD_GAS + 2 * BATCHED_FILL_GAS + FAIL_ORDER_GAS,
+        );
+
+        let plan = plan_fills(
+            orders(),
+            &mut SenderPolicy::default(),
+            &mut GasBudget::new(gas_limit),
+            FILL_ORDER_GAS,
+        );
+        assert_eq!(plan.fills.len(), 2);
+
+        let mut budget = GasBudget::new(gas_limit);
+        assert!(budget.spend(BATCH_OVERHEAD_GAS));
+        let plan = plan_fills(
+            orders(),
+            &mut SenderPolicy::default(),
+            &mut budget,
+            BATCHED_FILL_GAS,
+        );
+        assert_eq!(plan.fills.len(), 3);
+    }
+}
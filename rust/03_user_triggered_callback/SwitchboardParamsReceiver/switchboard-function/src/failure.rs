@@ -0,0 +1,80 @@
+use crate::*;
+
+use std::fmt;
+
+// Why a call was refused. The codes match the FailureReason enum in the receiver, 0 is reserved
+// there for orders that haven't failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureReason {
+    // the call's params didn't decode, either the envelope or the request inside it
+    InvalidParams,
+    SenderBlocked,
+    SenderNotAllowed,
+    // the request envelope has a version this function doesn't know
+    UnsupportedVersion,
+    // no handler is registered for the request's kind
+    UnknownKind,
+    // the receiver has no such order for the call
+    UnknownOrder,
+}
+
+impl FailureReason {
+    pub fn code(&self) -> u8 {
+        match self {
+            FailureReason::InvalidParams => 1,
+            FailureReason::SenderBlocked => 2,
+            FailureReason::SenderNotAllowed => 3,
+            FailureReason::UnsupportedVersion => 4,
+            FailureReason::UnknownKind => 5,
+            FailureReason::UnknownOrder => 6,
+        }
+    }
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureReason::InvalidParams => write!(f, "invalid params"),
+            FailureReason::SenderBlocked => write!(f, "sender blocked"),
+            FailureReason::SenderNotAllowed => write!(f, "sender not allowed"),
+            FailureReason::UnsupportedVersion => write!(f, "unsupported version"),
+            FailureReason::UnknownKind => write!(f, "unknown request kind"),
+            FailureReason::UnknownOrder => write!(f, "unknown order"),
+        }
+    }
+}
+
+// A call the function won't fill, reported back to the receiver so the sender can be refunded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refusal {
+    pub call_id: Address,
+    pub reason: FailureReason,
+    // what gets logged, e.g. the decoding error
+    pub detail: String,
+}
+
+impl Refusal {
+    // None for rejections that only defer the call
+    pub fn from_rejection(call_id: Address, rejection: &Rejection) -> Option<Self> {
+        let reason = match rejection {
+            Rejection::Blocked(_) => FailureReason::SenderBlocked,
+            Rejection::NotAllowed(_) => FailureReason::SenderNotAllowed,
+            Rejection::LimitExceeded(..) => return None,
+        };
+        Some(Refusal {
+            call_id,
+            reason,
+            detail: rejection.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "call {:?} refused ({}): {}",
+            self.call_id, self.reason, self.detail
+        )
+    }
+}
@@ -1,34 +1,63 @@
+pub mod target;
+pub use target::*;
+pub mod dry_run;
+pub use dry_run::*;
+pub mod runner;
+pub use runner::*;
+pub mod randomness;
+pub use randomness::*;
+pub mod policy;
+pub use policy::*;
+pub mod budget;
+pub use budget::*;
+pub mod failure;
+pub use failure::*;
+pub mod router;
+pub use router::*;
+pub mod orders;
+pub use orders::*;
+pub mod preflight;
+pub use preflight::*;
+
 use ethers::{
-    prelude::{abigen, ContractCall, EthAbiCodec, EthAbiType, SignerMiddleware},
+    contract::EthCall,
+    prelude::{abigen, EthAbiCodec, EthAbiType, SignerMiddleware},
     providers::{Http, Provider},
+    signers::Signer,
     types::{Address, U256},
 };
-use rand;
 use std::env;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
-use switchboard_evm::sdk::{EVMFunctionRunner, EVMMiddleware};
 
-#[tokio::main(worker_threads = 12)]
-async fn main() {
-    // define the abi for the functions in the contract you'll be calling
-    // -- here it's just a function named "callback", expecting a random u256
-    abigen!(
-        Receiver,
-        r#"[
-            function fillOrder(uint256,uint256)
-        ]"#,
-    );
+// define the abi for the functions in the contract you'll be calling
+// -- "fillOrder" expects the order id and a random u256
+// -- "fillOrders" fills a batch of orders in one call, each order's failure is isolated
+// -- "failOrder" refuses a call with one of the FailureReason codes, so the sender can be refunded
+// -- "orders" and "nextOrderId" are read before filling, so orders that would revert are dropped
+abigen!(
+    Receiver,
+    r#"[
+        function fillOrder(uint256,uint256)
+        function fillOrders(uint256[],uint256[])
+        function failOrder(address,uint8)
+        function orders(uint256) view returns (address,address,uint256,bool,uint256,uint8,uint256)
+        function nextOrderId() view returns (uint256)
+    ]"#,
+);
 
-    // Define the type we're getting from the function call
-    #[derive(Debug, Clone, EthAbiType, EthAbiCodec)]
-    struct OrderParams {
-        order_id: U256,
-        sender: Address,
-    }
+// Define the type we're getting from the function call
+#[derive(Debug, Clone, EthAbiType, EthAbiCodec)]
+pub struct OrderParams {
+    pub order_id: U256,
+    pub sender: Address,
+}
 
+#[tokio::main(worker_threads = 12)]
+async fn main() {
     // Generates a new enclave wallet, pulls in relevant environment variables
-    let function_runner = EVMFunctionRunner::new().unwrap();
+    // -- dry runs don't need the enclave, see src/dry_run.rs
+    let function_runner = Runner::new().unwrap();
 
     // set the gas limit and expiration date
     let gas_limit = 1_000_000;
@@ -40,78 +69,551 @@ async fn main() {
 
     // create a client, wallet and middleware. This is just so we can create the contract instance and sign the txn.
     // @TODO: update the provider to whichever network you're using
-    let provider = Provider::<Http>::try_from("https://rpc.test.btcs.network").unwrap();
+    // -- RPC_URL overrides the default network, e.g. to point the function at a local dev chain
+    let rpc_url = std::env::var("RPC_URL").unwrap_or("https://rpc.test.btcs.network".to_string());
+    let provider = Provider::<Http>::try_from(rpc_url).unwrap();
     let client = Arc::new(
         SignerMiddleware::new_with_provider_chain(
             provider.clone(),
-            function_runner.enclave_wallet.clone(),
+            function_runner.enclave_wallet(),
         )
         .await
         .unwrap(),
     );
 
-    // get contract address from docker env
-    let contract_address = env!("SWITCHBOARD_RECEIVER_ADDRESS")
-        .parse::<ethers::types::Address>()
-        .unwrap();
+    // resolve the receiver at runtime so one enclave build can serve any deployment
+    // -- a SWITCHBOARD_RECEIVER_ADDRESS set at build time is only used as a pinned default
+    let contract_address = resolve_receiver_address(
+        "SWITCHBOARD_RECEIVER_ADDRESS",
+        option_env!("SWITCHBOARD_RECEIVER_ADDRESS"),
+    )
+    .unwrap();
 
+    // make sure the receiver is deployed and exposes the calls we're about to make
+    // -- BATCH_FILLS=1 fills every order in one fillOrders call, see src/orders.rs
+    let batch_fills = batch_fills();
+    let fill_selector = match batch_fills {
+        true => FillOrdersCall::selector(),
+        false => FillOrderCall::selector(),
+    };
+    verify_receiver(
+        client.clone(),
+        contract_address,
+        &[fill_selector, FailOrderCall::selector()],
+    )
+    .await
+    .unwrap();
+
+    let chain_id = client.signer().chain_id();
     let receiver_contract = Receiver::new(contract_address, client);
 
-    // Get individual call parameters and their corresponding call ids
-    let params = function_runner.params::<OrderParams>();
+    // seed from the enclave's hardware RNG, each order's value is derived from it
+    // -- dry runs outside the enclave use a random seed, their values are never sent
+    let entropy = match is_dry_run() {
+        true => EnclaveEntropy::from_seed(rand::random()),
+        false => EnclaveEntropy::from_enclave().unwrap(),
+    };
+
+    // read the orders before filling them, a fill that reverts takes the whole batch down with it
+    // -- if the reads fail, the orders are filled unchecked and the receiver decides
+    let order_requests = order_requests(&function_runner.params::<RequestEnvelope>());
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    let mut snapshot = match OrderSnapshot::read(&receiver_contract, &order_requests, now).await {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            println!("pre-flight skipped, {}", e);
+            OrderSnapshot::default()
+        }
+    };
 
-    // No calls from these addresses will be processed
+    // re-queued orders are always filled through fillOrders, see src/orders.rs
+    if !batch_fills && !snapshot.requeue.is_empty() {
+        if let Err(e) = verify_receiver(
+            receiver_contract.client(),
+            contract_address,
+            &[FillOrdersCall::selector()],
+        )
+        .await
+        {
+            println!("{}, not re-queueing unfilled orders", e);
+            snapshot.requeue.clear();
+        }
+    }
+
+    // Decide whose orders get served, see src/policy.json
     // It'll be cheaper to check off-chain in a function runner than on-chain in a contract,
     // especially if there are many
-    let blocked_senders = vec!["0x0000000000000000000000000000000000000000"];
-
-    // Handle each function call that has happened since the last run
-    let calls: Vec<ContractCall<EVMMiddleware<_>, _>> = params
+    // -- re-queued orders are checked against the policy too
+    let senders: Vec<Address> = order_requests
         .iter()
-        .filter(|param| {
-            // params in format (Result<OrderParams, Err>, call_id Address)[], one per call since last fn run
-            // here we filter out params that failed to parse & blocked params.sender addresses
-            !param.0.is_err()
-                && !blocked_senders.contains(&param.0.as_ref().unwrap().sender.to_string().as_str())
-        })
-        .map(|param| {
-            // Handle each param, call_id pair
-            let (param_result, _call_id) = param;
-            let param = param_result.as_ref().unwrap();
-            let order_id = param.order_id;
-
-            // generate a random number U256
-            let random: [u64; 4] = rand::random();
-            let random = U256(random);
-
-            // Create a contract call for each param
-            let contract_fn_call: ContractCall<EVMMiddleware<_>, _> =
-                receiver_contract.fill_order(order_id, random);
-
-            // return the contract call
-            contract_fn_call
-        })
-        .collect::<Vec<_>>();
+        .map(|(x, _)| x.sender)
+        .chain(snapshot.requeue.iter().map(|(_, x)| x.sender))
+        .collect();
+    let policy = SenderPolicy::resolve(&PolicyConfig::load(), receiver_contract.client(), &senders)
+        .await
+        .unwrap();
+
+    // one handler per kind of request the receiver sends us
+    let mut router = Router::new().register(OrderHandler {
+        receiver_contract: &receiver_contract,
+        entropy: &entropy,
+        chain_id,
+        policy,
+        snapshot,
+        batch_fills,
+    });
+
+    // Emit the result, a dry run's runner prints the calls instead
+    handle_requests(
+        &function_runner,
+        &receiver_contract,
+        &mut router,
+        expiration_time_seconds,
+        gas_limit.into(),
+    )
+    .unwrap();
+}
+
+// Handle every request made since the last run and emit the callbacks through the runner
+fn handle_requests<R: FunctionRunner>(
+    runner: &R,
+    receiver_contract: &Receiver<Client>,
+    router: &mut Router,
+    expiration_time_seconds: u64,
+    gas_limit: U256,
+) -> Result<(), RunnerError> {
+    // Get individual call parameters and their corresponding call ids
+    // -- params in format (Result<RequestEnvelope, Err>, call_id Address)[], one per call since last fn run
+    let params = runner.params::<RequestEnvelope>();
+    let pending = params.len();
+
+    // every handler spends from the same budget, what doesn't fit stays pending for later runs
+    let mut handled = router.route(params, &mut GasBudget::new(gas_limit));
+    for refusal in &handled.refused {
+        println!("{}", refusal);
+    }
+    if !handled.deferred.is_empty() {
+        println!(
+            "{} call(s) left pending for the next run",
+            handled.deferred.len()
+        );
+    }
+
+    // tell the receiver about every call we refuse, rather than resolving it silently
+    let mut calls = std::mem::take(&mut handled.calls);
+    for refusal in &handled.refused {
+        calls.push(receiver_contract.fail_order(refusal.call_id, refusal.reason.code()));
+    }
+
+    if is_dry_run() {
+        println!(
+            "DRY RUN: {} of {} pending call(s) resolved, {} refused, {} deferred",
+            handled.resolved.len(),
+            pending,
+            handled.refused.len(),
+            handled.deferred.len()
+        );
+    }
 
     // Emit the result
-    // @NOTE function_runner.emit will mark all call_ids as resolved
-    function_runner
-        .emit(
-            contract_address,
-            expiration_time_seconds.try_into().unwrap(),
+    // @NOTE runner.emit_resolve only marks the passed-in call_ids as completed, deferred calls
+    // are left for subsequent runs to handle
+    runner.emit_resolve(
+        receiver_contract.address(),
+        expiration_time_seconds.into(),
+        gas_limit,
+        calls,
+        handled.resolved_call_ids(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::{AbiDecode, AbiEncode};
+    use ethers::types::Bytes;
+    use std::collections::HashMap;
+
+    fn order_request(param: OrderParams) -> Bytes {
+        RequestEnvelope::new(ORDER_REQUEST, param.encode())
+            .encode()
+            .into()
+    }
+
+    // route the runner's calls to an order handler and emit the callbacks
+    fn fill_orders(
+        runner: &RecordingRunner,
+        receiver_contract: &Receiver<Client>,
+        entropy: &EnclaveEntropy,
+        policy: SenderPolicy,
+        gas_limit: u64,
+    ) {
+        let handler = OrderHandler {
+            receiver_contract,
+            entropy,
+            chain_id: 1,
+            policy,
+            snapshot: OrderSnapshot::default(),
+            batch_fills: false,
+        };
+        route_orders(runner, handler, gas_limit);
+    }
+
+    fn route_orders(runner: &RecordingRunner, handler: OrderHandler, gas_limit: u64) {
+        let receiver_contract = handler.receiver_contract;
+        let mut router = Router::new().register(handler);
+        handle_requests(
+            runner,
+            receiver_contract,
+            &mut router,
+            1_000,
             gas_limit.into(),
-            calls,
         )
         .unwrap();
+    }
+
+    #[test]
+    fn fills_each_decodable_order() {
+        let orders = [U256::from(1), U256::from(2)];
+        let mut params: Vec<_> = orders
+            .iter()
+            .map(|order_id| {
+                let param = OrderParams {
+                    order_id: *order_id,
+                    sender: Address::random(),
+                };
+                (order_request(param), Address::random())
+            })
+            .collect();
+        // a call whose params don't decode is refused
+        params.push((
+            RequestEnvelope::new(ORDER_REQUEST, vec![1u8, 2, 3])
+                .encode()
+                .into(),
+            Address::random(),
+        ));
+        let call_ids: Vec<Address> = params.iter().map(|x| x.1).collect();
+        let runner = RecordingRunner::with_params(params);
+        let receiver_contract = Receiver::new(Address::random(), runner.client());
+        let entropy = EnclaveEntropy::from_seed([7u8; 32]);
+
+        fill_orders(
+            &runner,
+            &receiver_contract,
+            &entropy,
+            SenderPolicy::default(),
+            1_000_000,
+        );
+
+        let emitted = runner.emitted.borrow();
+        assert_eq!(emitted.len(), 1);
+        // the undecodable call is resolved along with the fills
+        assert_eq!(emitted[0].call_ids, Some(call_ids.clone()));
+        let calls = &emitted[0].calls;
+        assert_eq!(calls.len(), 3);
+        let failed = FailOrderCall::decode(calls[2].calldata().unwrap()).unwrap();
+        assert_eq!(
+            (failed.0, failed.1),
+            (call_ids[2], FailureReason::InvalidParams.code())
+        );
+        let filled: Vec<(U256, U256)> = calls[..2]
+            .iter()
+            .map(|x| {
+                let call = FillOrderCall::decode(x.calldata().unwrap()).unwrap();
+                (call.0, call.1)
+            })
+            .collect();
+        // each order gets its own value, derived from its call id and order id
+        let expected: Vec<(U256, U256)> = orders
+            .iter()
+            .zip(call_ids)
+            .map(|(order_id, call_id)| {
+                let domain = RandomnessDomain::new(1, receiver_contract.address())
+                    .with_call(call_id)
+                    .with_order(*order_id);
+                (*order_id, entropy.derive(&domain))
+            })
+            .collect();
+        assert_eq!(filled, expected);
+    }
+
+    #[test]
+    fn fails_orders_the_policy_rejects() {
+        let blocked = Address::random();
+        let senders = [Address::random(), blocked, Address::random()];
+        let params: Vec<_> = senders
+            .iter()
+            .enumerate()
+            .map(|(i, sender)| {
+                let param = OrderParams {
+                    order_id: U256::from(i),
+                    sender: *sender,
+                };
+                (order_request(param), Address::random())
+            })
+            .collect();
+        let blocked_call_id = params[1].1;
+        let runner = RecordingRunner::with_params(params);
+        let receiver_contract = Receiver::new(Address::random(), runner.client());
+        let policy = SenderPolicy::new(&PolicyConfig {
+            senders: vec![blocked],
+            ..Default::default()
+        });
+
+        fill_orders(
+            &runner,
+            &receiver_contract,
+            &EnclaveEntropy::from_seed([7u8; 32]),
+            policy,
+            1_000_000,
+        );
+
+        let emitted = runner.emitted.borrow();
+        let calls = &emitted[0].calls;
+        let filled: Vec<U256> = calls[..2]
+            .iter()
+            .map(|x| FillOrderCall::decode(x.calldata().unwrap()).unwrap().0)
+            .collect();
+        assert_eq!(filled, vec![U256::from(0), U256::from(2)]);
+        assert_eq!(
+            calls[2].calldata().unwrap().to_vec(),
+            FailOrderCall(blocked_call_id, FailureReason::SenderBlocked.code()).encode()
+        );
+    }
+
+    #[test]
+    fn leaves_orders_over_the_gas_budget_pending() {
+        let params: Vec<_> = (1..=3)
+            .map(|order_id| {
+                let param = OrderParams {
+                    order_id: U256::from(order_id),
+                    sender: Address::random(),
+                };
+                (order_request(param), Address::random())
+            })
+            .collect();
+        let call_ids: Vec<Address> = params.iter().map(|x| x.1).collect();
+        let runner = RecordingRunner::with_params(params);
+        let receiver_contract = Receiver::new(Address::random(), runner.client());
 
-    // Alternatively if you wanted to only handle a subsset of calls in each run, you can call emit_resolve
-    // which only marks the passed-in call_ids as completed - leaving the rest for subsequent runs to handle.
-    //
-    // function_runner.emit_resolve(
-    //     contract_address,
-    //     expiration_time_seconds.try_into().unwrap(),
-    //     gas_limit.into(),
-    //     calls,
-    //     call_ids, // Vec<Address> of call_ids to resolve <--- this is the only difference
-    // ).unwrap();
+        fill_orders(
+            &runner,
+            &receiver_contract,
+            &EnclaveEntropy::from_seed([7u8; 32]),
+            SenderPolicy::default(),
+            EMIT_OVERHEAD_GAS + 2 * FILL_ORDER_GAS,
+        );
+
+        let emitted = runner.emitted.borrow();
+        assert_eq!(emitted[0].calls.len(), 2);
+        assert_eq!(emitted[0].call_ids, Some(call_ids[..2].to_vec()));
+    }
+
+    #[test]
+    fn fails_requests_of_unknown_kinds() {
+        let order = OrderParams {
+            order_id: U256::one(),
+            sender: Address::random(),
+        };
+        let unknown = RequestEnvelope::new(ORDER_REQUEST + 1, vec![]);
+        let params = vec![
+            (order_request(order), Address::random()),
+            (unknown.encode().into(), Address::random()),
+        ];
+        let unknown_call_id = params[1].1;
+        let runner = RecordingRunner::with_params(params);
+        let receiver_contract = Receiver::new(Address::random(), runner.client());
+
+        fill_orders(
+            &runner,
+            &receiver_contract,
+            &EnclaveEntropy::from_seed([7u8; 32]),
+            SenderPolicy::default(),
+            1_000_000,
+        );
+
+        let emitted = runner.emitted.borrow();
+        let calls = &emitted[0].calls;
+        assert_eq!(calls.len(), 2);
+        assert_eq!(
+            FillOrderCall::decode(calls[0].calldata().unwrap())
+                .unwrap()
+                .0,
+            U256::one()
+        );
+        assert_eq!(
+            calls[1].calldata().unwrap().to_vec(),
+            FailOrderCall(unknown_call_id, FailureReason::UnknownKind.code()).encode()
+        );
+    }
+
+    #[test]
+    fn checks_orders_before_filling_them() {
+        let params: Vec<_> = (1..=3)
+            .map(|order_id| {
+                let param = OrderParams {
+                    order_id: U256::from(order_id),
+                    sender: Address::random(),
+                };
+                (order_request(param), Address::random())
+            })
+            .collect();
+        let call_ids: Vec<Address> = params.iter().map(|x| x.1).collect();
+        let state = |call_id: Address| OrderState {
+            call_id,
+            sender: Address::random(),
+            filled: false,
+            failed: false,
+            created_at: 0,
+        };
+        let stranded = state(Address::random());
+        let orders = HashMap::from([
+            (U256::from(1), state(call_ids[0])),
+            // already filled, resolved without a callback
+            (
+                U256::from(2),
+                OrderState {
+                    filled: true,
+                    ..state(call_ids[1])
+                },
+            ),
+            // unknown to the receiver
+            (
+                U256::from(3),
+                OrderState {
+                    sender: Address::zero(),
+                    ..state(Address::zero())
+                },
+            ),
+            // an earlier run resolved its call without filling it
+            (U256::from(0), stranded.clone()),
+        ]);
+        let runner = RecordingRunner::with_params(params);
+        let pending = order_requests(&runner.params::<RequestEnvelope>());
+        let snapshot = OrderSnapshot::new(orders, &pending, REQUEUE_AFTER);
+        let receiver_contract = Receiver::new(Address::random(), runner.client());
+
+        let handler = OrderHandler {
+            receiver_contract: &receiver_contract,
+            entropy: &EnclaveEntropy::from_seed([7u8; 32]),
+            chain_id: 1,
+            policy: SenderPolicy::default(),
+            snapshot,
+            batch_fills: false,
+        };
+        route_orders(&runner, handler, 1_000_000);
+
+        let emitted = runner.emitted.borrow();
+        // the settled call is resolved as soon as it's checked
+        assert_eq!(
+            emitted[0].call_ids,
+            Some(vec![call_ids[1], call_ids[0], call_ids[2]])
+        );
+        let calls = &emitted[0].calls;
+        assert_eq!(calls.len(), 3);
+        let filled = FillOrderCall::decode(calls[0].calldata().unwrap()).unwrap();
+        assert_eq!(filled.0, U256::from(1));
+        // the stranded order goes through fillOrders, which skips it if a fill is still in flight
+        let requeued = FillOrdersCall::decode(calls[1].calldata().unwrap()).unwrap();
+        assert_eq!(requeued.0, vec![U256::from(0)]);
+        assert_eq!(
+            calls[2].calldata().unwrap().to_vec(),
+            FailOrderCall(call_ids[2], FailureReason::UnknownOrder.code()).encode()
+        );
+    }
+
+    #[test]
+    fn requeues_orders_without_pending_calls() {
+        let blocked = Address::random();
+        let state = |sender: Address| OrderState {
+            call_id: Address::random(),
+            sender,
+            filled: false,
+            failed: false,
+            created_at: 0,
+        };
+        let orders = HashMap::from([
+            (U256::from(1), state(Address::random())),
+            // the policy blocks its sender, it's left alone rather than failed
+            (U256::from(2), state(blocked)),
+        ]);
+        let runner = RecordingRunner::new();
+        let snapshot = OrderSnapshot::new(orders, &[], REQUEUE_AFTER);
+        let receiver_contract = Receiver::new(Address::random(), runner.client());
+        let handler = OrderHandler {
+            receiver_contract: &receiver_contract,
+            entropy: &EnclaveEntropy::from_seed([7u8; 32]),
+            chain_id: 1,
+            policy: SenderPolicy::new(&PolicyConfig {
+                senders: vec![blocked],
+                ..Default::default()
+            }),
+            snapshot,
+            batch_fills: false,
+        };
+        route_orders(&runner, handler, 1_000_000);
+
+        let emitted = runner.emitted.borrow();
+        assert_eq!(emitted[0].call_ids, Some(vec![]));
+        let calls = &emitted[0].calls;
+        assert_eq!(calls.len(), 1);
+        let requeued = FillOrdersCall::decode(calls[0].calldata().unwrap()).unwrap();
+        assert_eq!(requeued.0, vec![U256::from(1)]);
+    }
+
+    #[test]
+    fn batches_fills_into_one_call() {
+        let params: Vec<_> = (1..=3)
+            .map(|order_id| {
+                let param = OrderParams {
+                    order_id: U256::from(order_id),
+                    sender: Address::random(),
+                };
+                (order_request(param), Address::random())
+            })
+            .collect();
+        let call_ids: Vec<Address> = params.iter().map(|x| x.1).collect();
+        let runner = RecordingRunner::with_params(params);
+        let receiver_contract = Receiver::new(Address::random(), runner.client());
+        let entropy = EnclaveEntropy::from_seed([7u8; 32]);
+        let handler = OrderHandler {
+            receiver_contract: &receiver_contract,
+            entropy: &entropy,
+            chain_id: 1,
+            policy: SenderPolicy::default(),
+            snapshot: OrderSnapshot::default(),
+            batch_fills: true,
+        };
+
+        // the gas that fits two separate fills fits three batched ones
+        route_orders(
+            &runner,
+            handler,
+            EMIT_OVERHEAD_GAS + BATCH_OVERHEAD_GAS + 2 * BATCHED_FILL_GAS + FAIL_ORDER_GAS,
+        );
+
+        let emitted = runner.emitted.borrow();
+        assert_eq!(emitted[0].call_ids, Some(call_ids.clone()));
+        let calls = &emitted[0].calls;
+        assert_eq!(calls.len(), 1);
+        let batch = FillOrdersCall::decode(calls[0].calldata().unwrap()).unwrap();
+        let expected: Vec<(U256, U256)> = call_ids
+            .iter()
+            .enumerate()
+            .map(|(i, call_id)| {
+                let order_id = U256::from(i + 1);
+                let domain = RandomnessDomain::new(1, receiver_contract.address())
+                    .with_call(*call_id)
+                    .with_order(order_id);
+                (order_id, entropy.derive(&domain))
+            })
+            .collect();
+        assert_eq!(
+            batch.0.into_iter().zip(batch.1).collect::<Vec<_>>(),
+            expected
+        );
+    }
 }
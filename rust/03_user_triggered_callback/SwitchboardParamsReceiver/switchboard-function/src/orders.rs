@@ -0,0 +1,228 @@
+use crate::*;
+
+use ethers::abi::AbiDecode;
+use ethers::types::Bytes;
+
+// The kind the receiver tags createOrder's requests with
+pub const ORDER_REQUEST: u16 = 1;
+
+// Fills orders with a random value bound to each order
+pub struct OrderHandler<'a> {
+    pub receiver_contract: &'a Receiver<Client>,
+    pub entropy: &'a EnclaveEntropy,
+    pub chain_id: u64,
+    pub policy: SenderPolicy,
+    // the orders' state on-chain, see OrderSnapshot::read
+    pub snapshot: OrderSnapshot,
+    // fill every order in one fillOrders call instead of a fillOrder call each
+    pub batch_fills: bool,
+}
+
+impl OrderHandler<'_> {
+    // what a single fill is budgeted at
+    fn fill_gas(&self) -> u64 {
+        match self.batch_fills {
+            true => BATCHED_FILL_GAS,
+            false => FILL_ORDER_GAS,
+        }
+    }
+
+    // derive a random number U256 bound to this exact order
+    fn value(&self, order_id: U256, sender: Address, call_id: Address) -> U256 {
+        let domain = RandomnessDomain::new(self.chain_id, self.receiver_contract.address())
+            .with_call(call_id)
+            .with_order(order_id);
+        let random = self.entropy.derive(&domain);
+
+        if is_dry_run() {
+            println!(
+                "DRY RUN: order {} from {:?} (call id {:?}) -> {}",
+                order_id, sender, call_id, random
+            );
+        }
+        random
+    }
+
+    // Create a contract call for each order, or a single one for all of them when batching
+    fn fill_calls(&self, fills: Vec<(U256, U256)>) -> Vec<Callback> {
+        if fills.is_empty() {
+            return vec![];
+        }
+        if self.batch_fills {
+            let (order_ids, values) = fills.into_iter().unzip();
+            return vec![self.receiver_contract.fill_orders(order_ids, values)];
+        }
+        fills
+            .into_iter()
+            .map(|(order_id, value)| self.receiver_contract.fill_order(order_id, value))
+            .collect()
+    }
+
+    // Fill the orders whose calls an earlier run resolved without the order being filled, e.g.
+    // because its transaction reverted. Their calls are already resolved, so these only add fills.
+    // They always go through fillOrders, which skips orders that are no longer open, so a fill of
+    // the same order that's still in flight can't revert the run.
+    fn requeue(&mut self, budget: &mut GasBudget) -> Vec<(U256, U256)> {
+        let mut fills = vec![];
+        for (order_id, order) in &self.snapshot.requeue {
+            // unbatched runs pay for a fillOrders call of their own
+            let overhead = match !self.batch_fills && fills.is_empty() {
+                true => BATCH_OVERHEAD_GAS,
+                false => 0,
+            };
+            if !budget.fits(overhead + BATCHED_FILL_GAS) {
+                break;
+            }
+            // failing an order whose fill is in flight would revert the run, so orders the policy
+            // rejects are left alone
+            if let Err(rejection) = self.policy.check(order.sender) {
+                println!(
+                    "order {} was never filled, not re-queueing it: {}",
+                    order_id, rejection
+                );
+                continue;
+            }
+            budget.spend(overhead + BATCHED_FILL_GAS);
+            println!("order {} was never filled, re-queueing it", order_id);
+            fills.push((
+                *order_id,
+                self.value(*order_id, order.sender, order.call_id),
+            ));
+        }
+        fills
+    }
+}
+
+impl RequestHandler for OrderHandler<'_> {
+    fn kind(&self) -> u16 {
+        ORDER_REQUEST
+    }
+
+    fn handle(&mut self, requests: Vec<(Bytes, Address)>, budget: &mut GasBudget) -> Handled {
+        let mut handled = Handled::default();
+        let mut fills = vec![];
+
+        // a batch pays its overhead once, however many orders it ends up with
+        if self.batch_fills && !(requests.is_empty() && self.snapshot.requeue.is_empty()) {
+            budget.spend(BATCH_OVERHEAD_GAS);
+        }
+
+        // orders left over from earlier runs are the oldest, they go first
+        let requeued = self.requeue(budget);
+        if self.batch_fills {
+            fills.extend(requeued.iter().copied());
+        }
+
+        // drop the orders whose fill would revert
+        let mut params = vec![];
+        let mut unknown = vec![];
+        for (payload, call_id) in requests {
+            let param = match OrderParams::decode(payload) {
+                Ok(param) => param,
+                Err(e) => {
+                    params.push((Err(RunnerError(e.to_string())), call_id));
+                    continue;
+                }
+            };
+            match self.snapshot.check(&param, call_id) {
+                Preflight::Fill => params.push((Ok(param), call_id)),
+                Preflight::Settled => {
+                    println!(
+                        "order {} is already settled, resolving call {:?}",
+                        param.order_id, call_id
+                    );
+                    handled.resolved.push(call_id);
+                }
+                Preflight::Unknown(detail) => unknown.push(Refusal {
+                    call_id,
+                    reason: FailureReason::UnknownOrder,
+                    detail,
+                }),
+            }
+        }
+
+        // only fill as many orders as fit in the gas limit, the rest stay pending for later runs
+        let fill_gas = self.fill_gas();
+        let plan = plan_fills(params, &mut self.policy, budget, fill_gas);
+
+        // Handle each order that made it into this run
+        for (param, call_id) in &plan.fills {
+            fills.push((
+                param.order_id,
+                self.value(param.order_id, param.sender, *call_id),
+            ));
+            handled.resolved.push(*call_id);
+        }
+        handled.calls = self.fill_calls(fills);
+        if !self.batch_fills && !requeued.is_empty() {
+            let (order_ids, values) = requeued.into_iter().unzip();
+            handled
+                .calls
+                .push(self.receiver_contract.fill_orders(order_ids, values));
+        }
+        handled.refused.extend(plan.rejected);
+        handled.deferred.extend(plan.deferred);
+        for refusal in unknown {
+            match budget.spend(FAIL_ORDER_GAS) {
+                true => handled.refused.push(refusal),
+                false => handled.deferred.push(refusal.call_id),
+            }
+        }
+        handled
+    }
+}
+
+// Whether to fill every order in one fillOrders call, enable with BATCH_FILLS=1. The receiver has
+// to implement fillOrders.
+pub fn batch_fills() -> bool {
+    std::env::var("BATCH_FILLS")
+        .map(|x| x == "1" || x.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+// This run's order requests, for resolving the sender policy and reading the orders up front
+pub fn order_requests<E>(
+    params: &[(Result<RequestEnvelope, E>, Address)],
+) -> Vec<(OrderParams, Address)> {
+    params
+        .iter()
+        .filter_map(|(envelope, call_id)| Some((envelope.as_ref().ok()?, *call_id)))
+        .filter(|(x, _)| x.version == ENVELOPE_VERSION && x.kind == ORDER_REQUEST)
+        .filter_map(|(x, call_id)| Some((OrderParams::decode(&x.payload).ok()?, call_id)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::AbiEncode;
+
+    #[test]
+    fn collects_order_requests_only() {
+        let sender = Address::random();
+        let order = OrderParams {
+            order_id: U256::one(),
+            sender,
+        };
+        let call_id = Address::random();
+        let params: Vec<(Result<_, RunnerError>, _)> = vec![
+            (
+                Ok(RequestEnvelope::new(ORDER_REQUEST, order.clone().encode())),
+                call_id,
+            ),
+            (
+                Ok(RequestEnvelope::new(ORDER_REQUEST + 1, order.encode())),
+                Address::random(),
+            ),
+            (
+                Ok(RequestEnvelope::new(ORDER_REQUEST, vec![1u8, 2, 3])),
+                Address::random(),
+            ),
+        ];
+        let requests: Vec<(Address, Address)> = order_requests(&params)
+            .into_iter()
+            .map(|(x, call_id)| (x.sender, call_id))
+            .collect();
+        assert_eq!(requests, vec![(sender, call_id)]);
+    }
+}
@@ -0,0 +1,198 @@
+use ethers::{prelude::abigen, providers::Middleware, types::Address};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+// An on-chain list of senders, see contracts/src/SenderRegistry.sol
+abigen!(
+    SenderRegistry,
+    r#"[
+        function contains(address) view returns (bool)
+    ]"#,
+);
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyMode {
+    // only listed senders are served
+    Allowlist,
+    // everyone but the listed senders is served
+    #[default]
+    Blocklist,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct PolicyConfig {
+    pub mode: PolicyMode,
+    pub senders: Vec<Address>,
+    // a SenderRegistry whose entries are listed alongside `senders`
+    pub registry: Option<Address>,
+    // orders served per sender in a single run, the rest wait for a later run
+    pub max_orders_per_sender: Option<usize>,
+}
+
+impl PolicyConfig {
+    // The policy is compiled into the binary so it's covered by the enclave measurement
+    pub fn load() -> Self {
+        serde_json::from_str(include_str!("policy.json")).unwrap()
+    }
+}
+
+#[derive(Debug)]
+pub enum PolicyError {
+    // the registry couldn't be queried, serving everyone instead would defeat the policy
+    Registry(Address, String),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyError::Registry(registry, err) => {
+                write!(f, "failed to query sender registry {:?}: {}", registry, err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+// Why a sender's order wasn't served
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    Blocked(Address),
+    NotAllowed(Address),
+    // the sender already had this many orders served this run
+    LimitExceeded(Address, usize),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Blocked(sender) => write!(f, "sender {:?} is blocked", sender),
+            Rejection::NotAllowed(sender) => {
+                write!(f, "sender {:?} is not on the allowlist", sender)
+            }
+            Rejection::LimitExceeded(sender, limit) => write!(
+                f,
+                "sender {:?} reached the limit of {} order(s) per run",
+                sender, limit
+            ),
+        }
+    }
+}
+
+// The policy for a single run. Keeps count of what each sender was served so far, so orders have
+// to be checked in the order they'll be filled.
+#[derive(Debug, Clone, Default)]
+pub struct SenderPolicy {
+    pub mode: PolicyMode,
+    pub listed: HashSet<Address>,
+    pub max_orders_per_sender: Option<usize>,
+    served: HashMap<Address, usize>,
+}
+
+impl SenderPolicy {
+    pub fn new(config: &PolicyConfig) -> Self {
+        SenderPolicy {
+            mode: config.mode,
+            listed: config.senders.iter().copied().collect(),
+            max_orders_per_sender: config.max_orders_per_sender,
+            served: HashMap::new(),
+        }
+    }
+
+    // Build the policy, looking up `senders` in the configured registry. Only the senders with
+    // pending orders are queried, which keeps the run's RPC calls bounded.
+    pub async fn resolve<M: Middleware + 'static>(
+        config: &PolicyConfig,
+        client: Arc<M>,
+        senders: &[Address],
+    ) -> Result<Self, PolicyError> {
+        let mut policy = SenderPolicy::new(config);
+        let registry_address = match config.registry {
+            Some(registry_address) => registry_address,
+            None => return Ok(policy),
+        };
+        let registry = SenderRegistry::new(registry_address, client);
+        for sender in senders.iter().collect::<HashSet<_>>() {
+            let listed = registry
+                .contains(*sender)
+                .call()
+                .await
+                .map_err(|e| PolicyError::Registry(registry_address, e.to_string()))?;
+            if listed {
+                policy.listed.insert(*sender);
+            }
+        }
+        Ok(policy)
+    }
+
+    // Check the sender's next order, counting it as served if it passes
+    pub fn check(&mut self, sender: Address) -> Result<(), Rejection> {
+        let listed = self.listed.contains(&sender);
+        match self.mode {
+            PolicyMode::Blocklist if listed => return Err(Rejection::Blocked(sender)),
+            PolicyMode::Allowlist if !listed => return Err(Rejection::NotAllowed(sender)),
+            _ => {}
+        }
+        let served = self.served.entry(sender).or_default();
+        if let Some(limit) = self.max_orders_per_sender {
+            if *served >= limit {
+                return Err(Rejection::LimitExceeded(sender, limit));
+            }
+        }
+        *served += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_bundled_policy() {
+        let config = PolicyConfig::load();
+        assert_eq!(config.mode, PolicyMode::Blocklist);
+        assert!(SenderPolicy::new(&config).check(Address::zero()).is_err());
+    }
+
+    #[test]
+    fn compares_full_addresses() {
+        let blocked: Address = "0x1111111111111111111111111111111111111111"
+            .parse()
+            .unwrap();
+        // shares the first and last bytes that Address's Display keeps
+        let other: Address = "0x1111111111111111111111111111111111221111"
+            .parse()
+            .unwrap();
+        let config: PolicyConfig = serde_json::from_str(
+            r#"{ "senders": ["0x1111111111111111111111111111111111111111"] }"#,
+        )
+        .unwrap();
+        let mut policy = SenderPolicy::new(&config);
+        assert_eq!(policy.check(blocked), Err(Rejection::Blocked(blocked)));
+        assert_eq!(policy.check(other), Ok(()));
+    }
+
+    #[test]
+    fn enforces_the_allowlist_and_limits() {
+        let allowed = Address::repeat_byte(1);
+        let stranger = Address::repeat_byte(2);
+        let mut policy = SenderPolicy::new(&PolicyConfig {
+            mode: PolicyMode::Allowlist,
+            senders: vec![allowed],
+            registry: None,
+            max_orders_per_sender: Some(2),
+        });
+        assert_eq!(policy.check(stranger), Err(Rejection::NotAllowed(stranger)));
+        assert_eq!(policy.check(allowed), Ok(()));
+        assert_eq!(policy.check(allowed), Ok(()));
+        assert_eq!(
+            policy.check(allowed),
+            Err(Rejection::LimitExceeded(allowed, 2))
+        );
+    }
+}
@@ -0,0 +1,279 @@
+use crate::*;
+
+use ethers::{
+    abi::{Token, Tokenizable},
+    contract::Multicall,
+    providers::Middleware,
+};
+use futures::future::join_all;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// How many of the most recent orders are checked for ones that were never filled
+pub const REQUEUE_WINDOW: u64 = 256;
+
+// An unfilled order is only re-queued once it's this old, in seconds. Younger orders may still
+// have a callback on its way.
+pub const REQUEUE_AFTER: u64 = 600;
+
+// Orders read per eth_call when batching through Multicall
+const MULTICALL_BATCH: usize = 100;
+
+#[derive(Debug)]
+pub enum PreflightError {
+    Provider(String),
+}
+
+impl fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreflightError::Provider(err) => write!(f, "failed to read order state: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for PreflightError {}
+
+// An order as the receiver has it on record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderState {
+    pub call_id: Address,
+    // the zero address for orders the receiver doesn't know
+    pub sender: Address,
+    pub filled: bool,
+    pub failed: bool,
+    pub created_at: u64,
+}
+
+type OrderTuple = (Address, Address, U256, bool, U256, u8, U256);
+
+impl From<OrderTuple> for OrderState {
+    fn from(order: OrderTuple) -> Self {
+        let (call_id, sender, _value, filled, _fee, failure, created_at) = order;
+        OrderState {
+            call_id,
+            sender,
+            filled,
+            failed: failure != 0,
+            created_at: created_at.low_u64(),
+        }
+    }
+}
+
+// What to do with a pending call, given its order's state on-chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Preflight {
+    Fill,
+    // the order was already filled or failed, resolve the call without a callback
+    Settled,
+    // the receiver has no such order for this call
+    Unknown(String),
+}
+
+// The state of this run's orders, read before any callback is built
+#[derive(Debug, Clone, Default)]
+pub struct OrderSnapshot {
+    // keyed by order id, orders whose state couldn't be read are missing
+    pub orders: HashMap<U256, OrderState>,
+    // unfilled orders whose calls were resolved by an earlier run, oldest first
+    pub requeue: Vec<(U256, OrderState)>,
+}
+
+impl OrderSnapshot {
+    // Read the pending orders and the last REQUEUE_WINDOW orders from the receiver
+    pub async fn read<M: Middleware + 'static>(
+        receiver_contract: &Receiver<M>,
+        pending: &[(OrderParams, Address)],
+        now: u64,
+    ) -> Result<Self, PreflightError> {
+        let next_order_id = receiver_contract
+            .next_order_id()
+            .call()
+            .await
+            .map_err(|e| PreflightError::Provider(e.to_string()))?;
+        let first = next_order_id
+            .saturating_sub(REQUEUE_WINDOW.into())
+            .max(U256::one());
+        let mut order_ids: Vec<U256> = pending.iter().map(|(param, _)| param.order_id).collect();
+        let mut id = first;
+        while id < next_order_id {
+            order_ids.push(id);
+            id += U256::one();
+        }
+        order_ids.sort();
+        order_ids.dedup();
+
+        let orders = read_orders(receiver_contract, &order_ids).await?;
+        Ok(Self::new(orders, pending, now))
+    }
+
+    pub fn new(
+        orders: HashMap<U256, OrderState>,
+        pending: &[(OrderParams, Address)],
+        now: u64,
+    ) -> Self {
+        let pending_call_ids: HashSet<Address> =
+            pending.iter().map(|(_, call_id)| *call_id).collect();
+        let mut requeue: Vec<(U256, OrderState)> = orders
+            .iter()
+            .filter(|(_, x)| {
+                x.sender != Address::zero()
+                    && !x.filled
+                    && !x.failed
+                    && !pending_call_ids.contains(&x.call_id)
+                    && now >= x.created_at.saturating_add(REQUEUE_AFTER)
+            })
+            .map(|(order_id, x)| (*order_id, x.clone()))
+            .collect();
+        requeue.sort_by_key(|(order_id, _)| *order_id);
+        OrderSnapshot { orders, requeue }
+    }
+
+    // What to do with the pending call for `param`. Orders that couldn't be read are filled and
+    // left for the receiver to check.
+    pub fn check(&self, param: &OrderParams, call_id: Address) -> Preflight {
+        let order = match self.orders.get(&param.order_id) {
+            Some(order) => order,
+            None => return Preflight::Fill,
+        };
+        if order.sender == Address::zero() {
+            return Preflight::Unknown(format!("order {} doesn't exist", param.order_id));
+        }
+        if order.call_id != call_id {
+            return Preflight::Unknown(format!(
+                "order {} was created by call {:?}",
+                param.order_id, order.call_id
+            ));
+        }
+        if order.filled || order.failed {
+            return Preflight::Settled;
+        }
+        Preflight::Fill
+    }
+}
+
+// Read each order's state, through Multicall3 where it's deployed and with one eth_call per order
+// everywhere else. Orders whose read reverts are left out.
+async fn read_orders<M: Middleware + 'static>(
+    receiver_contract: &Receiver<M>,
+    order_ids: &[U256],
+) -> Result<HashMap<U256, OrderState>, PreflightError> {
+    let client = receiver_contract.client();
+    let mut orders = HashMap::new();
+    if let Ok(mut multicall) = Multicall::new(client, None).await {
+        for batch in order_ids.chunks(MULTICALL_BATCH) {
+            multicall.clear_calls();
+            for order_id in batch {
+                multicall.add_call(receiver_contract.orders(*order_id), true);
+            }
+            let results = multicall
+                .call_raw()
+                .await
+                .map_err(|e| PreflightError::Provider(e.to_string()))?;
+            for (order_id, result) in batch.iter().zip(results) {
+                if let Some(order) = result.ok().and_then(decode_order) {
+                    orders.insert(*order_id, order);
+                }
+            }
+        }
+        return Ok(orders);
+    }
+
+    let calls = order_ids.iter().map(|order_id| async move {
+        let order = receiver_contract.orders(*order_id).call().await;
+        (*order_id, order)
+    });
+    for (order_id, order) in join_all(calls).await {
+        if let Ok(order) = order {
+            orders.insert(order_id, order.into());
+        }
+    }
+    Ok(orders)
+}
+
+fn decode_order(token: Token) -> Option<OrderState> {
+    OrderTuple::from_token(token).ok().map(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(call_id: Address, created_at: u64) -> OrderState {
+        OrderState {
+            call_id,
+            sender: Address::random(),
+            filled: false,
+            failed: false,
+            created_at,
+        }
+    }
+
+    fn param(order_id: u64) -> OrderParams {
+        OrderParams {
+            order_id: U256::from(order_id),
+            sender: Address::random(),
+        }
+    }
+
+    #[test]
+    fn checks_pending_calls_against_their_orders() {
+        let call_id = Address::random();
+        let orders = HashMap::from([
+            (U256::from(1), order(call_id, 0)),
+            (
+                U256::from(2),
+                OrderState {
+                    filled: true,
+                    ..order(call_id, 0)
+                },
+            ),
+            (
+                U256::from(3),
+                OrderState {
+                    sender: Address::zero(),
+                    ..order(Address::zero(), 0)
+                },
+            ),
+        ]);
+        let snapshot = OrderSnapshot::new(orders, &[], 0);
+
+        assert_eq!(snapshot.check(&param(1), call_id), Preflight::Fill);
+        assert_eq!(snapshot.check(&param(2), call_id), Preflight::Settled);
+        assert!(matches!(
+            snapshot.check(&param(3), call_id),
+            Preflight::Unknown(_)
+        ));
+        // someone else's order
+        assert!(matches!(
+            snapshot.check(&param(1), Address::random()),
+            Preflight::Unknown(_)
+        ));
+        // not read, the receiver gets to decide
+        assert_eq!(snapshot.check(&param(4), call_id), Preflight::Fill);
+    }
+
+    #[test]
+    fn requeues_old_unfilled_orders_without_a_pending_call() {
+        let pending_call_id = Address::random();
+        let orders = HashMap::from([
+            (U256::from(3), order(Address::random(), 0)),
+            (U256::from(1), order(Address::random(), 0)),
+            // still pending
+            (U256::from(2), order(pending_call_id, 0)),
+            // too recent, its callback may still land
+            (U256::from(4), order(Address::random(), 1_000)),
+            (
+                U256::from(5),
+                OrderState {
+                    failed: true,
+                    ..order(Address::random(), 0)
+                },
+            ),
+        ]);
+        let snapshot = OrderSnapshot::new(orders, &[(param(2), pending_call_id)], 1_000);
+
+        let requeued: Vec<U256> = snapshot.requeue.iter().map(|x| x.0).collect();
+        assert_eq!(requeued, vec![U256::from(1), U256::from(3)]);
+    }
+}
@@ -0,0 +1,154 @@
+use ethers::{
+    abi::{encode, Token},
+    types::{Address, U256},
+    utils::keccak256,
+};
+use std::fmt;
+use switchboard_common::Gramine;
+
+// Tags every derivation so outputs can't collide with any other use of the seed. Bump the
+// version if the encoding below ever changes.
+pub const RANDOMNESS_DOMAIN_TAG: &str = "switchboard.randomness.v1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RandomnessError {
+    // the enclave couldn't provide entropy, never fall back to a weaker source
+    Entropy(String),
+}
+
+impl fmt::Display for RandomnessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RandomnessError::Entropy(err) => {
+                write!(f, "failed to read enclave entropy: {}", err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RandomnessError {}
+
+// What a random value is for. Every field goes into the derivation (and the VRF seed), so a value
+// derived for one request can never be replayed as the value for another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RandomnessDomain {
+    pub chain_id: u64,
+    pub receiver: Address,
+    // zero for scheduled runs that aren't answering a call
+    pub call_id: Address,
+    // zero when the request has no order, scheduled VRF runs use the receiver's round
+    pub order_id: U256,
+}
+
+impl RandomnessDomain {
+    pub fn new(chain_id: u64, receiver: Address) -> Self {
+        RandomnessDomain {
+            chain_id,
+            receiver,
+            call_id: Address::zero(),
+            order_id: U256::zero(),
+        }
+    }
+
+    pub fn with_call(self, call_id: Address) -> Self {
+        RandomnessDomain { call_id, ..self }
+    }
+
+    pub fn with_order(self, order_id: U256) -> Self {
+        RandomnessDomain { order_id, ..self }
+    }
+
+    // keccak256(abi.encode(chainId, receiver, callId, orderId)), the seed a VRF proves for. A
+    // receiver that verifies proofs computes the same seed on-chain so the function can't pick
+    // its own.
+    pub fn seed(&self) -> U256 {
+        let preimage = encode(&[
+            Token::Uint(self.chain_id.into()),
+            Token::Address(self.receiver),
+            Token::Address(self.call_id),
+            Token::Uint(self.order_id),
+        ]);
+        U256::from_big_endian(&keccak256(preimage))
+    }
+}
+
+// A 32 byte seed drawn from the enclave's hardware RNG once per run. Outputs are never the seed
+// itself, only values derived from it for a specific domain.
+pub struct EnclaveEntropy {
+    seed: [u8; 32],
+}
+
+impl EnclaveEntropy {
+    pub fn from_enclave() -> Result<Self, RandomnessError> {
+        let mut seed = [0u8; 32];
+        Gramine::read_rand(&mut seed).map_err(|e| RandomnessError::Entropy(format!("{:?}", e)))?;
+        Ok(EnclaveEntropy { seed })
+    }
+
+    // Only for tests and test vectors, a known seed makes every output predictable
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        EnclaveEntropy { seed }
+    }
+
+    // keccak256(abi.encode(tag, seed, chainId, receiver, callId, orderId))
+    pub fn derive(&self, domain: &RandomnessDomain) -> U256 {
+        let preimage = encode(&[
+            Token::String(RANDOMNESS_DOMAIN_TAG.to_string()),
+            Token::FixedBytes(self.seed.to_vec()),
+            Token::Uint(domain.chain_id.into()),
+            Token::Address(domain.receiver),
+            Token::Address(domain.call_id),
+            Token::Uint(domain.order_id),
+        ]);
+        U256::from_big_endian(&keccak256(preimage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derivation_is_bound_to_every_field() {
+        let entropy = EnclaveEntropy::from_seed([7u8; 32]);
+        let receiver = Address::repeat_byte(1);
+        let base = RandomnessDomain::new(1, receiver)
+            .with_call(Address::repeat_byte(2))
+            .with_order(U256::from(3));
+
+        let value = entropy.derive(&base);
+        assert_eq!(value, entropy.derive(&base));
+
+        let others = [
+            RandomnessDomain {
+                chain_id: 2,
+                ..base
+            },
+            RandomnessDomain {
+                receiver: Address::repeat_byte(9),
+                ..base
+            },
+            base.with_call(Address::repeat_byte(9)),
+            base.with_order(U256::from(4)),
+        ];
+        for other in others {
+            assert_ne!(value, entropy.derive(&other));
+            assert_ne!(base.seed(), other.seed());
+        }
+        assert_ne!(value, EnclaveEntropy::from_seed([8u8; 32]).derive(&base));
+    }
+
+    #[test]
+    fn matches_test_vector() {
+        // pins the encoding, this only changes along with RANDOMNESS_DOMAIN_TAG
+        let domain = RandomnessDomain::new(1, Address::repeat_byte(1)).with_order(U256::from(3));
+        assert_eq!(
+            EnclaveEntropy::from_seed([7u8; 32]).derive(&domain),
+            U256::from_str_radix(
+                "97bd450b47662e8c82c560c03ac23a02a6ef22cdb53c868510da62757c50b61c",
+                16
+            )
+            .unwrap()
+        );
+    }
+}
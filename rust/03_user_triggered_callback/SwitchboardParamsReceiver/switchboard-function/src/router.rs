@@ -0,0 +1,239 @@
+use crate::*;
+
+use ethers::types::Bytes;
+use std::collections::HashMap;
+
+// Envelopes with any other version are refused, bump it if the envelope layout ever changes
+pub const ENVELOPE_VERSION: u8 = 1;
+
+// What every call's params decode as. The receiver wraps each request's own params in `payload`
+// and tags them with the kind of request, so one function can serve several kinds.
+#[derive(Debug, Clone, PartialEq, Eq, EthAbiType, EthAbiCodec)]
+pub struct RequestEnvelope {
+    pub version: u8,
+    pub kind: u16,
+    pub payload: Bytes,
+}
+
+impl RequestEnvelope {
+    pub fn new(kind: u16, payload: impl Into<Bytes>) -> Self {
+        RequestEnvelope {
+            version: ENVELOPE_VERSION,
+            kind,
+            payload: payload.into(),
+        }
+    }
+}
+
+// The outcome of handling a set of calls
+#[derive(Default)]
+pub struct Handled {
+    pub calls: Vec<Callback>,
+    // resolved by `calls`
+    pub resolved: Vec<Address>,
+    // resolved with a failure callback
+    pub refused: Vec<Refusal>,
+    // left pending for a later run
+    pub deferred: Vec<Address>,
+}
+
+impl Handled {
+    pub fn extend(&mut self, other: Handled) {
+        self.calls.extend(other.calls);
+        self.resolved.extend(other.resolved);
+        self.refused.extend(other.refused);
+        self.deferred.extend(other.deferred);
+    }
+
+    // the call ids this run resolves
+    pub fn resolved_call_ids(&self) -> Vec<Address> {
+        self.resolved
+            .iter()
+            .copied()
+            .chain(self.refused.iter().map(|x| x.call_id))
+            .collect()
+    }
+}
+
+// Handles one kind of request
+pub trait RequestHandler {
+    // the kind requests for this handler are tagged with
+    fn kind(&self) -> u16;
+
+    // Handle this run's requests of this kind, spending from `budget`. Every call has to end up
+    // resolved, refused or deferred. Handlers are called every run, with no requests if none
+    // are pending, so they can do work of their own.
+    fn handle(&mut self, requests: Vec<(Bytes, Address)>, budget: &mut GasBudget) -> Handled;
+}
+
+// Hands each call to the handler registered for its kind
+#[derive(Default)]
+pub struct Router<'a> {
+    handlers: Vec<Box<dyn RequestHandler + 'a>>,
+}
+
+impl<'a> Router<'a> {
+    pub fn new() -> Self {
+        Router { handlers: vec![] }
+    }
+
+    pub fn register(mut self, handler: impl RequestHandler + 'a) -> Self {
+        if self.handlers.iter().any(|x| x.kind() == handler.kind()) {
+            panic!(
+                "a handler for request kind {} is already registered",
+                handler.kind()
+            );
+        }
+        self.handlers.push(Box::new(handler));
+        self
+    }
+
+    // Route the calls to their handlers, in the order the handlers were registered. Every
+    // handler is called, whether or not any calls are of its kind. Calls that can't be routed are
+    // refused once every handler has had its share of the budget.
+    pub fn route(
+        &mut self,
+        params: Vec<(Result<RequestEnvelope, RunnerError>, Address)>,
+        budget: &mut GasBudget,
+    ) -> Handled {
+        let mut by_kind: HashMap<u16, Vec<(Bytes, Address)>> = HashMap::new();
+        let mut unroutable = vec![];
+        for (envelope, call_id) in params {
+            let refusal = match envelope {
+                Err(e) => Refusal {
+                    call_id,
+                    reason: FailureReason::InvalidParams,
+                    detail: format!("failed to decode request envelope, {}", e),
+                },
+                Ok(envelope) if envelope.version != ENVELOPE_VERSION => Refusal {
+                    call_id,
+                    reason: FailureReason::UnsupportedVersion,
+                    detail: format!("request envelope version {}", envelope.version),
+                },
+                Ok(envelope) if !self.handlers.iter().any(|x| x.kind() == envelope.kind) => {
+                    Refusal {
+                        call_id,
+                        reason: FailureReason::UnknownKind,
+                        detail: format!("no handler for request kind {}", envelope.kind),
+                    }
+                }
+                Ok(envelope) => {
+                    by_kind
+                        .entry(envelope.kind)
+                        .or_default()
+                        .push((envelope.payload, call_id));
+                    continue;
+                }
+            };
+            unroutable.push(refusal);
+        }
+
+        let mut handled = Handled::default();
+        for handler in self.handlers.iter_mut() {
+            let requests = by_kind.remove(&handler.kind()).unwrap_or_default();
+            handled.extend(handler.handle(requests, budget));
+        }
+        for refusal in unroutable {
+            match budget.spend(FAIL_ORDER_GAS) {
+                true => handled.refused.push(refusal),
+                false => handled.deferred.push(refusal.call_id),
+            }
+        }
+        handled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::{AbiDecode, AbiEncode};
+    use std::cell::Cell;
+
+    // resolves every request it's given without emitting anything
+    struct Accept(u16);
+
+    impl RequestHandler for Accept {
+        fn kind(&self) -> u16 {
+            self.0
+        }
+
+        fn handle(&mut self, requests: Vec<(Bytes, Address)>, _: &mut GasBudget) -> Handled {
+            Handled {
+                resolved: requests.into_iter().map(|(_, call_id)| call_id).collect(),
+                ..Default::default()
+            }
+        }
+    }
+
+    #[test]
+    fn routes_by_kind_and_refuses_the_rest() {
+        let params = vec![
+            (Ok(RequestEnvelope::new(1, vec![])), Address::repeat_byte(1)),
+            (Ok(RequestEnvelope::new(2, vec![])), Address::repeat_byte(2)),
+            (Ok(RequestEnvelope::new(7, vec![])), Address::repeat_byte(3)),
+            (
+                Ok(RequestEnvelope {
+                    version: ENVELOPE_VERSION + 1,
+                    ..RequestEnvelope::new(1, vec![])
+                }),
+                Address::repeat_byte(4),
+            ),
+            (
+                Err(RunnerError("bad envelope".to_string())),
+                Address::repeat_byte(5),
+            ),
+        ];
+        let mut router = Router::new().register(Accept(1)).register(Accept(2));
+
+        let handled = router.route(params, &mut GasBudget::new(U256::from(1_000_000)));
+        assert_eq!(
+            handled.resolved,
+            vec![Address::repeat_byte(1), Address::repeat_byte(2)]
+        );
+        let refused: Vec<(Address, FailureReason)> = handled
+            .refused
+            .iter()
+            .map(|x| (x.call_id, x.reason))
+            .collect();
+        assert_eq!(
+            refused,
+            vec![
+                (Address::repeat_byte(3), FailureReason::UnknownKind),
+                (Address::repeat_byte(4), FailureReason::UnsupportedVersion),
+                (Address::repeat_byte(5), FailureReason::InvalidParams),
+            ]
+        );
+    }
+
+    // counts the runs it's handed, with or without requests
+    struct Count<'a>(u16, &'a Cell<usize>);
+
+    impl RequestHandler for Count<'_> {
+        fn kind(&self) -> u16 {
+            self.0
+        }
+
+        fn handle(&mut self, _: Vec<(Bytes, Address)>, _: &mut GasBudget) -> Handled {
+            self.1.set(self.1.get() + 1);
+            Handled::default()
+        }
+    }
+
+    #[test]
+    fn calls_handlers_without_requests() {
+        let runs = Cell::new(0);
+        let mut router = Router::new().register(Accept(1)).register(Count(2, &runs));
+        let params = vec![(Ok(RequestEnvelope::new(1, vec![])), Address::random())];
+
+        router.route(params, &mut GasBudget::new(U256::from(1_000_000)));
+        router.route(vec![], &mut GasBudget::new(U256::from(1_000_000)));
+        assert_eq!(runs.get(), 2);
+    }
+
+    #[test]
+    fn decodes_the_receivers_encoding() {
+        let envelope = RequestEnvelope::new(ORDER_REQUEST, vec![1u8, 2, 3]);
+        let decoded = RequestEnvelope::decode(envelope.clone().encode()).unwrap();
+        assert_eq!(decoded, envelope);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::dry_run::*;
+
+use ethers::{
+    abi::{AbiDecode, AbiType, Tokenizable},
+    prelude::{ContractCall, LocalWallet},
+    providers::Http,
+    types::{Address, U256},
+};
+use std::fmt;
+use switchboard_evm::sdk::{EVMFunctionRunner, EVMMiddleware};
+
+// The client every callback is built with
+pub type Client = EVMMiddleware<Http>;
+pub type Callback = ContractCall<Client, ()>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunnerError(pub String);
+
+impl fmt::Display for RunnerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for RunnerError {}
+
+// Everything a function needs from the Switchboard runtime. The SGX runner is the real thing,
+// tests swap in a recorder so the emitted callbacks can be inspected.
+pub trait FunctionRunner {
+    // the enclave generated wallet the callbacks are signed with
+    fn enclave_wallet(&self) -> LocalWallet;
+
+    // params of each user triggered call since the last run, with the call's id
+    fn params<T: AbiDecode + AbiType + Tokenizable>(
+        &self,
+    ) -> Vec<(Result<T, RunnerError>, Address)>;
+
+    // emit the callbacks, resolving every pending call
+    fn emit(
+        &self,
+        to: Address,
+        expiration_time_seconds: U256,
+        gas_limit: U256,
+        calls: Vec<Callback>,
+    ) -> Result<(), RunnerError>;
+
+    // emit the callbacks, only resolving `call_ids`
+    fn emit_resolve(
+        &self,
+        to: Address,
+        expiration_time_seconds: U256,
+        gas_limit: U256,
+        calls: Vec<Callback>,
+        call_ids: Vec<Address>,
+    ) -> Result<(), RunnerError>;
+}
+
+impl FunctionRunner for EVMFunctionRunner {
+    fn enclave_wallet(&self) -> LocalWallet {
+        self.enclave_wallet.clone()
+    }
+
+    fn params<T: AbiDecode + AbiType + Tokenizable>(
+        &self,
+    ) -> Vec<(Result<T, RunnerError>, Address)> {
+        EVMFunctionRunner::params::<T>(self)
+            .into_iter()
+            .map(|(param, call_id)| (param.map_err(|e| RunnerError(format!("{:?}", e))), call_id))
+            .collect()
+    }
+
+    fn emit(
+        &self,
+        to: Address,
+        expiration_time_seconds: U256,
+        gas_limit: U256,
+        calls: Vec<Callback>,
+    ) -> Result<(), RunnerError> {
+        EVMFunctionRunner::emit(self, to, expiration_time_seconds, gas_limit, calls)
+            .map_err(|e| RunnerError(format!("{:?}", e)))
+    }
+
+    fn emit_resolve(
+        &self,
+        to: Address,
+        expiration_time_seconds: U256,
+        gas_limit: U256,
+        calls: Vec<Callback>,
+        call_ids: Vec<Address>,
+    ) -> Result<(), RunnerError> {
+        EVMFunctionRunner::emit_resolve(
+            self,
+            to,
+            expiration_time_seconds,
+            gas_limit,
+            calls,
+            call_ids,
+        )
+        .map_err(|e| RunnerError(format!("{:?}", e)))
+    }
+}
+
+// What main runs with, the SGX runner or on dry runs a stand-in that doesn't need the enclave
+pub enum Runner {
+    Enclave(EVMFunctionRunner),
+    DryRun(DryRunner),
+}
+
+impl Runner {
+    pub fn new() -> Result<Self, RunnerError> {
+        if is_dry_run() {
+            return DryRunner::from_env().map(Runner::DryRun);
+        }
+        EVMFunctionRunner::new()
+            .map(Runner::Enclave)
+            .map_err(|e| RunnerError(format!("{:?}", e)))
+    }
+}
+
+impl FunctionRunner for Runner {
+    fn enclave_wallet(&self) -> LocalWallet {
+        match self {
+            Runner::Enclave(runner) => FunctionRunner::enclave_wallet(runner),
+            Runner::DryRun(runner) => runner.enclave_wallet(),
+        }
+    }
+
+    fn params<T: AbiDecode + AbiType + Tokenizable>(
+        &self,
+    ) -> Vec<(Result<T, RunnerError>, Address)> {
+        match self {
+            Runner::Enclave(runner) => FunctionRunner::params(runner),
+            Runner::DryRun(runner) => runner.params(),
+        }
+    }
+
+    fn emit(
+        &self,
+        to: Address,
+        expiration_time_seconds: U256,
+        gas_limit: U256,
+        calls: Vec<Callback>,
+    ) -> Result<(), RunnerError> {
+        match self {
+            Runner::Enclave(runner) => {
+                FunctionRunner::emit(runner, to, expiration_time_seconds, gas_limit, calls)
+            }
+            Runner::DryRun(runner) => runner.emit(to, expiration_time_seconds, gas_limit, calls),
+        }
+    }
+
+    fn emit_resolve(
+        &self,
+        to: Address,
+        expiration_time_seconds: U256,
+        gas_limit: U256,
+        calls: Vec<Callback>,
+        call_ids: Vec<Address>,
+    ) -> Result<(), RunnerError> {
+        match self {
+            Runner::Enclave(runner) => FunctionRunner::emit_resolve(
+                runner,
+                to,
+                expiration_time_seconds,
+                gas_limit,
+                calls,
+                call_ids,
+            ),
+            Runner::DryRun(runner) => {
+                runner.emit_resolve(to, expiration_time_seconds, gas_limit, calls, call_ids)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub use recorder::*;
+
+#[cfg(test)]
+mod recorder {
+    use super::*;
+
+    use ethers::{prelude::SignerMiddleware, providers::Provider, signers::Signer, types::Bytes};
+    use std::cell::RefCell;
+    use std::sync::Arc;
+
+    // One call to emit / emit_resolve
+    pub struct Emitted {
+        pub to: Address,
+        pub expiration_time_seconds: U256,
+        pub gas_limit: U256,
+        pub calls: Vec<Callback>,
+        // None when every pending call was resolved
+        pub call_ids: Option<Vec<Address>>,
+    }
+
+    // In-memory runner that records what gets emitted instead of sending it anywhere
+    pub struct RecordingRunner {
+        pub wallet: LocalWallet,
+        pub params: Vec<(Bytes, Address)>,
+        pub emitted: RefCell<Vec<Emitted>>,
+    }
+
+    impl Default for RecordingRunner {
+        fn default() -> Self {
+            RecordingRunner {
+                wallet: LocalWallet::new(&mut rand::thread_rng()),
+                params: vec![],
+                emitted: RefCell::new(vec![]),
+            }
+        }
+    }
+
+    impl RecordingRunner {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn with_params(params: Vec<(Bytes, Address)>) -> Self {
+            RecordingRunner {
+                params,
+                ..Self::new()
+            }
+        }
+
+        // A client for building callbacks, encoding calls doesn't touch the provider
+        pub fn client(&self) -> Arc<Client> {
+            let provider = Provider::<Http>::try_from("http://127.0.0.1:8545").unwrap();
+            Arc::new(SignerMiddleware::new(
+                provider,
+                self.wallet.clone().with_chain_id(1u64),
+            ))
+        }
+    }
+
+    impl FunctionRunner for RecordingRunner {
+        fn enclave_wallet(&self) -> LocalWallet {
+            self.wallet.clone()
+        }
+
+        fn params<T: AbiDecode + AbiType + Tokenizable>(
+            &self,
+        ) -> Vec<(Result<T, RunnerError>, Address)> {
+            self.params
+                .iter()
+                .map(|(param, call_id)| {
+                    (
+                        T::decode(param).map_err(|e| RunnerError(e.to_string())),
+                        *call_id,
+                    )
+                })
+                .collect()
+        }
+
+        fn emit(
+            &self,
+            to: Address,
+            expiration_time_seconds: U256,
+            gas_limit: U256,
+            calls: Vec<Callback>,
+        ) -> Result<(), RunnerError> {
+            self.emitted.borrow_mut().push(Emitted {
+                to,
+                expiration_time_seconds,
+                gas_limit,
+                calls,
+                call_ids: None,
+            });
+            Ok(())
+        }
+
+        fn emit_resolve(
+            &self,
+            to: Address,
+            expiration_time_seconds: U256,
+            gas_limit: U256,
+            calls: Vec<Callback>,
+            call_ids: Vec<Address>,
+        ) -> Result<(), RunnerError> {
+            self.emitted.borrow_mut().push(Emitted {
+                to,
+                expiration_time_seconds,
+                gas_limit,
+                calls,
+                call_ids: Some(call_ids),
+            });
+            Ok(())
+        }
+    }
+}
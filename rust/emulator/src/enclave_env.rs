@@ -0,0 +1,42 @@
+use ethers::types::{Address, Bytes};
+
+// The inputs the Switchboard runtime normally hands a function through its environment
+// (see config/app.manifest.template in each function)
+#[derive(Debug, Clone)]
+pub struct EnclaveEnv {
+    pub rpc_url: String,
+    pub chain_id: u64,
+    pub function_id: Address,
+    pub verifying_contract: Address,
+    pub verifier: Address,
+    pub payer: Address,
+    pub reward_receiver: Address,
+    // user triggered calls: ABI encoded params and the call id each was created with
+    pub params: Vec<Bytes>,
+    pub call_ids: Vec<Address>,
+}
+
+impl EnclaveEnv {
+    pub fn vars(&self) -> Vec<(&'static str, String)> {
+        let mut vars = vec![
+            ("RPC_URL", self.rpc_url.clone()),
+            ("CHAIN_ID", self.chain_id.to_string()),
+            ("FUNCTION_KEY", format!("{:?}", self.function_id)),
+            ("VERIFYING_CONTRACT", format!("{:?}", self.verifying_contract)),
+            ("VERIFIER", format!("{:?}", self.verifier)),
+            ("PAYER", format!("{:?}", self.payer)),
+            ("REWARD_RECEIVER", format!("{:?}", self.reward_receiver)),
+        ];
+        if !self.params.is_empty() {
+            vars.push((
+                "FUNCTION_PARAMS",
+                serde_json::to_string(&self.params).unwrap(),
+            ));
+            vars.push((
+                "FUNCTION_CALL_IDS",
+                serde_json::to_string(&self.call_ids).unwrap(),
+            ));
+        }
+        vars
+    }
+}
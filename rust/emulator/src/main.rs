@@ -0,0 +1,171 @@
+pub mod result;
+pub use result::*;
+pub mod enclave_env;
+pub use enclave_env::*;
+pub mod replay;
+pub use replay::*;
+
+use ethers::{
+    providers::{Http, Middleware, Provider},
+    types::{Address, Bytes},
+    utils::hex,
+};
+use std::io::{BufRead, BufReader};
+use std::process::{exit, Command, Stdio};
+
+const USAGE: &str = "usage: switchboard-emulator <function binary> [args...]
+
+Runs a Switchboard function outside of the SGX runtime, then replays the transactions it emits
+against a local dev chain (anvil or hardhat node) as if the Switchboard contract forwarded them.
+
+environment:
+  RPC_URL              local dev chain (default http://127.0.0.1:8545)
+  SWITCHBOARD_ADDRESS  the switchboard address the receiver trusts, impersonated for the replay
+  FUNCTION_ID          function id appended to each callback (default: random)
+  EMULATOR_PARAMS      JSON array of hex ABI encoded params, one per user triggered call
+  EMULATOR_CALL_IDS    JSON array of the call id of each call (default: random)
+  EMULATOR_NO_REPLAY   set to skip replaying the emitted transactions";
+
+#[tokio::main(worker_threads = 4)]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() || args[0] == "--help" {
+        println!("{}", USAGE);
+        exit(1);
+    }
+
+    let rpc_url = std::env::var("RPC_URL").unwrap_or("http://127.0.0.1:8545".to_string());
+    let provider = Provider::<Http>::try_from(rpc_url.as_str()).unwrap();
+    let chain_id = provider.get_chainid().await.unwrap().as_u64();
+
+    let switchboard = std::env::var("SWITCHBOARD_ADDRESS")
+        .expect("SWITCHBOARD_ADDRESS must be set")
+        .parse::<Address>()
+        .unwrap();
+    let function_id = std::env::var("FUNCTION_ID")
+        .map(|x| x.parse::<Address>().unwrap())
+        .unwrap_or(Address::random());
+
+    // every user triggered call has the call id Switchboard.callFunction handed out for it
+    // -- receivers that check call ids need the real ones in EMULATOR_CALL_IDS, e.g. from their
+    //    OrderCreated events, otherwise each call gets a random one
+    let params: Vec<Bytes> = std::env::var("EMULATOR_PARAMS")
+        .map(|x| serde_json::from_str(&x).expect("EMULATOR_PARAMS must be a JSON array of hex"))
+        .unwrap_or_default();
+    let call_ids: Vec<Address> = match std::env::var("EMULATOR_CALL_IDS") {
+        Ok(x) => {
+            serde_json::from_str(&x).expect("EMULATOR_CALL_IDS must be a JSON array of addresses")
+        }
+        Err(_) => params.iter().map(|_| Address::random()).collect(),
+    };
+    if call_ids.len() != params.len() {
+        println!(
+            "[emulator] {} EMULATOR_PARAMS for {} EMULATOR_CALL_IDS",
+            params.len(),
+            call_ids.len()
+        );
+        exit(1);
+    }
+
+    let enclave_env = EnclaveEnv {
+        rpc_url,
+        chain_id,
+        function_id,
+        verifying_contract: switchboard,
+        verifier: Address::random(),
+        payer: Address::random(),
+        reward_receiver: Address::random(),
+        params,
+        call_ids: call_ids.clone(),
+    };
+    println!(
+        "[emulator] running {} as function {:?} on chain {} with {} call(s)",
+        args[0],
+        function_id,
+        chain_id,
+        call_ids.len()
+    );
+
+    // run the function, forwarding its output and picking out the emitted result
+    let mut child = Command::new(&args[0])
+        .args(&args[1..])
+        .envs(enclave_env.vars())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut function_result = None;
+    for line in BufReader::new(child.stdout.take().unwrap()).lines() {
+        let line = line.unwrap();
+        match FunctionResult::from_output_line(&line) {
+            Some(result) => function_result = Some(result),
+            None => println!("{}", line),
+        }
+    }
+    let status = child.wait().unwrap();
+    if !status.success() {
+        println!("[emulator] function exited with {}", status);
+    }
+    let function_result = match function_result {
+        Some(Ok(result)) => result,
+        Some(Err(e)) => {
+            println!("[emulator] {}", e);
+            exit(1);
+        }
+        None => {
+            println!("[emulator] the function didn't emit a result");
+            exit(1);
+        }
+    };
+
+    // decode what was emitted
+    let evm = function_result.evm();
+    println!(
+        "[emulator] result v{} with {} tx(s), error code {}",
+        function_result.version,
+        evm.txs.len(),
+        function_result.error_code
+    );
+    for (i, tx) in evm.txs.iter().enumerate() {
+        println!(
+            "  [{}] to {:?}, gas limit {}, selector 0x{}, {} bytes",
+            i,
+            tx.to().unwrap(),
+            tx.gas_limit,
+            hex::encode(tx.data.get(..4).unwrap_or_default()),
+            tx.data.len()
+        );
+    }
+    let resolved = evm.resolved_call_ids();
+    let pending: Vec<_> = call_ids.iter().filter(|x| !resolved.contains(x)).collect();
+    if !call_ids.is_empty() {
+        println!(
+            "[emulator] {} call(s) resolved, {} left pending",
+            call_ids.len() - pending.len(),
+            pending.len()
+        );
+    }
+
+    if std::env::var("EMULATOR_NO_REPLAY").is_ok() {
+        return;
+    }
+
+    // replay against the local chain
+    let function_id = function_result.function_id().unwrap();
+    match replay(&provider, switchboard, function_id, &evm.txs).await {
+        Ok(receipts) => {
+            for (i, receipt) in receipts.iter().enumerate() {
+                println!(
+                    "  [{}] {:?} status {} gas used {}",
+                    i,
+                    receipt.transaction_hash,
+                    receipt.status.unwrap_or_default(),
+                    receipt.gas_used.unwrap_or_default()
+                );
+            }
+        }
+        Err(e) => {
+            println!("[emulator] replay failed: {}", e);
+            exit(1);
+        }
+    }
+}
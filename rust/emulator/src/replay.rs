@@ -0,0 +1,70 @@
+use crate::*;
+
+use ethers::{
+    providers::{Http, Middleware, Provider},
+    types::{Address, TransactionReceipt, TransactionRequest, U256},
+};
+use std::time::{Duration, SystemTime};
+
+// Send each emitted transaction to a local dev chain as if the Switchboard contract forwarded it.
+// The switchboard address is impersonated (anvil or hardhat node), so receivers that check
+// `msg.sender` and read the encoded function id work unmodified.
+pub async fn replay(
+    provider: &Provider<Http>,
+    switchboard: Address,
+    function_id: Address,
+    txs: &[EvmTransaction],
+) -> Result<Vec<TransactionReceipt>, String> {
+    impersonate(provider, switchboard).await?;
+
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+
+    let mut receipts = vec![];
+    for (i, tx) in txs.iter().enumerate() {
+        if tx.expiration_time_seconds < now {
+            println!(
+                "[emulator] tx {} expired at {} (now {}), the verifier would reject it",
+                i, tx.expiration_time_seconds, now
+            );
+        }
+        let request = TransactionRequest::new()
+            .from(switchboard)
+            .to(tx.to()?)
+            .data(tx.forwarded_calldata(function_id))
+            .gas(tx.gas_limit()?)
+            .value(tx.value()?);
+        let receipt = provider
+            .send_transaction(request, None)
+            .await
+            .map_err(|e| format!("tx {} failed to send: {}", i, e))?
+            .await
+            .map_err(|e| format!("tx {} failed to confirm: {}", i, e))?
+            .ok_or(format!("tx {} was dropped", i))?;
+        receipts.push(receipt);
+    }
+    Ok(receipts)
+}
+
+async fn impersonate(provider: &Provider<Http>, account: Address) -> Result<(), String> {
+    // 1000 ETH to cover gas
+    let balance = U256::exp10(21);
+    for node in ["anvil", "hardhat"] {
+        let impersonated: Result<(), _> = provider
+            .request(&format!("{}_impersonateAccount", node), [account])
+            .await;
+        if impersonated.is_ok() {
+            provider
+                .request::<_, ()>(&format!("{}_setBalance", node), (account, balance))
+                .await
+                .map_err(|e| format!("failed to fund {:?}: {}", account, e))?;
+            return Ok(());
+        }
+    }
+    Err(format!(
+        "failed to impersonate {:?}, replay needs an anvil or hardhat node",
+        account
+    ))
+}
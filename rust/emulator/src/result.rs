@@ -0,0 +1,151 @@
+use ethers::{
+    types::{Address, Bytes, U256},
+    utils::hex,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+// The prefix the function runner prints its hex encoded result behind
+pub const FN_OUT_PREFIX: &str = "FN_OUT: ";
+
+// The parts of the function result the emulator needs. The runner serializes byte arrays as
+// lists of numbers, e.g. "to": [83, 107, ...].
+#[derive(Deserialize, Debug, Clone)]
+pub struct FunctionResult {
+    pub version: u32,
+    pub fn_key: Vec<u8>,
+    pub signer: Vec<u8>,
+    pub chain_result_info: ChainResultInfo,
+    #[serde(default)]
+    pub error_code: u8,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub enum ChainResultInfo {
+    Evm(EvmFunctionResult),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct EvmFunctionResult {
+    pub txs: Vec<EvmTransaction>,
+    #[serde(default)]
+    pub call_ids: Vec<Value>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct EvmTransaction {
+    pub expiration_time_seconds: u64,
+    pub gas_limit: String,
+    pub value: String,
+    pub to: Vec<u8>,
+    pub from: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+impl FunctionResult {
+    // Parse a line of function output, returning None if it isn't the FN_OUT line
+    pub fn from_output_line(line: &str) -> Option<Result<FunctionResult, String>> {
+        let encoded = line.trim().strip_prefix(FN_OUT_PREFIX)?;
+        Some(
+            hex::decode(encoded.trim())
+                .map_err(|e| format!("FN_OUT is not valid hex: {}", e))
+                .and_then(|json| {
+                    serde_json::from_slice(&json)
+                        .map_err(|e| format!("FN_OUT is not a function result: {}", e))
+                }),
+        )
+    }
+
+    pub fn function_id(&self) -> Result<Address, String> {
+        to_address(&self.fn_key).ok_or(format!("invalid fn_key {:?}", self.fn_key))
+    }
+
+    pub fn evm(&self) -> &EvmFunctionResult {
+        match &self.chain_result_info {
+            ChainResultInfo::Evm(result) => result,
+        }
+    }
+}
+
+impl EvmFunctionResult {
+    // call ids the run marked as resolved, in whichever form the runner serialized them
+    pub fn resolved_call_ids(&self) -> Vec<Address> {
+        self.call_ids
+            .iter()
+            .filter_map(|x| match x {
+                Value::String(s) => s.parse().ok(),
+                Value::Array(_) => serde_json::from_value::<Vec<u8>>(x.clone())
+                    .ok()
+                    .and_then(|bytes| to_address(&bytes)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl EvmTransaction {
+    pub fn to(&self) -> Result<Address, String> {
+        to_address(&self.to).ok_or(format!("invalid tx.to {:?}", self.to))
+    }
+
+    pub fn gas_limit(&self) -> Result<U256, String> {
+        U256::from_dec_str(&self.gas_limit).map_err(|e| format!("invalid gas limit: {}", e))
+    }
+
+    pub fn value(&self) -> Result<U256, String> {
+        U256::from_dec_str(&self.value).map_err(|e| format!("invalid value: {}", e))
+    }
+
+    // The calldata as the receiver sees it when the Switchboard contract forwards the call: the
+    // function id is appended so `Switchboard.getEncodedFunctionId()` can read it from the
+    // last 20 bytes (EIP-2771 style).
+    pub fn forwarded_calldata(&self, function_id: Address) -> Bytes {
+        let mut data = self.data.clone();
+        data.extend_from_slice(function_id.as_bytes());
+        data.into()
+    }
+}
+
+fn to_address(bytes: &[u8]) -> Option<Address> {
+    if bytes.len() != 20 {
+        return None;
+    }
+    Some(Address::from_slice(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fn_out(json: &str) -> String {
+        format!("{}{}", FN_OUT_PREFIX, hex::encode(json))
+    }
+
+    #[test]
+    fn parses_fn_out_and_appends_function_id() {
+        let json = r#"{"version":1,"quote":[],"fn_key":[1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1],"signer":[2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2],"fn_request_key":[],"fn_request_hash":[],"chain_result_info":{"Evm":{"txs":[{"expiration_time_seconds":1691638863,"gas_limit":"5500000","value":"0","to":[3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3,3],"from":[2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2],"data":[160,223,119,106]}],"signatures":[],"call_ids":[[4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4,4]],"checksums":[]}},"error_code":0}"#;
+        let result = FunctionResult::from_output_line(&fn_out(json))
+            .unwrap()
+            .unwrap();
+
+        let function_id = result.function_id().unwrap();
+        assert_eq!(function_id, Address::repeat_byte(1));
+
+        let evm = result.evm();
+        assert_eq!(evm.resolved_call_ids(), vec![Address::repeat_byte(4)]);
+
+        let tx = &evm.txs[0];
+        assert_eq!(tx.to().unwrap(), Address::repeat_byte(3));
+        assert_eq!(tx.gas_limit().unwrap(), U256::from(5_500_000));
+
+        let calldata = tx.forwarded_calldata(function_id);
+        assert_eq!(&calldata[..4], &[160, 223, 119, 106]);
+        assert_eq!(&calldata[4..], function_id.as_bytes());
+    }
+
+    #[test]
+    fn ignores_other_output() {
+        assert!(FunctionResult::from_output_line("BTC/USD -> 1").is_none());
+        assert!(FunctionResult::from_output_line("FN_OUT: zz").unwrap().is_err());
+    }
+}
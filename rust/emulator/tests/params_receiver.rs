@@ -0,0 +1,92 @@
+use ethers::{
+    abi::{encode, Token},
+    contract::{abigen, parse_log},
+    providers::{Http, Middleware, Provider},
+    types::{Address, Bytes, U256},
+};
+use std::process::Command;
+use std::sync::Arc;
+
+abigen!(
+    ParamsReceiver,
+    r#"[
+        event OrderCreated(uint256 orderId, address callId, address sender)
+        function createOrder() payable
+        function orders(uint256) view returns (address,address,uint256,bool,uint256,uint8,uint256)
+    ]"#,
+);
+
+// The params createOrder passes to callFunction, a RequestEnvelope wrapping OrderParams
+fn order_envelope(order_id: U256, sender: Address) -> Bytes {
+    let payload = encode(&[Token::Uint(order_id), Token::Address(sender)]);
+    encode(&[Token::Tuple(vec![
+        Token::Uint(1.into()),
+        Token::Uint(1.into()),
+        Token::Bytes(payload),
+    ])])
+    .into()
+}
+
+fn env_address(key: &str) -> Address {
+    std::env::var(key)
+        .unwrap_or_else(|_| panic!("{} must be set", key))
+        .parse()
+        .unwrap()
+}
+
+// Creates an order on a 03 receiver deployed to a local dev chain, then runs the order filling
+// function through the emulator with the order's call id and checks the order got filled. Needs
+// an anvil or hardhat node with the receiver deployed against
+// contracts/test/fixtures/MockSwitchboard.sol, see the README, and the function built:
+//   SWITCHBOARD_ADDRESS=<mock switchboard> SWITCHBOARD_RECEIVER_ADDRESS=<receiver> \
+//   PARAMS_FUNCTION_BIN=<path to the built function> cargo test -- --ignored
+#[tokio::test]
+#[ignore]
+async fn fills_an_order_through_the_emulator() {
+    let rpc_url = std::env::var("RPC_URL").unwrap_or("http://127.0.0.1:8545".to_string());
+    let provider = Provider::<Http>::try_from(rpc_url.as_str()).unwrap();
+    let sender = provider.get_accounts().await.unwrap()[0];
+    let receiver = ParamsReceiver::new(
+        env_address("SWITCHBOARD_RECEIVER_ADDRESS"),
+        Arc::new(provider.clone()),
+    );
+
+    let create = receiver.create_order().from(sender).value(U256::exp10(17));
+    let receipt = create.send().await.unwrap().await.unwrap().unwrap();
+    let created = receipt
+        .logs
+        .into_iter()
+        .find_map(|log| parse_log::<OrderCreatedFilter>(log).ok())
+        .unwrap();
+    assert_eq!(created.sender, sender);
+
+    let params = vec![order_envelope(created.order_id, created.sender)];
+    let status = Command::new(env!("CARGO_BIN_EXE_switchboard-emulator"))
+        .arg(std::env::var("PARAMS_FUNCTION_BIN").expect("PARAMS_FUNCTION_BIN must be set"))
+        .env("RPC_URL", &rpc_url)
+        .env(
+            "SWITCHBOARD_ADDRESS",
+            format!("{:?}", env_address("SWITCHBOARD_ADDRESS")),
+        )
+        .env("EMULATOR_PARAMS", serde_json::to_string(&params).unwrap())
+        .env(
+            "EMULATOR_CALL_IDS",
+            serde_json::to_string(&[created.call_id]).unwrap(),
+        )
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let (call_id, _, _, filled, _, failure, _) =
+        receiver.orders(created.order_id).call().await.unwrap();
+    assert_eq!(call_id, created.call_id);
+    assert_eq!(failure, 0);
+    assert!(filled);
+}
+
+#[test]
+fn encodes_the_readme_envelope() {
+    let envelope = order_envelope(U256::one(), Address::from_low_u64_be(1));
+    let readme = "0x0000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000060000000000000000000000000000000000000000000000000000000000000004000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000001";
+    assert_eq!(envelope, readme.parse::<Bytes>().unwrap());
+}